@@ -0,0 +1,145 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::csg::{Solid, Span};
+use super::math::{inverse_direction, ray_slabs};
+
+/// Anything with finite extent. Acceleration structures and culling only
+/// ever look at these bounds, never at the shape inside.
+pub trait Bounded {
+    fn aabb(&self) -> Aabb;
+}
+
+/// Axis-aligned bounds, shared by the `Cuboid` primitive and anything that
+/// needs a cheap bounding volume.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+impl Aabb {
+    pub fn new(a: Point3<f32>, b: Point3<f32>) -> Aabb {
+        Aabb {
+            min: Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+
+    /// Slab test: returns the ray parameters where the ray enters and
+    /// leaves the box, if it overlaps it in front of the origin.
+    pub fn slabs(&self, ray: &Ray) -> Option<(f32, f32)> {
+        ray_slabs(ray.point, inverse_direction(ray.direction), self.min, self.max)
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.max - self.min;
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Option<Aabb> {
+        points.into_iter().fold(None, |b: Option<Aabb>, p| {
+            let point = Aabb::new(p, p);
+            Some(b.map_or(point, |b| b.union(&point)))
+        })
+    }
+
+    pub fn around_sphere(center: Point3<f32>, radius: f32) -> Aabb {
+        let r = vec3(radius, radius, radius);
+        Aabb::new(center + -r, center + r)
+    }
+
+    /// Tight bounds of a disc with unit `normal`.
+    pub fn around_disc(center: Point3<f32>, normal: Vector3<f32>, radius: f32) -> Aabb {
+        let e = |n: f32| radius * (1.0 - n * n).max(0.0).sqrt();
+        let extent = vec3(e(normal.x), e(normal.y), e(normal.z));
+        Aabb::new(center + -extent, center + extent)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [Point3::new(a.x, a.y, a.z), Point3::new(b.x, a.y, a.z),
+         Point3::new(a.x, b.y, a.z), Point3::new(b.x, b.y, a.z),
+         Point3::new(a.x, a.y, b.z), Point3::new(b.x, a.y, b.z),
+         Point3::new(a.x, b.y, b.z), Point3::new(b.x, b.y, b.z)]
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    /// Outward normal of the face nearest to `point`.
+    pub fn normal_at(&self, point: Point3<f32>) -> Vector3<f32> {
+        let half = (self.max - self.min) * 0.5;
+        let local = point - self.center();
+        let mut axis = 0;
+        let mut best = 0.0;
+        for i in 0..3 {
+            let d = (local[i] / half[i]).abs();
+            if d > best {
+                best = d;
+                axis = i;
+            }
+        }
+        let mut normal = Vector3::zero();
+        normal[axis] = local[axis].signum();
+        normal
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cuboid {
+    bounds: Aabb,
+    color: Vector3<f32>,
+}
+impl Cuboid {
+    pub fn new(min: Point3<f32>, max: Point3<f32>, color: Vector3<f32>) -> Cuboid {
+        Cuboid {
+            bounds: Aabb::new(min, max),
+            color: color,
+        }
+    }
+}
+
+impl Intersect for Cuboid {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let (t_enter, t_exit) = self.bounds.slabs(ray)?;
+        // from inside the box the visible surface is where the ray leaves
+        let t = if t_enter >= 0.0 { t_enter } else { t_exit };
+        Some(Hit { t: t, normal: self.bounds.normal_at(ray.point_at(t)), color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+}
+
+impl Bounded for Cuboid {
+    fn aabb(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Solid for Cuboid {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        match self.bounds.slabs(ray) {
+            Some((t_enter, t_exit)) => {
+                let hit = |t: f32| Hit {
+                    t: t,
+                    normal: self.bounds.normal_at(ray.point_at(t)),
+                    color: self.color,
+                    material: None,
+                    front: true,
+                };
+                vec![Span { enter: hit(t_enter), exit: hit(t_exit) }]
+            }
+            None => vec![],
+        }
+    }
+}
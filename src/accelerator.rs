@@ -0,0 +1,104 @@
+use super::Ray;
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::grid::Grid;
+use super::kdtree::KdTree;
+
+/// A spatial index over primitive bounds that finds which primitives a ray
+/// may hit. Primitives are identified by their position in the bounds list
+/// the index was built from; the owner does the actual intersection tests.
+pub trait Accelerator: Send + Sync {
+    /// Calls `visit` on candidate primitives, nearest first where possible.
+    /// `visit` returns the distance of a hit closer than any it has seen,
+    /// which lets the search skip primitives that can only be further away.
+    fn closest(&self, ray: &Ray, t_max: f32, visit: &mut dyn FnMut(usize) -> Option<f32>);
+
+    /// Whether `test` accepts any candidate primitive before `t_max`.
+    fn any(&self, ray: &Ray, t_max: f32, test: &mut dyn FnMut(usize) -> bool) -> bool;
+
+    /// Updates the index in place for primitives that moved, given their
+    /// new bounds in the original order. Returns false if the structure
+    /// can't be refit and has to be rebuilt instead.
+    fn refit(&mut self, _bounds: &[Aabb]) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcceleratorKind {
+    Bvh,
+    LinearBvh,
+    KdTree,
+    Grid,
+}
+impl AcceleratorKind {
+    pub fn build(&self, bounds: &[Aabb]) -> Box<dyn Accelerator> {
+        match *self {
+            AcceleratorKind::Bvh => Box::new(Bvh::new(bounds)),
+            AcceleratorKind::LinearBvh => Box::new(Bvh::linear(bounds)),
+            AcceleratorKind::KdTree => Box::new(KdTree::new(bounds)),
+            AcceleratorKind::Grid => Box::new(Grid::new(bounds)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::*;
+    use super::super::{Intersect, Sphere};
+    use super::super::math;
+    use super::super::rng::Rng;
+
+    // overlapping spheres of mixed sizes, and rays from inside and outside
+    // the crowd every which way
+    fn scene(rng: &mut Rng) -> (Vec<Sphere>, Vec<Ray>) {
+        let mut point = || Point3::new(rng.next_f32() * 20.0 - 10.0, rng.next_f32() * 20.0 - 10.0, rng.next_f32() * 20.0 - 10.0);
+        let centers: Vec<Point3<f32>> = (0..300).map(|_| point()).collect();
+        let origins: Vec<Point3<f32>> = (0..500).map(|_| point() * 1.5).collect();
+        let spheres = centers.into_iter().enumerate()
+            .map(|(i, center)| Sphere::new(center, 0.2 + (i % 7) as f32 * 0.3, vec3(1.0, 1.0, 1.0)))
+            .collect();
+        let rays = origins.into_iter()
+            .map(|origin| Ray::new(origin, math::uniform_sphere(rng.next_f32(), rng.next_f32())))
+            .collect();
+        (spheres, rays)
+    }
+
+    fn brute_force(spheres: &[Sphere], ray: &Ray, t_max: f32) -> Option<(usize, f32)> {
+        spheres.iter().enumerate()
+            .filter_map(|(i, sphere)| sphere.intersect(ray).map(|hit| (i, hit.t)))
+            .filter(|&(_, t)| t < t_max)
+            .fold(None, |best: Option<(usize, f32)>, (i, t)| if best.is_none_or(|b| t < b.1) { Some((i, t)) } else { best })
+    }
+
+    fn accelerated(accelerator: &dyn Accelerator, spheres: &[Sphere], ray: &Ray, t_max: f32) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        accelerator.closest(ray, t_max, &mut |i| match spheres[i].intersect(ray) {
+            Some(hit) if hit.t < t_max && best.is_none_or(|b| hit.t < b.1) => {
+                best = Some((i, hit.t));
+                Some(hit.t)
+            }
+            _ => None,
+        });
+        best
+    }
+
+    #[test]
+    fn accelerators_find_the_closest_hit() {
+        let (spheres, rays) = scene(&mut Rng::new(7));
+        let bounds: Vec<Aabb> = spheres.iter().map(|sphere| sphere.bounds().unwrap()).collect();
+        for &kind in &[AcceleratorKind::Bvh, AcceleratorKind::LinearBvh, AcceleratorKind::KdTree, AcceleratorKind::Grid] {
+            let accelerator = kind.build(&bounds);
+            for ray in &rays {
+                for &t_max in &[f32::INFINITY, 5.0] {
+                    let expected = brute_force(&spheres, ray, t_max);
+                    assert_eq!(accelerated(&*accelerator, &spheres, ray, t_max), expected, "{:?} along {:?}", kind, ray);
+                    let blocked = accelerator.any(ray, t_max, &mut |i| spheres[i].intersect(ray).is_some_and(|hit| hit.t < t_max));
+                    assert_eq!(blocked, expected.is_some(), "{:?} along {:?}", kind, ray);
+                }
+            }
+        }
+    }
+}
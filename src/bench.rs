@@ -0,0 +1,55 @@
+use cgmath::*;
+
+use std::time::Instant;
+
+use super::{Intersect, Ray, Sphere};
+use super::bvh::Bvh;
+use super::rng::Rng;
+use super::sphere_set::{SphereSet, Spheres};
+
+const SPHERES: usize = 4096;
+const RAYS: usize = 200000;
+
+/// Times closest-hit queries against a cloud of random spheres, one sphere
+/// at a time versus four at a time with SIMD. Both go through a BVH, the
+/// way the scene would use them.
+pub fn spheres() {
+    let mut rng = Rng::new(1);
+    let mut random = |scale: f32| (rng.next_f32() - 0.5) * scale;
+    let mut spheres = Spheres::default();
+    for _ in 0..SPHERES {
+        spheres.push(Point3::new(random(20.0), random(20.0), random(20.0)), 0.1 + random(0.1).abs(), vec3(1.0, 1.0, 1.0));
+    }
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| Ray::new(Point3::new(random(4.0), random(4.0), -15.0), vec3(random(1.0), random(1.0), 1.0)))
+        .collect();
+
+    let scalar: Vec<Sphere> = (0..SPHERES)
+        .map(|i| Sphere::new(spheres.centers[i], spheres.radii[i], spheres.colors[i]))
+        .collect();
+    let bounds: Vec<_> = scalar.iter().map(|s| s.bounds().unwrap()).collect();
+    let bvh = Bvh::new(&bounds);
+    let start = Instant::now();
+    let mut scalar_hits = 0;
+    for ray in &rays {
+        let mut closest = f32::INFINITY;
+        bvh.closest(ray, closest, |i| match scalar[i].intersect(ray) {
+            Some(hit) if hit.t < closest => {
+                closest = hit.t;
+                Some(hit.t)
+            }
+            _ => None,
+        });
+        if closest < f32::INFINITY { scalar_hits += 1; }
+    }
+    let scalar_time = start.elapsed();
+
+    let set = SphereSet::new(&spheres);
+    let start = Instant::now();
+    let packet_hits = rays.iter().filter(|ray| set.intersect(ray).is_some()).count();
+    let packet_time = start.elapsed();
+
+    println!("{} rays against {} spheres", RAYS, SPHERES);
+    println!("  one at a time:  {:?} ({} hits)", scalar_time, scalar_hits);
+    println!("  four at a time: {:?} ({} hits)", packet_time, packet_hits);
+}
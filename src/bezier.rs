@@ -0,0 +1,119 @@
+use cgmath::*;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use super::mesh::Mesh;
+
+/// A bicubic Bézier patch. Control points are stored row by row, with rows
+/// running along `v` and columns along `u`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BezierPatch {
+    pub control: [Point3<f32>; 16],
+}
+impl BezierPatch {
+    pub fn new(control: [Point3<f32>; 16]) -> BezierPatch {
+        BezierPatch { control: control }
+    }
+
+    fn weighted(&self, bu: [f32; 4], bv: [f32; 4]) -> Vector3<f32> {
+        let mut sum = Vector3::zero();
+        for (row, &v) in bv.iter().enumerate() {
+            for (col, &u) in bu.iter().enumerate() {
+                sum = sum + self.control[row * 4 + col].to_vec() * (u * v);
+            }
+        }
+        sum
+    }
+
+    pub fn point(&self, u: f32, v: f32) -> Point3<f32> {
+        Point3::from_vec(self.weighted(bernstein(u), bernstein(v)))
+    }
+
+    pub fn normal(&self, u: f32, v: f32) -> Vector3<f32> {
+        let du = self.weighted(bernstein_derivative(u), bernstein(v));
+        let dv = self.weighted(bernstein(u), bernstein_derivative(v));
+        let n = du.cross(dv);
+        if n.length2() > 1e-12 { return n.normalize(); }
+
+        // a collapsed edge (like the teapot's lid and bottom) has a vanishing
+        // derivative, so look just inside the patch instead
+        let nudge = |x: f32| if x < 0.5 { x + 1e-3 } else { x - 1e-3 };
+        let du = self.weighted(bernstein_derivative(nudge(u)), bernstein(nudge(v)));
+        let dv = self.weighted(bernstein(nudge(u)), bernstein_derivative(nudge(v)));
+        du.cross(dv).normalize()
+    }
+}
+
+fn bernstein(t: f32) -> [f32; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+fn bernstein_derivative(t: f32) -> [f32; 4] {
+    let s = 1.0 - t;
+    [-3.0 * s * s, 3.0 * s * s - 6.0 * t * s, 6.0 * t * s - 3.0 * t * t, 3.0 * t * t]
+}
+
+/// Subdivides each patch into a `resolution` by `resolution` grid of quads
+/// with exact surface normals at the vertices.
+pub fn tessellate(patches: &[BezierPatch], resolution: usize, color: Vector3<f32>) -> Mesh {
+    let n = resolution.max(1);
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut faces = vec![];
+    for patch in patches {
+        let base = positions.len();
+        for j in 0..n + 1 {
+            for i in 0..n + 1 {
+                let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+                positions.push(patch.point(u, v));
+                normals.push(patch.normal(u, v));
+            }
+        }
+        let index = |i: usize, j: usize| base + j * (n + 1) + i;
+        for j in 0..n {
+            for i in 0..n {
+                faces.push([index(i, j), index(i + 1, j), index(i + 1, j + 1)]);
+                faces.push([index(i, j), index(i + 1, j + 1), index(i, j + 1)]);
+            }
+        }
+    }
+    Mesh::new(positions, normals, faces, color)
+}
+
+/// Loads patches in the `.bpt` format used to distribute the Utah teapot:
+/// a patch count, then per patch a `3 3` degree line and sixteen control
+/// points.
+pub fn load_bpt(path: &Path) -> io::Result<Vec<BezierPatch>> {
+    let file = File::open(path)?;
+    let mut numbers = vec![];
+    for line in BufReader::new(file).lines() {
+        for token in line?.split_whitespace() {
+            let x = token.parse::<f32>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bpt: malformed number {:?}", token)))?;
+            numbers.push(x);
+        }
+    }
+
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bpt: {}", message));
+    let mut numbers = numbers.into_iter();
+    let count = numbers.next().ok_or_else(|| invalid("missing patch count"))? as usize;
+    let mut patches = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (du, dv) = (numbers.next(), numbers.next());
+        if du != Some(3.0) || dv != Some(3.0) {
+            return Err(invalid("only bicubic patches are supported"));
+        }
+        let mut control = [Point3::new(0.0, 0.0, 0.0); 16];
+        for p in control.iter_mut() {
+            match (numbers.next(), numbers.next(), numbers.next()) {
+                (Some(x), Some(y), Some(z)) => *p = Point3::new(x, y, z),
+                _ => return Err(invalid("truncated control points")),
+            }
+        }
+        patches.push(BezierPatch::new(control));
+    }
+    Ok(patches)
+}
@@ -0,0 +1,255 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+
+use super::{Hit, Ray, Scene, EPSILON};
+use super::integrator;
+use super::math;
+use super::rng::Rng;
+
+// A point where a subpath met a surface.
+#[derive(Debug, Clone)]
+struct Vertex {
+    // the ray that got there, and what it hit
+    ray: Ray,
+    hit: Hit,
+    point: Point3<f32>,
+    // what the subpath carries up to the point, over the odds of getting
+    // there
+    beta: Vector3<f32>,
+    // whether the subpath left the point by a mirror-like bounce no other
+    // path could be joined through
+    delta: bool,
+    // the densities, over area, of reaching the point along the subpath and
+    // from the other end of it
+    pdf_forward: f32,
+    pdf_reverse: f32,
+}
+
+/// The light seen along a camera ray with bidirectional path tracing: a
+/// path of up to `depth` bounces from the camera and one from a random
+/// emitter are joined at every pair of their vertices, each join weighed by
+/// the power heuristic against every other way of making the same path.
+pub fn trace(scene: &Scene, ray: &Ray, depth: usize) -> Vector3<f32> {
+    let mut rng = ray.rng();
+    let count = depth + 1;
+    let mut camera = vec![];
+    let mut color = walk(scene, ray.clone(), vec3(1.0, 1.0, 1.0), 1.0, count, &mut camera, &mut rng) * scene.ambient;
+    let light = light_path(scene, count, ray.time, &mut rng);
+
+    for t in 1..camera.len() + 1 {
+        let z = &camera[t - 1];
+        // the little lights, which no path can hit
+        let lights = scene.unshadowed(&z.ray, &z.hit);
+        if !lights.is_empty() {
+            color = color + z.beta * scene.shade(&z.ray, &z.hit, &lights);
+        }
+        let emitted = scene.material_at(&z.hit).emitted(&z.ray, &z.hit);
+        if emitted != Vector3::zero() {
+            let weight = if integrator::emitter_at(scene, &z.ray, &z.hit).is_some() {
+                weight(scene, &light, &camera, 0, t, count)
+            } else {
+                1.0
+            };
+            color = color + z.beta * emitted * weight;
+        }
+        for s in 1..light.len().min(count + 1 - t) + 1 {
+            color = color + connect(scene, &light, &camera, s, t, count);
+        }
+    }
+    color
+}
+
+// A path from a random point on a random emitter, in a random direction, at
+// `time` in the shutter interval.
+fn light_path(scene: &Scene, count: usize, time: f32, rng: &mut Rng) -> Vec<Vertex> {
+    let mut vertices = vec![];
+    let emitters = scene.emitters.len();
+    if emitters == 0 { return vertices; }
+    let emitter = scene.emitters[((rng.next_f32() * emitters as f32) as usize).min(emitters - 1)];
+    let (point, normal, area) = match integrator::surface_point(scene, emitter, rng.next_f32(), rng.next_f32(), time) {
+        Some(sample) => sample,
+        None => return vertices,
+    };
+    let (ray, hit) = match integrator::onto(scene, emitter, point, normal, time) {
+        Some(onto) => onto,
+        None => return vertices,
+    };
+    let pdf = 1.0 / (area * emitters as f32);
+    let emitted = scene.material_at(&hit).emitted(&ray, &hit);
+    let point = ray.point_at(hit.t);
+    let normal = hit.normal;
+    vertices.push(Vertex {
+        ray: ray.clone(),
+        hit: hit,
+        point: point,
+        beta: vec3(1.0, 1.0, 1.0) / pdf,
+        delta: false,
+        pdf_forward: pdf,
+        pdf_reverse: 0.0,
+    });
+
+    // emitters glow the same way on both sides
+    let direction = math::uniform_sphere(rng.next_f32(), rng.next_f32());
+    let side = if normal.dot(direction) < 0.0 { -normal } else { normal };
+    let beta = emitted * (normal.dot(direction).abs() * 4.0 * PI / pdf);
+    let ray = Ray::new(point + side * EPSILON, direction).at_time(ray.time);
+    walk(scene, ray, beta, 1.0 / (4.0 * PI), count, &mut vertices, rng);
+    vertices
+}
+
+// Follows a ray carrying `beta`, picked with density `pdf`, from bounce to
+// bounce until there are `count` vertices or it is lost, and gives how much
+// of it left the scene.
+fn walk(scene: &Scene, mut ray: Ray, mut beta: Vector3<f32>, mut pdf: f32, count: usize, vertices: &mut Vec<Vertex>, rng: &mut Rng) -> Vector3<f32> {
+    while vertices.len() < count {
+        let hit = match scene.closest_hit(&ray) {
+            Some(hit) => hit,
+            None => return beta,
+        };
+        let point = ray.point_at(hit.t);
+        let mut vertex = Vertex {
+            ray: ray.clone(),
+            hit: hit,
+            point: point,
+            beta: beta,
+            delta: false,
+            pdf_forward: 0.0,
+            pdf_reverse: 0.0,
+        };
+        vertex.pdf_forward = to_area(pdf, ray.point, &vertex);
+        vertices.push(vertex);
+        if vertices.len() == count { break; }
+
+        // mirror-like rays and the material's own sample are picked between
+        // evenly
+        let (next, weight, delta) = {
+            let vertex = &vertices[vertices.len() - 1];
+            let material = scene.material_at(&vertex.hit);
+            let mut choices = material.scatter(&ray, &vertex.hit, rng);
+            let scattered = choices.len();
+            choices.extend(material.sample(&ray, &vertex.hit, rng));
+            if choices.is_empty() { break; }
+            let n = choices.len();
+            let k = ((rng.next_f32() * n as f32) as usize).min(n - 1);
+            let (next, weight) = choices.swap_remove(k);
+            (next, weight * n as f32, k < scattered)
+        };
+        if weight == Vector3::zero() { break; }
+        let last = vertices.len() - 1;
+        pdf = if delta { 0.0 } else {
+            let vertex = &vertices[last];
+            scene.material_at(&vertex.hit).pdf(&ray, &vertex.hit, next.direction)
+        };
+        if last > 0 && !delta {
+            let back = pdf_between(scene, &vertices[last], next.point + next.direction, &vertices[last - 1]);
+            vertices[last - 1].pdf_reverse = back;
+        }
+        vertices[last].delta = delta;
+        beta = beta * weight;
+        ray = next;
+    }
+    Vector3::zero()
+}
+
+// The light carried by joining the first `s` light vertices to the first `t`
+// camera vertices, with its weight.
+fn connect(scene: &Scene, light: &[Vertex], camera: &[Vertex], s: usize, t: usize, count: usize) -> Vector3<f32> {
+    let (y, z) = (&light[s - 1], &camera[t - 1]);
+    let to = y.point - z.point;
+    let distance2 = to.length2();
+    let direction = to / distance2.sqrt();
+    let fz = scene.material_at(&z.hit).eval(&z.ray, &z.hit, direction);
+    if fz == Vector3::zero() { return Vector3::zero(); }
+    let fy = if s == 1 {
+        scene.material_at(&y.hit).emitted(&y.ray, &y.hit) * y.hit.normal.dot(direction).abs()
+    } else {
+        scene.material_at(&y.hit).eval(&y.ray, &y.hit, -direction)
+    };
+    if fy == Vector3::zero() { return Vector3::zero(); }
+
+    let (from, to) = (z.point + side(z, direction) * EPSILON, y.point + side(y, -direction) * EPSILON);
+    let shadow = Ray::new(from, to - from).at_time(z.ray.time);
+    if scene.in_shadow(&shadow, (to - from).length()) { return Vector3::zero(); }
+    y.beta * fy * fz * z.beta * (weight(scene, light, camera, s, t, count) / distance2)
+}
+
+// The power heuristic weight of joining the first `s` light vertices to the
+// first `t` camera vertices, against joining the same path anywhere else or
+// the camera path reaching the emitter on its own.
+fn weight(scene: &Scene, light: &[Vertex], camera: &[Vertex], s: usize, t: usize, count: usize) -> f32 {
+    let densities = |vertices: &[Vertex]| -> Vec<(f32, f32, bool)> {
+        vertices.iter().map(|v| (v.pdf_forward, v.pdf_reverse, v.delta)).collect()
+    };
+    let (mut y, mut z) = (densities(&light[..s]), densities(&camera[..t]));
+
+    // the densities around the join are those of the path as joined
+    let zt = &camera[t - 1];
+    z[t - 1].2 = false;
+    z[t - 1].1 = if s == 0 {
+        match integrator::emitter_at(scene, &zt.ray, &zt.hit) {
+            Some(emitter) => {
+                let area = integrator::surface_point(scene, emitter, 0.5, 0.5, zt.ray.time).map_or(0.0, |p| p.2);
+                1.0 / (area * scene.emitters.len() as f32)
+            }
+            None => 0.0,
+        }
+    } else if s == 1 {
+        to_area(1.0 / (4.0 * PI), light[0].point, zt)
+    } else {
+        pdf_between(scene, &light[s - 1], light[s - 1].ray.point, zt)
+    };
+    if t >= 2 {
+        z[t - 2].1 = if s == 0 {
+            to_area(1.0 / (4.0 * PI), zt.point, &camera[t - 2])
+        } else {
+            pdf_between(scene, zt, light[s - 1].point, &camera[t - 2])
+        };
+    }
+    if s >= 1 {
+        y[s - 1].2 = false;
+        y[s - 1].1 = pdf_between(scene, zt, zt.ray.point, &light[s - 1]);
+    }
+    if s >= 2 {
+        y[s - 2].1 = pdf_between(scene, &light[s - 1], zt.point, &light[s - 2]);
+    }
+
+    let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        ratio *= remap(z[i].1) / remap(z[i].0);
+        if !z[i].2 && !z[i - 1].2 { sum += ratio * ratio; }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(y[i].1) / remap(y[i].0);
+        let before = i > 0 && y[i - 1].2;
+        // the camera path is only so long
+        if !y[i].2 && !before && s + t - i <= count { sum += ratio * ratio; }
+    }
+    1.0 / (1.0 + sum)
+}
+
+// The density, over area at `to`, of the vertex's material bouncing light
+// that arrived from `from` on to `to`.
+fn pdf_between(scene: &Scene, vertex: &Vertex, from: Point3<f32>, to: &Vertex) -> f32 {
+    let incoming = (vertex.point - from).normalize();
+    let ray = Ray::new(vertex.point + incoming * -1.0, incoming).at_time(vertex.ray.time);
+    let hit = Hit { t: 1.0, ..vertex.hit.clone() };
+    let pdf = scene.material_at(&hit).pdf(&ray, &hit, (to.point - vertex.point).normalize());
+    to_area(pdf, vertex.point, to)
+}
+
+// A density over directions from `from` as one over area at the vertex.
+fn to_area(pdf: f32, from: Point3<f32>, to: &Vertex) -> f32 {
+    let to_vertex = to.point - from;
+    let distance2 = to_vertex.length2();
+    if distance2 == 0.0 { return 0.0; }
+    pdf * to.hit.normal.dot(to_vertex / distance2.sqrt()).abs() / distance2
+}
+
+// The side of the vertex's surface facing `direction`.
+fn side(vertex: &Vertex, direction: Vector3<f32>) -> Vector3<f32> {
+    if vertex.hit.normal.dot(direction) < 0.0 { -vertex.hit.normal } else { vertex.hit.normal }
+}
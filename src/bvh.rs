@@ -0,0 +1,436 @@
+use cgmath::*;
+
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+use std::thread;
+
+use super::Ray;
+use super::aabb::Aabb;
+use super::accelerator::Accelerator;
+use super::cache::{read_point, read_u64, write_point, write_u64};
+use super::math::{inverse_direction, ray_slabs};
+
+const LEAF_SIZE: usize = 2;
+const SAH_BINS: usize = 12;
+// levels of the linear build that hand their subtrees to new threads
+const PARALLEL_DEPTH: usize = 3;
+
+#[derive(Debug)]
+struct Node {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+    // interior nodes keep their left child right after themselves
+    right: usize,
+    axis: usize,
+}
+
+/// A bounding volume hierarchy over a list of primitive bounds. The
+/// hierarchy only stores primitive indices, so the owner keeps its
+/// primitives and tests them itself while traversing.
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: vec![],
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    /// Builds a linear BVH: primitives are sorted along a Morton curve over
+    /// their centroids and the hierarchy split where the codes first differ.
+    /// Much faster to build than `new`, and the top levels are built in
+    /// parallel, at the cost of somewhat worse trees.
+    pub fn linear(bounds: &[Aabb]) -> Bvh {
+        if bounds.is_empty() { return Bvh::new(bounds); }
+        let first = bounds[0].center();
+        let centroids = bounds.iter().fold(Aabb::new(first, first), |b, a| {
+            let c = a.center();
+            b.union(&Aabb::new(c, c))
+        });
+        let mut keyed: Vec<(u32, usize)> = bounds.iter().enumerate()
+            .map(|(i, b)| (morton_code(&centroids, b.center()), i))
+            .collect();
+        keyed.sort_unstable();
+        let codes: Vec<u32> = keyed.iter().map(|k| k.0).collect();
+        let indices: Vec<usize> = keyed.iter().map(|k| k.1).collect();
+        Bvh {
+            nodes: emit_linear(bounds, &indices, &codes, 0, 0),
+            indices: indices,
+        }
+    }
+
+    /// Recomputes every node's bounds from new primitive bounds while
+    /// keeping the tree's shape. Much cheaper than a rebuild, but the tree
+    /// gets worse the further primitives move from where it was built.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        // children always come after their parent, so walking backwards
+        // updates them first
+        for index in (0..self.nodes.len()).rev() {
+            let node_bounds = {
+                let node = &self.nodes[index];
+                if node.count == 0 {
+                    self.nodes[index + 1].bounds.union(&self.nodes[node.right].bounds)
+                } else {
+                    let range = &self.indices[node.start..node.start + node.count];
+                    range.iter().skip(1).fold(bounds[range[0]], |b, &i| b.union(&bounds[i]))
+                }
+            };
+            self.nodes[index].bounds = node_bounds;
+        }
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u64(w, self.nodes.len() as u64)?;
+        for node in &self.nodes {
+            write_point(w, node.bounds.min)?;
+            write_point(w, node.bounds.max)?;
+            for &x in &[node.start, node.count, node.right, node.axis] {
+                write_u64(w, x as u64)?;
+            }
+        }
+        write_u64(w, self.indices.len() as u64)?;
+        for &i in &self.indices {
+            write_u64(w, i as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a BVH written by `write` over `primitives` primitives. Entries
+    /// that don't hold together, like nodes pointing past the end, are
+    /// `InvalidData` rather than a panic when traversed.
+    pub fn read(r: &mut dyn Read, primitives: usize) -> io::Result<Bvh> {
+        let mut bvh = Bvh { nodes: vec![], indices: vec![] };
+        for _ in 0..read_u64(r)? {
+            let bounds = Aabb::new(read_point(r)?, read_point(r)?);
+            bvh.nodes.push(Node {
+                bounds: bounds,
+                start: read_u64(r)? as usize,
+                count: read_u64(r)? as usize,
+                right: read_u64(r)? as usize,
+                axis: read_u64(r)? as usize,
+            });
+        }
+        for _ in 0..read_u64(r)? {
+            bvh.indices.push(read_u64(r)? as usize);
+        }
+
+        let invalid = |message: &str| Err(io::Error::new(io::ErrorKind::InvalidData, format!("bvh: {}", message)));
+        if bvh.indices.len() != primitives || bvh.indices.iter().any(|&i| i >= primitives) {
+            return invalid("primitive index out of range");
+        }
+        for (index, node) in bvh.nodes.iter().enumerate() {
+            if node.count == 0 {
+                // children come after their parent, so traversal always ends
+                if index + 1 >= bvh.nodes.len() || node.right <= index || node.right >= bvh.nodes.len() || node.axis > 2 {
+                    return invalid("node child out of range");
+                }
+            } else if node.start.checked_add(node.count).is_none_or(|end| end > bvh.indices.len()) {
+                return invalid("leaf out of range");
+            }
+        }
+        Ok(bvh)
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, count: usize) -> usize {
+        let range = &mut self.indices[start..start + count];
+        let node_bounds = range.iter().skip(1).fold(bounds[range[0]], |b, &i| b.union(&bounds[i]));
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds: node_bounds, start: start, count: count, right: 0, axis: 0 });
+        if count <= LEAF_SIZE { return index; }
+
+        let first = bounds[range[0]].center();
+        let centroids = range.iter().fold(Aabb::new(first, first), |b, &i| {
+            let c = bounds[i].center();
+            b.union(&Aabb::new(c, c))
+        });
+        let (axis, half) = match sah_split(bounds, range, &centroids) {
+            Some(split) => split,
+            None => {
+                // fall back to a median split on the longest axis of the centroids
+                let extent = centroids.max - centroids.min;
+                let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+                let half = count / 2;
+                range.select_nth_unstable_by(half, |&a, &b| {
+                    bounds[a].center()[axis].partial_cmp(&bounds[b].center()[axis]).unwrap_or(Ordering::Equal)
+                });
+                (axis, half)
+            }
+        };
+
+        self.build(bounds, start, half);
+        let right = self.build(bounds, start + half, count - half);
+        let node = &mut self.nodes[index];
+        node.count = 0;
+        node.right = right;
+        node.axis = axis;
+        index
+    }
+
+    /// Visits every primitive whose bounds the ray enters before `t_max`,
+    /// nearer children first. `visit` returns the distance of a hit closer
+    /// than any seen so far, which prunes the rest of the search.
+    pub fn closest<F: FnMut(usize) -> Option<f32>>(&self, ray: &Ray, t_max: f32, mut visit: F) {
+        if self.nodes.is_empty() { return; }
+        let mut t_max = t_max;
+        let inv_direction = inverse_direction(ray.direction);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray_slabs(ray.point, inv_direction, node.bounds.min, node.bounds.max) {
+                Some((t_enter, _)) if t_enter < t_max => {}
+                _ => continue,
+            }
+            if node.count == 0 {
+                // pop the child on the near side of the split first
+                if ray.direction[node.axis] < 0.0 {
+                    stack.push(index + 1);
+                    stack.push(node.right);
+                } else {
+                    stack.push(node.right);
+                    stack.push(index + 1);
+                }
+                continue;
+            }
+            for &i in &self.indices[node.start..node.start + node.count] {
+                if let Some(t) = visit(i) {
+                    t_max = t_max.min(t);
+                }
+            }
+        }
+    }
+
+    /// Whether `test` accepts any primitive whose bounds the ray enters
+    /// before `t_max`; stops at the first one.
+    pub fn any<F: FnMut(usize) -> bool>(&self, ray: &Ray, t_max: f32, mut test: F) -> bool {
+        if self.nodes.is_empty() { return false; }
+        let inv_direction = inverse_direction(ray.direction);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray_slabs(ray.point, inv_direction, node.bounds.min, node.bounds.max) {
+                Some((t_enter, _)) if t_enter < t_max => {}
+                _ => continue,
+            }
+            if node.count == 0 {
+                stack.push(node.right);
+                stack.push(index + 1);
+                continue;
+            }
+            for &i in &self.indices[node.start..node.start + node.count] {
+                if test(i) { return true; }
+            }
+        }
+        false
+    }
+}
+
+// Binned surface area heuristic: buckets the centroids along each axis and
+// picks the bucket boundary that minimizes the summed area times primitive
+// count of the two sides. Partitions `range` and returns the axis and the
+// size of the left side, or None if the centroids can't be separated.
+fn sah_split(bounds: &[Aabb], range: &mut [usize], centroids: &Aabb) -> Option<(usize, usize)> {
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        let lo = centroids.min[axis];
+        let extent = centroids.max[axis] - lo;
+        if extent <= 0.0 { continue; }
+        let mut bins: [(Option<Aabb>, usize); SAH_BINS] = [(None, 0); SAH_BINS];
+        for &i in range.iter() {
+            let bin = &mut bins[bin_of(&bounds[i], axis, lo, extent)];
+            bin.0 = Some(bin.0.map_or(bounds[i], |b| b.union(&bounds[i])));
+            bin.1 += 1;
+        }
+
+        // sweep from the right to get the cost of every right-hand side
+        let mut right_cost = [0.0; SAH_BINS];
+        let mut acc: (Option<Aabb>, usize) = (None, 0);
+        for b in (1..SAH_BINS).rev() {
+            acc = merge(acc, bins[b]);
+            right_cost[b] = acc.0.map_or(0.0, |a| a.surface_area()) * acc.1 as f32;
+        }
+        let mut acc: (Option<Aabb>, usize) = (None, 0);
+        for b in 0..SAH_BINS - 1 {
+            acc = merge(acc, bins[b]);
+            if acc.1 == 0 || acc.1 == range.len() { continue; }
+            let cost = acc.0.map_or(0.0, |a| a.surface_area()) * acc.1 as f32 + right_cost[b + 1];
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+
+    best.map(|(_, axis, split)| {
+        let lo = centroids.min[axis];
+        let extent = centroids.max[axis] - lo;
+        let mut left = 0;
+        for j in 0..range.len() {
+            if bin_of(&bounds[range[j]], axis, lo, extent) <= split {
+                range.swap(left, j);
+                left += 1;
+            }
+        }
+        (axis, left)
+    })
+}
+
+fn bin_of(bounds: &Aabb, axis: usize, lo: f32, extent: f32) -> usize {
+    (((bounds.center()[axis] - lo) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+}
+
+fn merge(a: (Option<Aabb>, usize), b: (Option<Aabb>, usize)) -> (Option<Aabb>, usize) {
+    let bounds = match (a.0, b.0) {
+        (Some(x), Some(y)) => Some(x.union(&y)),
+        (x, y) => x.or(y),
+    };
+    (bounds, a.1 + b.1)
+}
+
+// Interleaves 10 bits of each centroid coordinate, x highest.
+fn morton_code(centroids: &Aabb, p: Point3<f32>) -> u32 {
+    let mut code = 0;
+    for axis in 0..3 {
+        let extent = centroids.max[axis] - centroids.min[axis];
+        let f = if extent > 0.0 { (p[axis] - centroids.min[axis]) / extent } else { 0.0 };
+        let q = ((f * 1024.0) as u32).min(1023);
+        for bit in 0..10 {
+            code |= ((q >> bit) & 1) << (3 * bit + 2 - axis);
+        }
+    }
+    code
+}
+
+// Builds the subtree over `indices[..]`, which start at `start` in the full
+// sorted list. Child links are relative to the subtree's first node.
+fn emit_linear(bounds: &[Aabb], indices: &[usize], codes: &[u32], start: usize, depth: usize) -> Vec<Node> {
+    let count = indices.len();
+    let node_bounds = indices.iter().skip(1).fold(bounds[indices[0]], |b, &i| b.union(&bounds[i]));
+    if count <= LEAF_SIZE {
+        return vec![Node { bounds: node_bounds, start: start, count: count, right: 0, axis: 0 }];
+    }
+
+    let (split, axis) = {
+        let diff = codes[0] ^ codes[count - 1];
+        if diff == 0 {
+            (count / 2, 0)
+        } else {
+            // split at the first code with the highest differing bit set
+            let bit = 31 - diff.leading_zeros();
+            let split = codes.iter().position(|&c| (c >> bit) & 1 == 1).unwrap();
+            (split, 2 - bit as usize % 3)
+        }
+    };
+
+    let (left, right) = if depth < PARALLEL_DEPTH {
+        thread::scope(|scope| {
+            let left = scope.spawn(|| emit_linear(bounds, &indices[..split], &codes[..split], start, depth + 1));
+            let right = emit_linear(bounds, &indices[split..], &codes[split..], start + split, depth + 1);
+            (left.join().unwrap(), right)
+        })
+    } else {
+        (emit_linear(bounds, &indices[..split], &codes[..split], start, depth + 1),
+         emit_linear(bounds, &indices[split..], &codes[split..], start + split, depth + 1))
+    };
+
+    let right_offset = 1 + left.len();
+    let mut nodes = Vec::with_capacity(right_offset + right.len());
+    nodes.push(Node { bounds: node_bounds, start: start, count: 0, right: right_offset, axis: axis });
+    for (offset, subtree) in [(1, left), (right_offset, right)] {
+        nodes.extend(subtree.into_iter().map(|mut node| {
+            if node.count == 0 { node.right += offset; }
+            node
+        }));
+    }
+    nodes
+}
+
+impl Accelerator for Bvh {
+    fn closest(&self, ray: &Ray, t_max: f32, visit: &mut dyn FnMut(usize) -> Option<f32>) {
+        Bvh::closest(self, ray, t_max, visit)
+    }
+
+    fn any(&self, ray: &Ray, t_max: f32, test: &mut dyn FnMut(usize) -> bool) -> bool {
+        Bvh::any(self, ray, t_max, test)
+    }
+
+    fn refit(&mut self, bounds: &[Aabb]) -> bool {
+        Bvh::refit(self, bounds);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxes(count: usize) -> Vec<Aabb> {
+        (0..count).map(|i| {
+            let corner = Point3::new((i * 7 % 11) as f32, (i * 3 % 5) as f32, i as f32 * 0.5);
+            Aabb::new(corner, corner + vec3(1.0, 0.5, 2.0))
+        }).collect()
+    }
+
+    fn written(bvh: &Bvh) -> Vec<u8> {
+        let mut bytes = vec![];
+        bvh.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read(bytes: &[u8], primitives: usize) -> io::Result<Bvh> {
+        Bvh::read(&mut &bytes[..], primitives)
+    }
+
+    #[test]
+    fn round_trip() {
+        for bvh in &[Bvh::new(&boxes(40)), Bvh::linear(&boxes(40))] {
+            let copy = read(&written(bvh), 40).unwrap();
+            assert_eq!(format!("{:?}", copy), format!("{:?}", bvh));
+        }
+    }
+
+    #[test]
+    fn rejects_what_would_break_traversal() {
+        let bounds = boxes(40);
+        let invalid = |bvh: Bvh, primitives: usize| {
+            let error = read(&written(&bvh), primitives).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        };
+
+        invalid(Bvh::new(&bounds), 39);
+
+        let mut bvh = Bvh::new(&bounds);
+        bvh.indices[3] = 40;
+        invalid(bvh, 40);
+
+        let mut bvh = Bvh::new(&bounds);
+        bvh.nodes[0].right = bvh.nodes.len();
+        invalid(bvh, 40);
+
+        let mut bvh = Bvh::new(&bounds);
+        bvh.nodes[0].right = 0;
+        invalid(bvh, 40);
+
+        let mut bvh = Bvh::new(&bounds);
+        let leaf = bvh.nodes.iter().position(|node| node.count > 0).unwrap();
+        bvh.nodes[leaf].start = 40;
+        invalid(bvh, 40);
+
+        let mut bvh = Bvh::new(&bounds);
+        bvh.nodes[leaf].count = usize::MAX;
+        invalid(bvh, 40);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = written(&Bvh::new(&boxes(40)));
+        assert!(read(&bytes[..bytes.len() - 1], 40).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use cgmath::*;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::aabb::Aabb;
+use super::bezier;
+use super::bvh::Bvh;
+use super::mesh::Mesh;
+use super::obj;
+use super::subdivision;
+
+const MAGIC: &[u8; 4] = b"RTC1";
+
+/// A directory of built BVHs and meshes, keyed by a hash of whatever they
+/// were built from, so repeated renders of a heavy scene skip the build.
+/// Unreadable or stale entries are rebuilt and written over.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+impl Cache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Cache> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Cache { dir: dir.as_ref().to_path_buf() })
+    }
+
+    /// A BVH over `bounds`, loaded from the cache if one was built over the
+    /// same bounds before.
+    pub fn bvh(&self, bounds: &[Aabb]) -> Bvh {
+        let mut hasher = Hasher::new();
+        for b in bounds {
+            hasher.write_floats(&[b.min.x, b.min.y, b.min.z, b.max.x, b.max.y, b.max.z]);
+        }
+        let path = self.entry("bvh", hasher.finish());
+        if let Ok(bvh) = self.load(&path, |r| Bvh::read(r, bounds.len())) {
+            return bvh;
+        }
+        let bvh = Bvh::new(bounds);
+        self.store(&path, |w| bvh.write(w));
+        bvh
+    }
+
+    /// The mesh built by `build`, or the one cached under `key` if there is
+    /// one. The key should hash everything the mesh depends on; see `Hasher`.
+    pub fn mesh<F: FnOnce() -> io::Result<Mesh>>(&self, key: u64, build: F) -> io::Result<Mesh> {
+        let path = self.entry("mesh", key);
+        if let Ok(mesh) = self.load(&path, |r| Mesh::read(r)) {
+            return Ok(mesh);
+        }
+        let mesh = build()?;
+        self.store(&path, |w| mesh.write(w));
+        Ok(mesh)
+    }
+
+    /// `obj::load`, keyed by the file's contents.
+    pub fn obj(&self, path: &Path, color: Vector3<f32>) -> io::Result<Mesh> {
+        let mut contents = vec![];
+        File::open(path)?.read_to_end(&mut contents)?;
+        let mut hasher = Hasher::new();
+        hasher.write(&contents);
+        hasher.write_floats(&[color.x, color.y, color.z]);
+        self.mesh(hasher.finish(), || obj::load(path, color))
+    }
+
+    /// `subdivision::catmull_clark`, keyed by the control mesh.
+    pub fn subdivision(&self, positions: Vec<Point3<f32>>, faces: Vec<Vec<usize>>, levels: usize, color: Vector3<f32>) -> Mesh {
+        let mut hasher = Hasher::new();
+        for p in &positions {
+            hasher.write_floats(&[p.x, p.y, p.z]);
+        }
+        for face in &faces {
+            hasher.write(&(face.len() as u64).to_le_bytes());
+            for &i in face {
+                hasher.write(&(i as u64).to_le_bytes());
+            }
+        }
+        hasher.write(&(levels as u64).to_le_bytes());
+        hasher.write_floats(&[color.x, color.y, color.z]);
+        // building never fails, so neither can this
+        let build = || Ok(subdivision::catmull_clark(positions, faces, levels, color));
+        self.mesh(hasher.finish(), build).unwrap()
+    }
+
+    /// The Bezier patches in a `.bpt` file tessellated by
+    /// `bezier::tessellate`, keyed by the file's contents.
+    pub fn bpt(&self, path: &Path, resolution: usize, color: Vector3<f32>) -> io::Result<Mesh> {
+        let mut contents = vec![];
+        File::open(path)?.read_to_end(&mut contents)?;
+        let mut hasher = Hasher::new();
+        hasher.write(&contents);
+        hasher.write(&(resolution as u64).to_le_bytes());
+        hasher.write_floats(&[color.x, color.y, color.z]);
+        self.mesh(hasher.finish(), || Ok(bezier::tessellate(&bezier::load_bpt(path)?, resolution, color)))
+    }
+
+    fn entry(&self, kind: &str, key: u64) -> PathBuf {
+        self.dir.join(format!("{}-{:016x}.bin", kind, key))
+    }
+
+    fn load<T, F: FnOnce(&mut dyn Read) -> io::Result<T>>(&self, path: &Path, read: F) -> io::Result<T> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a cache file"));
+        }
+        read(&mut reader)
+    }
+
+    // A cache that can't be written is only a missed speedup, so failures
+    // are ignored rather than failing the render.
+    fn store<F: FnOnce(&mut dyn Write) -> io::Result<()>>(&self, path: &Path, write: F) {
+        let result = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(MAGIC)?;
+            write(&mut writer)?;
+            writer.flush()
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// 64-bit FNV-1a. Unlike std's hasher its output is stable across builds,
+/// which cache keys need.
+pub struct Hasher(u64);
+impl Default for Hasher {
+    fn default() -> Hasher {
+        Hasher::new()
+    }
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        Hasher(0xcbf29ce484222325)
+    }
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    pub fn write_floats(&mut self, floats: &[f32]) {
+        for &x in floats {
+            self.write(&x.to_bits().to_le_bytes());
+        }
+    }
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn write_u64(w: &mut dyn Write, x: u64) -> io::Result<()> {
+    w.write_all(&x.to_le_bytes())
+}
+
+pub fn write_f32(w: &mut dyn Write, x: f32) -> io::Result<()> {
+    w.write_all(&x.to_bits().to_le_bytes())
+}
+
+pub fn read_u64(r: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32(r: &mut dyn Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(f32::from_bits(u32::from_le_bytes(bytes)))
+}
+
+pub fn write_point(w: &mut dyn Write, p: Point3<f32>) -> io::Result<()> {
+    write_f32(w, p.x)?;
+    write_f32(w, p.y)?;
+    write_f32(w, p.z)
+}
+
+pub fn read_point(r: &mut dyn Read) -> io::Result<Point3<f32>> {
+    Ok(Point3::new(read_f32(r)?, read_f32(r)?, read_f32(r)?))
+}
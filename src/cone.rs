@@ -0,0 +1,89 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::{Aabb, Bounded};
+
+/// A finite cone opening from `apex` along `axis`, closed by a flat base
+/// `height` units away.
+#[derive(Debug, PartialEq)]
+pub struct Cone {
+    apex: Point3<f32>,
+    axis: Vector3<f32>,
+    half_angle: f32,
+    height: f32,
+    color: Vector3<f32>,
+}
+impl Cone {
+    pub fn new(apex: Point3<f32>, axis: Vector3<f32>, half_angle: f32, height: f32, color: Vector3<f32>) -> Cone {
+        Cone {
+            apex: apex,
+            axis: axis.normalize(),
+            half_angle: half_angle,
+            height: height,
+            color: color,
+        }
+    }
+}
+
+impl Intersect for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let co = ray.point - self.apex;
+        let d_v = ray.direction.dot(self.axis);
+        let co_v = co.dot(self.axis);
+        let cos2 = self.half_angle.cos().powi(2);
+
+        let mut best: Option<(f32, Vector3<f32>)> = None;
+        let mut consider = |t: f32, normal: Vector3<f32>| {
+            if t >= 0.0 && best.is_none_or(|b| t < b.0) {
+                best = Some((t, normal));
+            }
+        };
+
+        let a = d_v * d_v - cos2;
+        let b = 2.0 * (d_v * co_v - ray.direction.dot(co) * cos2);
+        let c = co_v * co_v - co.dot(co) * cos2;
+        let roots = if a.abs() < 1e-6 {
+            // the ray runs parallel to the surface and crosses it at most once
+            if b == 0.0 { vec![] } else { vec![-c / b] }
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                vec![]
+            } else {
+                let sqrt = discriminant.sqrt();
+                vec![(-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a)]
+            }
+        };
+        for t in roots {
+            // reject the mirrored nappe behind the apex
+            let h = co_v + t * d_v;
+            if h >= 0.0 && h <= self.height {
+                let cp = co + ray.direction * t;
+                consider(t, (cp * cos2 - self.axis * cp.dot(self.axis)).normalize());
+            }
+        }
+
+        if d_v != 0.0 {
+            let t = (self.height - co_v) / d_v;
+            let p = co + ray.direction * t - self.axis * self.height;
+            let base_radius = self.height * self.half_angle.tan();
+            if p.dot(p) <= base_radius * base_radius {
+                consider(t, self.axis);
+            }
+        }
+
+        best.map(|(t, normal)| Hit { t: t, normal: normal, color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+}
+
+impl Bounded for Cone {
+    fn aabb(&self) -> Aabb {
+        let base = self.apex + self.axis * self.height;
+        let disc = Aabb::around_disc(base, self.axis, self.height * self.half_angle.tan());
+        disc.union(&Aabb::new(self.apex, self.apex))
+    }
+}
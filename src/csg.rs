@@ -0,0 +1,243 @@
+use std::cmp::Ordering;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+
+/// A stretch of a ray that lies inside a solid. Normals on both ends point
+/// out of the solid, and `enter.t` may be negative when the ray starts
+/// inside it.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub enter: Hit,
+    pub exit: Hit,
+}
+
+/// A closed object that can report every span a ray spends inside it, which
+/// is what boolean operations need to combine shapes.
+pub trait Solid: Intersect {
+    /// Spans sorted by `enter.t` and non-overlapping.
+    fn spans(&self, ray: &Ray) -> Vec<Span>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+pub struct Csg {
+    operation: Operation,
+    left: Box<dyn Solid>,
+    right: Box<dyn Solid>,
+}
+impl Csg {
+    pub fn new<A: Solid + 'static, B: Solid + 'static>(operation: Operation, left: A, right: B) -> Csg {
+        Csg {
+            operation: operation,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+    pub fn union<A: Solid + 'static, B: Solid + 'static>(left: A, right: B) -> Csg {
+        Csg::new(Operation::Union, left, right)
+    }
+    pub fn intersection<A: Solid + 'static, B: Solid + 'static>(left: A, right: B) -> Csg {
+        Csg::new(Operation::Intersection, left, right)
+    }
+    /// `left` with `right` carved out of it.
+    pub fn difference<A: Solid + 'static, B: Solid + 'static>(left: A, right: B) -> Csg {
+        Csg::new(Operation::Difference, left, right)
+    }
+}
+
+impl Solid for Csg {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let left = self.left.spans(ray);
+        let right = self.right.spans(ray);
+        match self.operation {
+            Operation::Union => union(left, right),
+            Operation::Intersection => intersection(&left, &right),
+            Operation::Difference => difference(left, &right),
+        }
+    }
+}
+
+impl Intersect for Csg {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        first_hit(self.spans(ray))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match self.operation {
+            Operation::Union => match (self.left.bounds(), self.right.bounds()) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                _ => None,
+            },
+            // the result never reaches outside the left operand
+            Operation::Intersection | Operation::Difference => self.left.bounds(),
+        }
+    }
+}
+
+/// The nearest surface in front of the ray origin.
+pub fn first_hit(spans: Vec<Span>) -> Option<Hit> {
+    for span in spans {
+        let hit = if span.enter.t >= 0.0 { span.enter } else { span.exit };
+        if hit.t >= 0.0 && hit.t.is_finite() {
+            return Some(hit);
+        }
+    }
+    None
+}
+
+fn union(left: Vec<Span>, right: Vec<Span>) -> Vec<Span> {
+    let mut all = left;
+    all.extend(right);
+    all.sort_by(|a, b| a.enter.t.partial_cmp(&b.enter.t).unwrap_or(Ordering::Equal));
+
+    let mut merged: Vec<Span> = vec![];
+    for span in all {
+        match merged.last_mut() {
+            Some(last) if span.enter.t <= last.exit.t => {
+                if span.exit.t > last.exit.t {
+                    last.exit = span.exit;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        merged.push(span);
+    }
+    merged
+}
+
+fn intersection(left: &[Span], right: &[Span]) -> Vec<Span> {
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        let (a, b) = (&left[i], &right[j]);
+        let enter = if a.enter.t > b.enter.t { &a.enter } else { &b.enter };
+        let exit = if a.exit.t < b.exit.t { &a.exit } else { &b.exit };
+        if enter.t < exit.t {
+            result.push(Span { enter: enter.clone(), exit: exit.clone() });
+        }
+        // advance whichever span ends first
+        if a.exit.t < b.exit.t { i += 1; } else { j += 1; }
+    }
+    result
+}
+
+fn difference(left: Vec<Span>, right: &[Span]) -> Vec<Span> {
+    let mut result = vec![];
+    for span in left {
+        let mut current = Some(span);
+        for cut in right {
+            let piece = match current.take() {
+                Some(piece) => piece,
+                None => break,
+            };
+            if cut.exit.t <= piece.enter.t || cut.enter.t >= piece.exit.t {
+                current = Some(piece);
+                continue;
+            }
+            // the carved-out surfaces face into the removed solid
+            if cut.enter.t > piece.enter.t {
+                result.push(Span { enter: piece.enter.clone(), exit: flipped(&cut.enter) });
+            }
+            if cut.exit.t < piece.exit.t {
+                current = Some(Span { enter: flipped(&cut.exit), exit: piece.exit });
+            }
+        }
+        if let Some(piece) = current {
+            result.push(piece);
+        }
+    }
+    result
+}
+
+fn flipped(hit: &Hit) -> Hit {
+    Hit { normal: -hit.normal, ..hit.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::*;
+    use super::super::Sphere;
+
+    fn hit(t: f32, normal: Vector3<f32>) -> Hit {
+        Hit { t: t, normal: normal, color: vec3(1.0, 1.0, 1.0), material: None, front: true }
+    }
+
+    // a span along +x, its ends facing out of it
+    fn span(enter: f32, exit: f32) -> Span {
+        Span { enter: hit(enter, vec3(-1.0, 0.0, 0.0)), exit: hit(exit, vec3(1.0, 0.0, 0.0)) }
+    }
+
+    fn ends(spans: &[Span]) -> Vec<(f32, f32)> {
+        spans.iter().map(|span| (span.enter.t, span.exit.t)).collect()
+    }
+
+    #[test]
+    fn union_merges_overlapping_spans() {
+        assert_eq!(ends(&union(vec![span(1.0, 3.0)], vec![span(2.0, 5.0)])), vec![(1.0, 5.0)]);
+        assert_eq!(ends(&union(vec![span(1.0, 5.0)], vec![span(2.0, 3.0)])), vec![(1.0, 5.0)]);
+        assert_eq!(ends(&union(vec![span(4.0, 6.0)], vec![span(1.0, 2.0)])), vec![(1.0, 2.0), (4.0, 6.0)]);
+    }
+
+    #[test]
+    fn intersection_keeps_the_overlaps() {
+        assert_eq!(ends(&intersection(&[span(1.0, 3.0)], &[span(2.0, 5.0)])), vec![(2.0, 3.0)]);
+        assert_eq!(ends(&intersection(&[span(1.0, 2.0)], &[span(3.0, 4.0)])), vec![]);
+        assert_eq!(ends(&intersection(&[span(0.0, 2.0), span(4.0, 6.0)], &[span(1.0, 5.0)])), vec![(1.0, 2.0), (4.0, 5.0)]);
+    }
+
+    #[test]
+    fn difference_carves_out_the_cuts() {
+        let carved = difference(vec![span(0.0, 5.0)], &[span(2.0, 3.0)]);
+        assert_eq!(ends(&carved), vec![(0.0, 2.0), (3.0, 5.0)]);
+        // the new surfaces face into the hole
+        assert_eq!(carved[0].exit.normal, vec3(1.0, 0.0, 0.0));
+        assert_eq!(carved[1].enter.normal, vec3(-1.0, 0.0, 0.0));
+
+        assert_eq!(ends(&difference(vec![span(0.0, 5.0)], &[span(-1.0, 1.0), span(2.0, 3.0), span(4.0, 6.0)])), vec![(1.0, 2.0), (3.0, 4.0)]);
+        assert_eq!(ends(&difference(vec![span(1.0, 2.0)], &[span(0.0, 3.0)])), vec![]);
+        assert_eq!(ends(&difference(vec![span(1.0, 2.0)], &[span(3.0, 4.0)])), vec![(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn first_hit_skips_what_is_behind_the_origin() {
+        assert_eq!(first_hit(vec![span(-3.0, -1.0), span(2.0, 4.0)]).map(|hit| hit.t), Some(2.0));
+        assert_eq!(first_hit(vec![span(-1.0, 2.0)]).map(|hit| hit.t), Some(2.0));
+        assert!(first_hit(vec![span(-3.0, -1.0)]).is_none());
+    }
+
+    #[test]
+    fn combined_spheres() {
+        let sphere = |x: f32| Sphere::new(Point3::new(x, 0.0, 0.0), 1.0, vec3(1.0, 1.0, 1.0));
+        let from_left = Ray::new(Point3::new(-5.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        let from_right = Ray::new(Point3::new(5.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0));
+
+        let t = |csg: Csg, ray: &Ray| csg.intersect(ray).map(|hit| hit.t);
+        assert_eq!(t(Csg::union(sphere(0.0), sphere(1.0)), &from_left), Some(4.0));
+        assert_eq!(t(Csg::intersection(sphere(0.0), sphere(1.0)), &from_left), Some(5.0));
+        assert_eq!(t(Csg::difference(sphere(0.0), sphere(1.0)), &from_left), Some(4.0));
+
+        // the bite the right sphere takes out of the left one
+        let hit = Csg::difference(sphere(0.0), sphere(1.0)).intersect(&from_right).unwrap();
+        assert_eq!(hit.t, 5.0);
+        assert!(hit.normal.x > 0.0);
+    }
+
+    #[test]
+    fn distant_sphere_spans_agree_with_its_hits() {
+        // a grazing ray a long way off, where `l.l - v v` cancels away
+        let sphere = Sphere::new(Point3::new(20000.0, 0.0, 0.0), 10.0, vec3(1.0, 1.0, 1.0));
+        let ray = Ray::new(Point3::new(0.0, 9.99, 0.0), vec3(1.0, 0.0, 0.0));
+        let spans = sphere.spans(&ray);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(Some(spans[0].enter.t), sphere.intersect(&ray).map(|hit| hit.t));
+    }
+}
@@ -0,0 +1,70 @@
+use cgmath::*;
+use image::*;
+
+use std::path::{Path, PathBuf};
+
+use super::{save, Camera, Scene};
+use super::settings::RenderSettings;
+
+/// How the six faces of a cube map are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    // each face in a file of its own, named after the output with the face
+    // appended, like `test-px.png`
+    Faces,
+    // the faces unfolded into a horizontal cross four faces wide and three
+    // high, in the output file itself; the single faces are still written
+    // as they render
+    Cross,
+}
+
+// A face's name, view direction and up vector, and where it goes in the
+// cross, in faces from the top left.
+type Face = (&'static str, [f32; 3], [f32; 3], (u32, u32));
+
+// The middle row turns right from +x, with +z above it and -z below, so
+// neighbouring faces share edges.
+const FACES: [Face; 6] = [
+    ("px", [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], (1, 1)),
+    ("ny", [0.0, -1.0, 0.0], [0.0, 0.0, 1.0], (2, 1)),
+    ("nx", [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], (3, 1)),
+    ("py", [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], (0, 1)),
+    ("pz", [0.0, 0.0, 1.0], [-1.0, 0.0, 0.0], (1, 0)),
+    ("nz", [0.0, 0.0, -1.0], [1.0, 0.0, 0.0], (1, 2)),
+];
+
+/// Renders the scene from its camera's eye along the six axes, each face a
+/// square of `settings.width` pixels, to bake an environment map. The
+/// camera is left as it was.
+pub fn render(scene: &mut Scene, settings: &RenderSettings, layout: Layout) {
+    let original = scene.camera.clone();
+    let size = settings.width;
+    let mut cross = ImageBuffer::new(4 * size, 3 * size);
+    for &(name, forward, up, (column, row)) in &FACES {
+        let eye = original.eye;
+        let mut camera = Camera::new(eye, eye + Vector3::from(forward));
+        camera.up(Vector3::from(up)).near(original.near).far(original.far)
+            .fovy(std::f32::consts::FRAC_PI_2).aspect_ratio(1.0);
+        scene.camera = camera;
+
+        let mut face_settings = settings.clone();
+        face_settings.width = size;
+        face_settings.height = size;
+        face_settings.output = face_path(&settings.output, name);
+        let face = scene.render(&face_settings);
+        if layout == Layout::Cross {
+            for (x, y, &pixel) in face.enumerate_pixels() {
+                cross.put_pixel(column * size + x, row * size + y, pixel);
+            }
+        }
+    }
+    scene.camera = original;
+    if layout == Layout::Cross {
+        save(&cross, &settings.output);
+    }
+}
+
+fn face_path(output: &Path, face: &str) -> PathBuf {
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("cube");
+    output.with_file_name(format!("{}-{}.png", stem, face))
+}
@@ -0,0 +1,119 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+
+#[derive(Debug, PartialEq)]
+struct Strand {
+    points: Vec<Point3<f32>>,
+    radii: Vec<f32>,
+    bounds: Aabb,
+}
+
+/// A collection of thin strands for hair, fur, or grass. Each strand is a
+/// polyline whose segments are swept into capsules; a strand's bounding box
+/// is checked before any of its segments so misses stay cheap.
+#[derive(Debug, PartialEq)]
+pub struct Curves {
+    strands: Vec<Strand>,
+    color: Vector3<f32>,
+}
+impl Curves {
+    pub fn new(color: Vector3<f32>) -> Curves {
+        Curves {
+            strands: vec![],
+            color: color,
+        }
+    }
+
+    /// Adds a strand through `points`, with one radius per point.
+    pub fn add_polyline(&mut self, points: Vec<Point3<f32>>, radii: Vec<f32>) -> &mut Curves {
+        assert!(points.len() >= 2, "a strand needs at least two points");
+        assert_eq!(points.len(), radii.len());
+
+        let mut bounds = Aabb::new(points[0], points[0]);
+        for (&p, &r) in points.iter().zip(&radii) {
+            let pad = vec3(r, r, r);
+            bounds = bounds.union(&Aabb::new(p + -pad, p + pad));
+        }
+        self.strands.push(Strand { points: points, radii: radii, bounds: bounds });
+        self
+    }
+
+    /// Adds a cubic Bézier strand flattened into `segments` pieces, tapering
+    /// linearly from `root_radius` to `tip_radius`.
+    pub fn add_bezier(&mut self, control: [Point3<f32>; 4], root_radius: f32, tip_radius: f32, segments: usize) -> &mut Curves {
+        let segments = segments.max(1);
+        let mut points = vec![];
+        let mut radii = vec![];
+        for i in 0..segments + 1 {
+            let t = i as f32 / segments as f32;
+            let s = 1.0 - t;
+            let weights = [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t];
+            let p = control.iter().zip(&weights).fold(Vector3::zero(), |sum, (c, &w)| sum + c.to_vec() * w);
+            points.push(Point3::from_vec(p));
+            radii.push(root_radius + (tip_radius - root_radius) * t);
+        }
+        self.add_polyline(points, radii)
+    }
+}
+
+// Ray against the capsule swept by a sphere of `radius` from `a` to `b`;
+// returns the ray parameter and the outward normal.
+fn intersect_capsule(ray: &Ray, a: Point3<f32>, b: Point3<f32>, radius: f32) -> Option<(f32, Vector3<f32>)> {
+    let ba = b - a;
+    let oa = ray.point - a;
+    let baba = ba.dot(ba);
+    let bard = ba.dot(ray.direction);
+    let baoa = ba.dot(oa);
+    let rdoa = ray.direction.dot(oa);
+    let oaoa = oa.dot(oa);
+    let r2 = radius * radius;
+
+    let qa = baba - bard * bard;
+    let qb = baba * rdoa - baoa * bard;
+    let qc = baba * oaoa - baoa * baoa - r2 * baba;
+    let h = qb * qb - qa * qc;
+    if h < 0.0 { return None; }
+
+    let mut t = (-qb - h.sqrt()) / qa;
+    let y = baoa + t * bard;
+    if !(qa > 0.0 && y > 0.0 && y < baba) {
+        // outside the straight section, so try the spherical end it's nearest
+        let oc = if y <= 0.0 { oa } else { ray.point - b };
+        let b = ray.direction.dot(oc);
+        let c = oc.dot(oc) - r2;
+        let h = b * b - c;
+        if h < 0.0 { return None; }
+        t = -b - h.sqrt();
+    }
+    if t < 0.0 { return None; }
+
+    let p = ray.point_at(t) - a;
+    let along = (p.dot(ba) / baba).clamp(0.0, 1.0);
+    Some((t, (p - ba * along) / radius))
+}
+
+impl Intersect for Curves {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<(f32, Vector3<f32>)> = None;
+        for strand in &self.strands {
+            match strand.bounds.slabs(ray) {
+                Some((t_enter, _)) if closest.is_none_or(|c| t_enter < c.0) => {}
+                _ => continue,
+            }
+            for i in 0..strand.points.len() - 1 {
+                let radius = 0.5 * (strand.radii[i] + strand.radii[i + 1]);
+                match intersect_capsule(ray, strand.points[i], strand.points[i + 1], radius) {
+                    Some((t, normal)) if closest.is_none_or(|c| t < c.0) => closest = Some((t, normal)),
+                    _ => {}
+                }
+            }
+        }
+        closest.map(|(t, normal)| Hit { t: t, normal: normal, color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.strands.iter().fold(None, |b: Option<Aabb>, s| Some(b.map_or(s.bounds, |b| b.union(&s.bounds))))
+    }
+}
@@ -0,0 +1,144 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+use super::csg::{Solid, Span};
+
+/// A circular cylinder around `axis` starting at `base`. A finite cylinder
+/// has a `height` and may be closed with flat end caps; an infinite one
+/// extends along the axis in both directions.
+#[derive(Debug, PartialEq)]
+pub struct Cylinder {
+    base: Point3<f32>,
+    axis: Vector3<f32>,
+    radius: f32,
+    height: Option<f32>,
+    capped: bool,
+    color: Vector3<f32>,
+}
+impl Cylinder {
+    pub fn new(base: Point3<f32>, top: Point3<f32>, radius: f32, capped: bool, color: Vector3<f32>) -> Cylinder {
+        let axis = top - base;
+        Cylinder {
+            base: base,
+            axis: axis.normalize(),
+            radius: radius,
+            height: Some(axis.length()),
+            capped: capped,
+            color: color,
+        }
+    }
+
+    pub fn infinite(point: Point3<f32>, axis: Vector3<f32>, radius: f32, color: Vector3<f32>) -> Cylinder {
+        Cylinder {
+            base: point,
+            axis: axis.normalize(),
+            radius: radius,
+            height: None,
+            capped: false,
+            color: color,
+        }
+    }
+}
+
+impl Intersect for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let o = ray.point - self.base;
+        let o_along = o.dot(self.axis);
+        let d_along = ray.direction.dot(self.axis);
+        // project the ray onto the plane perpendicular to the axis
+        let o_perp = o - self.axis * o_along;
+        let d_perp = ray.direction - self.axis * d_along;
+        let r2 = self.radius * self.radius;
+
+        let mut best: Option<(f32, Vector3<f32>)> = None;
+        let mut consider = |t: f32, normal: Vector3<f32>| {
+            if t >= 0.0 && best.is_none_or(|b| t < b.0) {
+                best = Some((t, normal));
+            }
+        };
+
+        let a = d_perp.dot(d_perp);
+        let b = 2.0 * o_perp.dot(d_perp);
+        let c = o_perp.dot(o_perp) - r2;
+        let discriminant = b * b - 4.0 * a * c;
+        if a > 0.0 && discriminant >= 0.0 {
+            let sqrt = discriminant.sqrt();
+            for &t in &[(-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a)] {
+                let h = o_along + t * d_along;
+                if self.height.is_none_or(|height| h >= 0.0 && h <= height) {
+                    consider(t, (o_perp + d_perp * t) / self.radius);
+                }
+            }
+        }
+
+        if let (true, Some(height)) = (self.capped, self.height) {
+            if d_along != 0.0 {
+                for &(h, normal) in &[(0.0, -self.axis), (height, self.axis)] {
+                    let t = (h - o_along) / d_along;
+                    let p = o_perp + d_perp * t;
+                    if p.dot(p) <= r2 {
+                        consider(t, normal);
+                    }
+                }
+            }
+        }
+
+        best.map(|(t, normal)| Hit { t: t, normal: normal, color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.height.map(|height| {
+            Aabb::around_disc(self.base, self.axis, self.radius)
+                .union(&Aabb::around_disc(self.base + self.axis * height, self.axis, self.radius))
+        })
+    }
+}
+
+/// Treats the cylinder as closed, whether or not its caps are drawn.
+impl Solid for Cylinder {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let o = ray.point - self.base;
+        let o_along = o.dot(self.axis);
+        let d_along = ray.direction.dot(self.axis);
+        let o_perp = o - self.axis * o_along;
+        let d_perp = ray.direction - self.axis * d_along;
+        let hit = |t: f32, normal: Vector3<f32>| Hit { t: t, normal: normal, color: self.color, material: None, front: true };
+        let side = |t: f32| hit(t, (o_perp + d_perp * t) / self.radius);
+
+        // the span inside the infinite tube
+        let a = d_perp.dot(d_perp);
+        let b = 2.0 * o_perp.dot(d_perp);
+        let c = o_perp.dot(o_perp) - self.radius * self.radius;
+        let (mut enter, mut exit) = if a == 0.0 {
+            // parallel to the axis: either always or never inside
+            if c > 0.0 { return vec![]; }
+            (hit(-f32::INFINITY, -self.axis), hit(f32::INFINITY, self.axis))
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 { return vec![]; }
+            let sqrt = discriminant.sqrt();
+            (side((-b - sqrt) / (2.0 * a)), side((-b + sqrt) / (2.0 * a)))
+        };
+
+        // clipped to the slab between the end planes
+        if let Some(height) = self.height {
+            if d_along == 0.0 {
+                if o_along < 0.0 || o_along > height { return vec![]; }
+            } else {
+                let t0 = -o_along / d_along;
+                let t1 = (height - o_along) / d_along;
+                let (near, far) = if t0 < t1 {
+                    (hit(t0, -self.axis), hit(t1, self.axis))
+                } else {
+                    (hit(t1, self.axis), hit(t0, -self.axis))
+                };
+                if near.t > enter.t { enter = near; }
+                if far.t < exit.t { exit = far; }
+            }
+        }
+
+        if enter.t >= exit.t || exit.t < 0.0 { return vec![]; }
+        vec![Span { enter: enter, exit: exit }]
+    }
+}
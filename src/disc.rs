@@ -0,0 +1,58 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::{Aabb, Bounded};
+use super::math;
+
+/// A flat disc, visible and lit from both sides.
+#[derive(Debug, PartialEq)]
+pub struct Disc {
+    pub center: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub radius: f32,
+    color: Vector3<f32>,
+}
+impl Disc {
+    pub fn new(center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: Vector3<f32>) -> Disc {
+        Disc {
+            center: center,
+            normal: normal.normalize(),
+            radius: radius,
+            color: color,
+        }
+    }
+}
+
+impl Intersect for Disc {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let denom = self.normal.dot(ray.direction);
+        if denom.abs() < EPSILON { return None; }
+
+        let t = (self.center - ray.point).dot(self.normal) / denom;
+        if t < 0.0 { return None; }
+
+        let offset = ray.point_at(t) - self.center;
+        if offset.length2() > self.radius * self.radius { return None; }
+
+        Some(Hit { t: t, normal: self.normal, color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        let (x, y) = math::concentric_disc(u, v);
+        let (tangent, bitangent) = math::orthonormal_basis(self.normal);
+        let point = self.center + (tangent * x + bitangent * y) * self.radius;
+        Some((point, self.normal, PI * self.radius * self.radius))
+    }
+}
+
+impl Bounded for Disc {
+    fn aabb(&self) -> Aabb {
+        Aabb::around_disc(self.center, self.normal, self.radius)
+    }
+}
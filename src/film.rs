@@ -0,0 +1,108 @@
+use cgmath::*;
+
+/// How a sample's color is spread over the pixels around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // every sample counts fully for the pixel it lands in, and only that
+    // pixel: a plain average
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3; sharper than the others, at the
+    // cost of slight ringing
+    Mitchell,
+}
+impl Filter {
+    /// How far from a pixel's center, in pixels, a sample still counts.
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// The weight of a sample `(dx, dy)` pixels away from a pixel's center.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let r = self.radius();
+        let x = d.abs();
+        if x > r {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x / r,
+            Filter::Gaussian => {
+                // shifted down so it reaches zero at the radius
+                let alpha = 2.0;
+                ((-alpha * x * x).exp() - (-alpha * r * r).exp()).max(0.0)
+            }
+            Filter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+        }
+    }
+}
+
+/// The image being rendered, as a weighted sum of the samples splatted
+/// around each pixel.
+#[derive(Debug)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    sums: Vec<Vector3<f32>>,
+    weights: Vec<f32>,
+}
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        let pixels = (width * height) as usize;
+        Film { width: width, height: height, filter: filter, sums: vec![Vector3::zero(); pixels], weights: vec![0.0; pixels] }
+    }
+
+    /// Adds a sample taken at `(x, y)`, in pixels from the image's top-left
+    /// corner, to every pixel within the filter's radius.
+    pub fn add(&mut self, x: f32, y: f32, color: Vector3<f32>) {
+        let r = self.filter.radius();
+        let (x_min, x_max) = self.span(x, r, self.width);
+        let (y_min, y_max) = self.span(y, r, self.height);
+        for py in y_min..y_max {
+            for px in x_min..x_max {
+                let weight = self.filter.weight(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if weight == 0.0 { continue; }
+                let i = (py * self.width + px) as usize;
+                self.sums[i] = self.sums[i] + color * weight;
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    /// The pixels a sample in the given pixel range can reach, as
+    /// `(x_min, y_min, x_max, y_max)`, exclusive at the top.
+    pub fn reach(&self, x_min: u32, y_min: u32, x_max: u32, y_max: u32) -> (u32, u32, u32, u32) {
+        let r = self.filter.radius().ceil() as u32;
+        (x_min.saturating_sub(r), y_min.saturating_sub(r), (x_max + r).min(self.width), (y_max + r).min(self.height))
+    }
+
+    pub fn color(&self, x: u32, y: u32) -> Vector3<f32> {
+        let i = (y * self.width + x) as usize;
+        if self.weights[i] > 0.0 { self.sums[i] / self.weights[i] } else { Vector3::zero() }
+    }
+
+    // The pixels along one axis whose centers are within `r` of `p`.
+    fn span(&self, p: f32, r: f32, size: u32) -> (u32, u32) {
+        let lo = (p - 0.5 - r).ceil().max(0.0) as u32;
+        let hi = ((p - 0.5 + r).floor() + 1.0).max(0.0).min(size as f32) as u32;
+        (lo, hi.max(lo))
+    }
+}
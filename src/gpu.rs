@@ -0,0 +1,222 @@
+use cgmath::*;
+use pollster;
+use wgpu;
+use wgpu::util::DeviceExt;
+
+use std::sync::mpsc;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The part of a scene the GPU renderer understands: spheres, planes, a
+/// single point light, and the camera. Scenes with anything else stay on the CPU.
+pub struct SceneData {
+    pub inverse_camera: Matrix4<f32>,
+    pub ambient: Vector3<f32>,
+    /// Where the point light is, and its color times its intensity.
+    pub light: Option<(Point3<f32>, Vector3<f32>)>,
+    pub spheres: Vec<(Point3<f32>, f32, Vector3<f32>)>,
+    pub planes: Vec<(Point3<f32>, Vector3<f32>, Vector3<f32>)>,
+}
+
+/// Traces primary and shadow rays in a compute shader, shading the same way
+/// `Scene::trace` does. Returns the image row by row, or why no GPU could be
+/// used.
+pub fn render(scene: &SceneData, width: u32, height: u32) -> Result<Vec<Vector3<f32>>, String> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .map_err(|e| e.to_string())?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default()))
+        .map_err(|e| e.to_string())?;
+
+    // storage buffers can't be empty, so unused ones get a single dummy entry
+    let mut spheres = vec![];
+    for &(center, radius, color) in &scene.spheres {
+        spheres.extend_from_slice(&[center.x, center.y, center.z, radius, color.x, color.y, color.z, 0.0]);
+    }
+    if spheres.is_empty() { spheres = vec![0.0; 8]; }
+    let mut planes = vec![];
+    for &(point, normal, color) in &scene.planes {
+        planes.extend_from_slice(&[point.x, point.y, point.z, 0.0, normal.x, normal.y, normal.z, 0.0, color.x, color.y, color.z, 0.0]);
+    }
+    if planes.is_empty() { planes = vec![0.0; 12]; }
+
+    let mut params: Vec<f32> = vec![];
+    let m: &[[f32; 4]; 4] = scene.inverse_camera.as_ref();
+    for column in m {
+        params.extend_from_slice(column);
+    }
+    let (light, radiance) = scene.light.unwrap_or((Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)));
+    params.extend_from_slice(&[light.x, light.y, light.z, if scene.light.is_some() { 1.0 } else { 0.0 }]);
+    params.extend_from_slice(&[radiance.x, radiance.y, radiance.z, 0.0]);
+    params.extend_from_slice(&[scene.ambient.x, scene.ambient.y, scene.ambient.z, 0.0]);
+    let counts = [width, height, scene.spheres.len() as u32, scene.planes.len() as u32];
+    params.extend(counts.iter().map(|&c| f32::from_bits(c)));
+
+    let storage = |label, data: &[f32], usage| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: &to_bytes(data),
+        usage: usage,
+    });
+    let params = storage("params", &params, wgpu::BufferUsages::UNIFORM);
+    let spheres = storage("spheres", &spheres, wgpu::BufferUsages::STORAGE);
+    let planes = storage("planes", &planes, wgpu::BufferUsages::STORAGE);
+    let size = (width * height) as u64 * 16;
+    let output = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("output"),
+        size: size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("trace"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("trace"),
+        layout: None,
+        module: &module,
+        entry_point: Some("main"),
+        compilation_options: Default::default(),
+        cache: None,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: spheres.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: planes.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: output.as_entire_binding() },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        let groups = |n: u32| n.div_ceil(WORKGROUP_SIZE);
+        pass.dispatch_workgroups(groups(width), groups(height), 1);
+    }
+    encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let (sender, receiver) = mpsc::channel();
+    readback.map_async(wgpu::MapMode::Read, .., move |result| { let _ = sender.send(result); });
+    device.poll(wgpu::PollType::Wait).map_err(|e| e.to_string())?;
+    receiver.recv().map_err(|e| e.to_string())?.map_err(|e| e.to_string())?;
+
+    let bytes = readback.get_mapped_range(..);
+    let floats: Vec<f32> = bytes.chunks(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(floats.chunks(4).map(|c| vec3(c[0], c[1], c[2])).collect())
+}
+
+fn to_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect()
+}
+
+const SHADER: &str = r#"
+struct Params {
+    inverse_camera: mat4x4<f32>,
+    light: vec4<f32>,
+    radiance: vec4<f32>,
+    ambient: vec4<f32>,
+    counts: vec4<u32>,
+}
+struct Sphere {
+    center_radius: vec4<f32>,
+    color: vec4<f32>,
+}
+struct Plane {
+    point: vec4<f32>,
+    normal: vec4<f32>,
+    color: vec4<f32>,
+}
+struct Hit {
+    t: f32,
+    normal: vec3<f32>,
+    color: vec3<f32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> spheres: array<Sphere>;
+@group(0) @binding(2) var<storage, read> planes: array<Plane>;
+@group(0) @binding(3) var<storage, read_write> output: array<vec4<f32>>;
+
+const EPSILON: f32 = 1e-4;
+const MISS: f32 = 3.4e38;
+
+fn closest(origin: vec3<f32>, direction: vec3<f32>) -> Hit {
+    var hit = Hit(MISS, vec3<f32>(0.0), vec3<f32>(0.0));
+    for (var i = 0u; i < params.counts.z; i++) {
+        let s = spheres[i];
+        let l = s.center_radius.xyz - origin;
+        let v = dot(l, direction);
+        let across = l - direction * v;
+        let d2 = dot(across, across);
+        let r2 = s.center_radius.w * s.center_radius.w;
+        let inner = max(s.center_radius.w - EPSILON / 2.0, 0.0);
+        let inside = dot(l, l) < inner * inner;
+        if ((v < 0.0 && !inside) || d2 > r2) { continue; }
+        let d = sqrt(r2 - d2);
+        let t = select(v - d, v + d, inside || v < d);
+        if (t < hit.t) {
+            hit = Hit(t, normalize(origin + direction * t - s.center_radius.xyz), s.color.xyz);
+        }
+    }
+    for (var i = 0u; i < params.counts.w; i++) {
+        let p = planes[i];
+        let denom = dot(p.normal.xyz, direction);
+        if (abs(denom) < EPSILON) { continue; }
+        let t = dot(p.point.xyz - origin, p.normal.xyz) / denom;
+        if (t < 0.0 || t >= hit.t) { continue; }
+        hit = Hit(t, p.normal.xyz, p.color.xyz);
+    }
+    return hit;
+}
+
+fn trace(origin: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    let ambient = params.ambient.xyz;
+    var hit = closest(origin, direction);
+    if (hit.t == MISS) { return ambient; }
+    // light the side of the surface the ray came from
+    if (dot(hit.normal, direction) > 0.0) { hit.normal = -hit.normal; }
+    if (params.light.w == 0.0) { return hit.color * ambient; }
+
+    let point = origin + direction * hit.t;
+    let to_light = params.light.xyz - point;
+    let light_direction = normalize(to_light);
+    let side = select(hit.normal, -hit.normal, dot(hit.normal, light_direction) < 0.0);
+    let blocker = closest(point + side * EPSILON, light_direction);
+    if (blocker.t < length(to_light)) { return hit.color * ambient; }
+
+    let lambert = max(dot(hit.normal, light_direction), 0.0);
+    let diffuse = vec3<f32>(0.5, 0.4, 0.5);
+    return hit.color * (ambient + params.radiance.xyz * diffuse * lambert);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let width = params.counts.x;
+    let height = params.counts.y;
+    if (id.x >= width || id.y >= height) { return; }
+
+    let screen = vec2<f32>(
+        2.0 * ((f32(id.x) + 0.5) / f32(width)) - 1.0,
+        -(2.0 * ((f32(id.y) + 0.5) / f32(height)) - 1.0));
+    let near = params.inverse_camera * vec4<f32>(screen, -1.0, 1.0);
+    let far = params.inverse_camera * vec4<f32>(screen, 1.0, 1.0);
+    let origin = near.xyz / near.w;
+    let direction = normalize(far.xyz / far.w - origin);
+    output[id.y * width + id.x] = vec4<f32>(trace(origin, direction), 1.0);
+}
+"#;
@@ -0,0 +1,44 @@
+use cgmath::*;
+
+use super::Intersect;
+use super::transform::Transformed;
+
+/// A node in a scene hierarchy. A node's transform is relative to its
+/// parent, so moving a node carries all of its children along with it.
+pub struct Node {
+    transform: Matrix4<f32>,
+    objects: Vec<Box<dyn Intersect>>,
+    children: Vec<Node>,
+}
+impl Node {
+    pub fn new(transform: Matrix4<f32>) -> Node {
+        Node {
+            transform: transform,
+            objects: vec![],
+            children: vec![],
+        }
+    }
+    pub fn add_object<T: Intersect + 'static>(&mut self, object: T) -> &mut Node {
+        self.objects.push(Box::new(object));
+        self
+    }
+    pub fn add_child(&mut self, child: Node) -> &mut Node {
+        self.children.push(child);
+        self
+    }
+
+    /// Resolves every object's world transform by composing the transforms
+    /// on the path from the root, and returns the objects placed in world
+    /// space.
+    pub fn flatten(self, parent: Matrix4<f32>) -> Vec<Box<dyn Intersect>> {
+        let world = parent * self.transform;
+        let mut flat: Vec<Box<dyn Intersect>> = vec![];
+        for object in self.objects {
+            flat.push(Box::new(Transformed::new(object, world)));
+        }
+        for child in self.children {
+            flat.extend(child.flatten(world));
+        }
+        flat
+    }
+}
@@ -0,0 +1,156 @@
+use super::Ray;
+use super::aabb::Aabb;
+use super::accelerator::Accelerator;
+
+// cells per primitive
+const DENSITY: f32 = 3.0;
+const MAX_RESOLUTION: usize = 128;
+
+/// A uniform grid over primitive bounds, walked cell by cell with a 3D-DDA.
+/// It is much cheaper to build than a tree, which makes it a good fit for
+/// scenes that change every frame, but it copes poorly with uneven scenes.
+#[derive(Debug)]
+pub struct Grid {
+    bounds: Option<Aabb>,
+    resolution: [usize; 3],
+    // primitives of cell `c` are `indices[offsets[c]..offsets[c + 1]]`
+    offsets: Vec<usize>,
+    indices: Vec<usize>,
+}
+impl Grid {
+    pub fn new(bounds: &[Aabb]) -> Grid {
+        let world = Aabb::from_points(bounds.iter().flat_map(|b| vec![b.min, b.max]));
+        let mut grid = Grid { bounds: world, resolution: [1, 1, 1], offsets: vec![0, 0], indices: vec![] };
+        let world = match world {
+            Some(world) => world,
+            None => return grid,
+        };
+
+        // roughly cubic cells, about DENSITY of them per primitive
+        let extent = world.max - world.min;
+        let longest = extent.x.max(extent.y).max(extent.z);
+        let cells_per_unit = (DENSITY * bounds.len() as f32).powf(1.0 / 3.0) / longest.max(1e-6);
+        for axis in 0..3 {
+            let n = (extent[axis] * cells_per_unit).round() as usize;
+            grid.resolution[axis] = n.clamp(1, MAX_RESOLUTION);
+        }
+
+        let mut cells: Vec<Vec<usize>> = vec![vec![]; grid.cell_count()];
+        for (i, b) in bounds.iter().enumerate() {
+            let lo = grid.cell_of(b.min[0], b.min[1], b.min[2]);
+            let hi = grid.cell_of(b.max[0], b.max[1], b.max[2]);
+            for z in lo[2]..hi[2] + 1 {
+                for y in lo[1]..hi[1] + 1 {
+                    for x in lo[0]..hi[0] + 1 {
+                        let c = grid.cell_index([x, y, z]);
+                        cells[c].push(i);
+                    }
+                }
+            }
+        }
+
+        grid.offsets = vec![0];
+        for cell in cells {
+            grid.indices.extend(cell);
+            grid.offsets.push(grid.indices.len());
+        }
+        grid
+    }
+
+    fn cell_count(&self) -> usize {
+        self.resolution[0] * self.resolution[1] * self.resolution[2]
+    }
+
+    fn cell_index(&self, cell: [usize; 3]) -> usize {
+        (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+    }
+
+    fn cell_of(&self, x: f32, y: f32, z: f32) -> [usize; 3] {
+        let world = self.bounds.unwrap();
+        let p = [x, y, z];
+        let mut cell = [0; 3];
+        for axis in 0..3 {
+            let extent = world.max[axis] - world.min[axis];
+            let f = if extent > 0.0 { (p[axis] - world.min[axis]) / extent } else { 0.0 };
+            let n = self.resolution[axis];
+            cell[axis] = ((f * n as f32) as isize).max(0).min(n as isize - 1) as usize;
+        }
+        cell
+    }
+
+    // Steps through the cells the ray crosses in order, calling `cell` with
+    // each cell's primitives and the distance at which the ray leaves it.
+    // Stops as soon as `cell` returns true.
+    fn walk<F: FnMut(&[usize], f32) -> bool>(&self, ray: &Ray, t_max: f32, mut cell: F) {
+        let world = match self.bounds {
+            Some(world) => world,
+            None => return,
+        };
+        let (t_enter, t_exit) = match world.slabs(ray) {
+            Some(range) => range,
+            None => return,
+        };
+        let t_start = t_enter.max(0.0);
+        let t_end = t_exit.min(t_max);
+        if t_start > t_end { return; }
+
+        let p = ray.point_at(t_start);
+        let mut current = self.cell_of(p.x, p.y, p.z);
+        let mut step = [0isize; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let size = (world.max[axis] - world.min[axis]) / self.resolution[axis] as f32;
+            let d = ray.direction[axis];
+            if d > 0.0 {
+                step[axis] = 1;
+                let boundary = world.min[axis] + (current[axis] + 1) as f32 * size;
+                t_next[axis] = (boundary - ray.point[axis]) / d;
+                t_delta[axis] = size / d;
+            } else if d < 0.0 {
+                step[axis] = -1;
+                let boundary = world.min[axis] + current[axis] as f32 * size;
+                t_next[axis] = (boundary - ray.point[axis]) / d;
+                t_delta[axis] = -size / d;
+            }
+        }
+
+        loop {
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] { 0 } else if t_next[1] < t_next[2] { 1 } else { 2 };
+            let c = self.cell_index(current);
+            let leave = t_next[axis].min(t_end);
+            if cell(&self.indices[self.offsets[c]..self.offsets[c + 1]], leave) { return; }
+            if t_next[axis] > t_end { return; }
+
+            let next = current[axis] as isize + step[axis];
+            if next < 0 || next >= self.resolution[axis] as isize { return; }
+            current[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+impl Accelerator for Grid {
+    fn closest(&self, ray: &Ray, t_max: f32, visit: &mut dyn FnMut(usize) -> Option<f32>) {
+        let mut best = t_max;
+        self.walk(ray, t_max, |primitives, t_leave| {
+            for &i in primitives {
+                if let Some(t) = visit(i) {
+                    best = best.min(t);
+                }
+            }
+            // a primitive can span several cells, so only a hit inside this
+            // cell rules out everything further along
+            best <= t_leave
+        });
+    }
+
+    fn any(&self, ray: &Ray, t_max: f32, test: &mut dyn FnMut(usize) -> bool) -> bool {
+        let mut found = false;
+        self.walk(ray, t_max, |primitives, _| {
+            found = primitives.iter().any(|&i| test(i));
+            found
+        });
+        found
+    }
+}
@@ -0,0 +1,134 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Hit, Ray, EPSILON};
+use super::aabb::Aabb;
+use super::material::Material;
+use super::rng::Rng;
+
+// cells along each axis of the guide's bounds
+const RESOLUTION: usize = 16;
+// bins over the cosine of a direction's angle to +z, and around it, which
+// makes every bin the same solid angle
+const HEIGHTS: usize = 16;
+const TURNS: usize = 16;
+const BINS: usize = HEIGHTS * TURNS;
+// how often a guided bounce follows the guide rather than the material
+const GUIDED: f32 = 0.5;
+// learned light is summed in fixed point, so threads adding to it in any
+// order get the same totals
+const FIXED: f32 = 65536.0;
+
+/// Where light comes from around the scene, learned from the paths of one
+/// pass to guide the bounces of the next: a grid over the scene holding a
+/// histogram of incoming light over the sphere of directions in each cell.
+/// Bounces follow the histogram half the time and the material the rest,
+/// weighed by both, so a poorly learned guide only ever costs noise.
+#[derive(Debug)]
+pub struct Guide {
+    bounds: Aabb,
+    // light learned so far, per cell and bin
+    learned: Vec<AtomicU64>,
+    // per cell, the running sum over its bins of what was learned up to the
+    // last pass, which bounces are guided by
+    sums: RwLock<Vec<f32>>,
+}
+impl Guide {
+    pub fn new(bounds: Aabb) -> Guide {
+        let cells = RESOLUTION * RESOLUTION * RESOLUTION;
+        Guide {
+            bounds: bounds,
+            learned: (0..cells * BINS).map(|_| AtomicU64::new(0)).collect(),
+            sums: RwLock::new(vec![0.0; cells * BINS]),
+        }
+    }
+
+    /// Learns from a bounced ray the light it brought back.
+    pub fn learn(&self, ray: &Ray, light: Vector3<f32>) {
+        let pdf = match ray.pdf {
+            Some(pdf) if pdf > 0.0 => pdf,
+            _ => return,
+        };
+        let brightness = (light.x + light.y + light.z) / (3.0 * pdf);
+        if !(brightness > 0.0) { return; }
+        let bin = self.cell(ray.point) * BINS + bin(ray.direction);
+        self.learned[bin].fetch_add((brightness * FIXED).min(1e15) as u64, Ordering::Relaxed);
+    }
+
+    /// Makes what has been learned so far guide bounces from now on.
+    pub fn refresh(&self) {
+        let mut sums = self.sums.write().unwrap();
+        for (cell, sums) in sums.chunks_mut(BINS).enumerate() {
+            let mut sum = 0.0;
+            for (i, s) in sums.iter_mut().enumerate() {
+                sum += self.learned[cell * BINS + i].load(Ordering::Relaxed) as f32 / FIXED;
+                *s = sum;
+            }
+        }
+    }
+
+    /// A bounce off the hit picked by the guide or by the material, with its
+    /// weight under both.
+    pub fn sample(&self, material: &dyn Material, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        let point = ray.point_at(hit.t);
+        let direction = {
+            let sums = self.sums.read().unwrap();
+            let sums = &sums[self.cell(point) * BINS..][..BINS];
+            if sums[BINS - 1] > 0.0 && rng.next_f32() < GUIDED {
+                let target = rng.next_f32() * sums[BINS - 1];
+                let bin = sums.iter().position(|&s| s > target).unwrap_or(BINS - 1);
+                Some(direction(bin, rng.next_f32(), rng.next_f32()))
+            } else {
+                None
+            }
+        };
+        let direction = match direction {
+            Some(direction) => direction,
+            None => material.sample(ray, hit, rng)?.0.direction,
+        };
+        let pdf = self.pdf(material, ray, hit, direction);
+        if !(pdf > 0.0) { return None; }
+        let weight = material.eval(ray, hit, direction) / pdf;
+        if weight == Vector3::zero() { return None; }
+        let point = point + hit.normal * EPSILON;
+        Some((Ray::new(point, direction).at_time(ray.time).in_media(ray.media.clone()).sampled(pdf), weight))
+    }
+
+    /// The density `sample` picks `direction` with.
+    pub fn pdf(&self, material: &dyn Material, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        let sums = self.sums.read().unwrap();
+        let sums = &sums[self.cell(ray.point_at(hit.t)) * BINS..][..BINS];
+        let total = sums[BINS - 1];
+        let own = material.pdf(ray, hit, direction);
+        if !(total > 0.0) { return own; }
+        let bin = bin(direction);
+        let guided = (sums[bin] - if bin > 0 { sums[bin - 1] } else { 0.0 }) / total * BINS as f32 / (4.0 * PI);
+        GUIDED * guided + (1.0 - GUIDED) * own
+    }
+
+    fn cell(&self, point: Point3<f32>) -> usize {
+        let extent = self.bounds.max - self.bounds.min;
+        let index = |axis: usize| {
+            let along = (point[axis] - self.bounds.min[axis]) / extent[axis].max(1e-3);
+            ((along * RESOLUTION as f32).max(0.0) as usize).min(RESOLUTION - 1)
+        };
+        (index(0) * RESOLUTION + index(1)) * RESOLUTION + index(2)
+    }
+}
+
+fn bin(direction: Vector3<f32>) -> usize {
+    let height = (((direction.z + 1.0) * 0.5 * HEIGHTS as f32) as usize).min(HEIGHTS - 1);
+    let turn = (direction.y.atan2(direction.x) / (2.0 * PI) + 0.5) * TURNS as f32;
+    height * TURNS + (turn as usize).min(TURNS - 1)
+}
+
+// A direction picked by `u` and `v` evenly within the bin.
+fn direction(bin: usize, u: f32, v: f32) -> Vector3<f32> {
+    let z = ((bin / TURNS) as f32 + u) / HEIGHTS as f32 * 2.0 - 1.0;
+    let phi = (((bin % TURNS) as f32 + v) / TURNS as f32 - 0.5) * 2.0 * PI;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
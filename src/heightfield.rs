@@ -0,0 +1,142 @@
+use cgmath::*;
+use image::{self, ImageResult};
+
+use std::path::Path;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::{Aabb, Bounded};
+use super::mesh::intersect_triangle;
+
+/// Terrain defined by a regular grid of elevations over the xy plane. Each
+/// grid cell is split into two triangles, and rays walk the cells they
+/// cross in order, so only a thin line of cells is tested per ray.
+pub struct Heightfield {
+    origin: Point3<f32>,
+    cell: Vector2<f32>,
+    columns: usize,
+    rows: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vector3<f32>>,
+    bounds: Aabb,
+    color: Vector3<f32>,
+}
+impl Heightfield {
+    /// `heights` holds `columns * rows` samples in `[0, 1]`, row by row,
+    /// stretched over `size.x` by `size.y` and scaled by `size.z`.
+    pub fn new(heights: Vec<f32>, columns: usize, rows: usize, origin: Point3<f32>, size: Vector3<f32>, color: Vector3<f32>) -> Heightfield {
+        assert!(columns >= 2 && rows >= 2, "heightfield needs at least 2x2 samples");
+        assert_eq!(heights.len(), columns * rows);
+
+        let cell = vec2(size.x / (columns - 1) as f32, size.y / (rows - 1) as f32);
+        let heights: Vec<f32> = heights.into_iter().map(|h| h * size.z).collect();
+        let (min, max) = heights.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+
+        let mut field = Heightfield {
+            origin: origin,
+            cell: cell,
+            columns: columns,
+            rows: rows,
+            heights: heights,
+            normals: vec![],
+            bounds: Aabb::new(origin + vec3(0.0, 0.0, min), origin + vec3(size.x, size.y, max)),
+            color: color,
+        };
+        field.normals = (0..rows).flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| field.gradient_normal(i, j))
+            .collect();
+        field
+    }
+
+    /// Loads a grayscale image as elevation data, one sample per pixel with
+    /// black at the bottom and white at `size.z`.
+    pub fn from_image<P: AsRef<Path>>(path: P, origin: Point3<f32>, size: Vector3<f32>, color: Vector3<f32>) -> ImageResult<Heightfield> {
+        let img = image::open(path)?.to_luma();
+        let (width, height) = img.dimensions();
+        let heights = img.pixels().map(|p| p.data[0] as f32 / 255.0).collect();
+        Ok(Heightfield::new(heights, width as usize, height as usize, origin, size, color))
+    }
+
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.columns + i]
+    }
+
+    fn point(&self, i: usize, j: usize) -> Point3<f32> {
+        self.origin + vec3(i as f32 * self.cell.x, j as f32 * self.cell.y, self.height(i, j))
+    }
+
+    fn gradient_normal(&self, i: usize, j: usize) -> Vector3<f32> {
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.columns - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.rows - 1));
+        let dx = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f32 * self.cell.x);
+        let dy = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f32 * self.cell.y);
+        vec3(-dx, -dy, 1.0).normalize()
+    }
+
+    fn intersect_cell(&self, ray: &Ray, i: usize, j: usize) -> Option<Hit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest: Option<Hit> = None;
+        for tri in &[[0, 1, 2], [0, 2, 3]] {
+            let (a, b, c) = (corners[tri[0]], corners[tri[1]], corners[tri[2]]);
+            match intersect_triangle(ray, self.point(a.0, a.1), self.point(b.0, b.1), self.point(c.0, c.1)) {
+                Some((t, u, v)) if closest.as_ref().is_none_or(|h| t < h.t) => {
+                    let n = |(i, j): (usize, usize)| self.normals[j * self.columns + i];
+                    let normal = (n(a) * (1.0 - u - v) + n(b) * u + n(c) * v).normalize();
+                    closest = Some(Hit { t: t, normal: normal, color: self.color, material: None, front: true });
+                }
+                _ => {}
+            }
+        }
+        closest
+    }
+}
+
+impl Intersect for Heightfield {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let (t_enter, t_exit) = self.bounds.slabs(ray)?;
+        let t_start = t_enter.max(0.0);
+        let start = ray.point_at(t_start) - self.origin;
+
+        // 2D DDA over the grid cells under the ray
+        let (max_i, max_j) = (self.columns as isize - 2, self.rows as isize - 2);
+        let clamp = |x: f32, max: isize| (x.floor() as isize).max(0).min(max);
+        let mut i = clamp(start.x / self.cell.x, max_i);
+        let mut j = clamp(start.y / self.cell.y, max_j);
+
+        let axis = |d: f32, cell: f32, index: isize, p: f32| -> (isize, f32, f32) {
+            if d > 0.0 {
+                (1, t_start + ((index + 1) as f32 * cell - p) / d, cell / d)
+            } else if d < 0.0 {
+                (-1, t_start + (index as f32 * cell - p) / d, -cell / d)
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (step_i, mut next_i, delta_i) = axis(ray.direction.x, self.cell.x, i, start.x);
+        let (step_j, mut next_j, delta_j) = axis(ray.direction.y, self.cell.y, j, start.y);
+
+        loop {
+            if let Some(hit) = self.intersect_cell(ray, i as usize, j as usize) {
+                return Some(hit);
+            }
+            if next_i.min(next_j) > t_exit { return None; }
+            if next_i < next_j {
+                i += step_i;
+                next_i += delta_i;
+            } else {
+                j += step_j;
+                next_j += delta_j;
+            }
+            if i < 0 || i > max_i || j < 0 || j > max_j { return None; }
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+}
+
+impl Bounded for Heightfield {
+    fn aabb(&self) -> Aabb {
+        self.bounds
+    }
+}
@@ -0,0 +1,62 @@
+use cgmath::*;
+
+use std::sync::Arc;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+use super::material::Material;
+use super::transform::Transformed;
+
+/// A placement of shared geometry with its own transform and color. Many
+/// instances can point at one mesh without copying it.
+pub struct Instance {
+    geometry: Transformed<Arc<dyn Intersect>>,
+    color: Vector3<f32>,
+    material: Option<Arc<dyn Material>>,
+}
+impl Instance {
+    pub fn new(geometry: Arc<dyn Intersect>, to_world: Matrix4<f32>, color: Vector3<f32>) -> Instance {
+        Instance {
+            geometry: Transformed::new(geometry, to_world),
+            color: color,
+            material: None,
+        }
+    }
+
+    /// Moves the instance; the shared geometry and its BVH are untouched.
+    pub fn set_transform(&mut self, to_world: Matrix4<f32>) {
+        self.geometry.set_transform(to_world);
+    }
+
+    /// Moves the instance over the shutter interval, ending at `to_world`.
+    pub fn set_motion(&mut self, to_world: Matrix4<f32>) {
+        self.geometry.set_motion(to_world);
+    }
+
+    /// Shades the instance with `material` rather than the geometry's own.
+    pub fn set_material(&mut self, material: Arc<dyn Material>) {
+        self.material = Some(material);
+    }
+}
+
+impl Intersect for Instance {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        self.geometry.intersect(ray).map(|hit| Hit {
+            color: self.color,
+            material: self.material.clone().or(hit.material),
+            ..hit
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.geometry.bounds()
+    }
+
+    fn emits(&self) -> bool {
+        self.material.as_ref().map_or_else(|| self.geometry.emits(), |m| m.emits())
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        self.geometry.sample_surface(u, v, time)
+    }
+}
@@ -0,0 +1,246 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+
+use super::{Hit, Intersect, Ray, Scene, Sphere, Target, EPSILON};
+use super::aabb::Aabb;
+use super::math;
+use super::microfacet::Frame;
+use super::rng::Rng;
+
+/// How the light reaching the camera is worked out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Whitted-style ray tracing: surfaces are lit by the lights and a flat
+    /// ambient term, and only mirrors and glass send rays further.
+    Whitted,
+    /// Monte Carlo path tracing: every surface also bounces a ray in a
+    /// direction picked by its material, and light reaches it from
+    /// everything else in the scene, with the ambient color as the sky.
+    /// Needs many samples per pixel to settle.
+    PathTracing,
+    /// Bidirectional path tracing: paths are followed from the camera and
+    /// from the glowing spheres and objects both, and joined at every pair
+    /// of their bounces, so light that only gets through a gap, or focuses
+    /// into caustics seen in something else, is still found. Light paths
+    /// are never joined to the camera itself, so a caustic seen head on is
+    /// only as clean as path tracing gets it, and scattering media are
+    /// passed through as if clear.
+    Bidirectional,
+    /// Ambient occlusion alone, in gray: how much of the sky each surface
+    /// sees, from `rays` rays around its normal that count as blocked by
+    /// anything closer than `distance`. Materials and lights are ignored.
+    AmbientOcclusion { distance: f32, rays: usize },
+    /// Whitted-style ray tracing, with the flat ambient term replaced by the
+    /// light bounced onto surfaces from everything around them, gathered
+    /// with `rays` rays at sparse points and blended in between. `error`
+    /// sets how far the points are spread: smaller is smoother and slower.
+    IrradianceCache { rays: usize, error: f32 },
+}
+
+/// The share of `rays` rays from a hit, spread by the cosine of their angle
+/// to the normal, that get `distance` away unblocked.
+pub fn occlusion(scene: &Scene, ray: &Ray, hit: &Hit, distance: f32, rays: usize) -> Vector3<f32> {
+    let mut rng = ray.rng();
+    let frame = Frame::around(hit.normal);
+    let point = ray.point_at(hit.t) + hit.normal * EPSILON;
+    let open = (0..rays).filter(|_| {
+        let direction = frame.to_world(math::cosine_hemisphere(rng.next_f32(), rng.next_f32()));
+        !scene.in_shadow(&Ray::new(point, direction).at_time(ray.time), distance)
+    }).count();
+    let open = open as f32 / rays.max(1) as f32;
+    vec3(open, open, open)
+}
+
+/// The light a hit gives off. When path tracing, light found by a bounced
+/// ray on something `emitter_light` could also have picked is weighed by how
+/// likely bouncing was to find it, so the two add up to it once.
+pub fn emission(scene: &Scene, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+    let emitted = scene.material_at(hit).emitted(ray, hit);
+    let pdf = match ray.pdf {
+        Some(pdf) if scene.integrator == Integrator::PathTracing && emitted != Vector3::zero() => pdf,
+        _ => return emitted,
+    };
+    // resampled light is left to `emitter_light` alone
+    if scene.reservoirs.is_some() {
+        return if emitter_at(scene, ray, hit).is_some() { Vector3::zero() } else { emitted };
+    }
+    let found = scene.light_tree.around(ray.point_at(hit.t)).into_iter()
+        .filter_map(|i| emitter_pdf(scene, scene.emitters[i], ray, hit).map(|light| (i, light)))
+        .next();
+    match found {
+        Some((i, light)) => emitted * power_heuristic(pdf, light * scene.light_tree.chance(ray.point, i)),
+        None => emitted,
+    }
+}
+
+/// Next-event estimation: when path tracing, the light reaching a hit
+/// straight from one of the glowing spheres or objects, picked by the light
+/// tree, along a shadow ray to a point on it. Weighed against the chance the
+/// material's own bounce had of finding the same light.
+pub fn emitter_light(scene: &Scene, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vector3<f32> {
+    if scene.integrator != Integrator::PathTracing || scene.emitters.is_empty() {
+        return Vector3::zero();
+    }
+    if let Some(ref reservoirs) = scene.reservoirs {
+        return reservoirs.light(scene, ray, hit, rng);
+    }
+    let point = ray.point_at(hit.t) + hit.normal * EPSILON;
+    let (emitter, chance) = match scene.light_tree.pick(point, rng.next_f32()) {
+        Some((i, chance)) => (scene.emitters[i], chance),
+        None => return Vector3::zero(),
+    };
+    let (direction, pdf) = match sample_emitter(scene, emitter, point, rng.next_f32(), rng.next_f32(), ray.time) {
+        Some(sample) => sample,
+        None => return Vector3::zero(),
+    };
+    let material = scene.material_at(hit);
+    let f = material.eval(ray, hit, direction);
+    if f == Vector3::zero() { return Vector3::zero(); }
+
+    // the emitter has to be the first thing in the way
+    let shadow = Ray::new(point, direction).at_time(ray.time);
+    let light = match scene.closest_hit(&shadow) {
+        Some(light) => light,
+        None => return Vector3::zero(),
+    };
+    if emitter_pdf(scene, emitter, &shadow, &light).is_none() { return Vector3::zero(); }
+    let emitted = scene.material_at(&light).emitted(&shadow, &light);
+    let pdf = pdf * chance;
+    f * emitted * (power_heuristic(pdf, scene.bounce_pdf(ray, hit, direction)) / pdf)
+}
+
+// A direction from `point` towards the emitter and the density, over solid
+// angle, it was picked with.
+fn sample_emitter(scene: &Scene, emitter: Target, point: Point3<f32>, u: f32, v: f32, time: f32) -> Option<(Vector3<f32>, f32)> {
+    match emitter {
+        Target::Sphere(i) => sample_sphere(point, scene.spheres.centers[i], scene.spheres.radii[i], u, v),
+        Target::Object(_) | Target::Instance(_) => {
+            let (on, normal, area) = shape(scene, emitter)?.sample_surface(u, v, time)?;
+            let to = on - point;
+            let distance2 = to.length2();
+            let direction = to / distance2.sqrt();
+            let cos = normal.dot(direction).abs();
+            if cos < 1e-6 { return None; }
+            Some((direction, distance2 / (cos * area)))
+        }
+    }
+}
+
+// What an emitter other than a sphere is.
+fn shape(scene: &Scene, emitter: Target) -> Option<&dyn Intersect> {
+    match emitter {
+        Target::Sphere(_) => None,
+        Target::Object(i) => Some(&*scene.objects[i]),
+        Target::Instance(i) => Some(&scene.instances[i]),
+    }
+}
+
+/// The emitter `emitter_light` can pick that the ray's hit is on.
+pub fn emitter_at(scene: &Scene, ray: &Ray, hit: &Hit) -> Option<Target> {
+    scene.light_tree.around(ray.point_at(hit.t)).into_iter()
+        .map(|i| scene.emitters[i])
+        .find(|&emitter| emitter_pdf(scene, emitter, ray, hit).is_some())
+}
+
+/// The emitter's bounds, and roughly how much light it gives off: how bright
+/// it glows at one point times its area.
+pub fn extent(scene: &Scene, emitter: Target) -> Option<(Aabb, f32)> {
+    let bounds = match emitter {
+        Target::Sphere(i) => Aabb::around_sphere(scene.spheres.centers[i], scene.spheres.radii[i]),
+        Target::Object(_) | Target::Instance(_) => shape(scene, emitter)?.bounds()?,
+    };
+    let (point, normal, area) = surface_point(scene, emitter, 0.5, 0.5, 0.0)?;
+    let glow = onto(scene, emitter, point, normal, 0.0).map_or(vec3(1.0, 1.0, 1.0), |(ray, hit)| {
+        scene.material_at(&hit).emitted(&ray, &hit)
+    });
+    Some((bounds, (glow.x + glow.y + glow.z) / 3.0 * area))
+}
+
+/// A point picked by `u` and `v` in the unit square, spread evenly over the
+/// emitter's surface where it is at `time`, with the normal there and the
+/// surface's area.
+pub fn surface_point(scene: &Scene, emitter: Target, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+    match emitter {
+        Target::Sphere(i) => {
+            let (center, radius) = (scene.spheres.centers[i], scene.spheres.radii[i]);
+            let normal = math::uniform_sphere(u, v);
+            Some((center + normal * radius, normal, 4.0 * PI * radius * radius))
+        }
+        Target::Object(_) | Target::Instance(_) => shape(scene, emitter)?.sample_surface(u, v, time),
+    }
+}
+
+// The density `sample_emitter` picks the ray's direction with, if what it hit
+// is on the emitter.
+fn emitter_pdf(scene: &Scene, emitter: Target, ray: &Ray, hit: &Hit) -> Option<f32> {
+    match emitter {
+        Target::Sphere(i) => {
+            let (center, radius) = (scene.spheres.centers[i], scene.spheres.radii[i]);
+            if !on_sphere(ray.point_at(hit.t), center, radius) { return None; }
+            Some(sphere_pdf(ray.point, center, radius))
+        }
+        Target::Object(_) | Target::Instance(_) => {
+            let object = shape(scene, emitter)?;
+            let own = object.intersect(ray)?;
+            if (own.t - hit.t).abs() > 1e-4 * hit.t.max(1.0) { return None; }
+            let (_, _, area) = object.sample_surface(0.5, 0.5, ray.time)?;
+            let cos = own.normal.dot(ray.direction).abs();
+            Some(own.t * own.t / (cos * area).max(1e-12))
+        }
+    }
+}
+
+/// A ray straight onto a point on an emitter from just off it at `time`, and
+/// what it hits there.
+pub fn onto(scene: &Scene, emitter: Target, point: Point3<f32>, normal: Vector3<f32>, time: f32) -> Option<(Ray, Hit)> {
+    let ray = Ray::new(point + normal * EPSILON, -normal).at_time(time);
+    let hit = match emitter {
+        Target::Sphere(i) => {
+            let spheres = &scene.spheres;
+            Sphere::new(spheres.centers[i], spheres.radii[i], spheres.colors[i]).intersect(&ray)
+                .map(|hit| Hit { material: spheres.materials[i].clone(), ..hit })
+        }
+        Target::Object(_) | Target::Instance(_) => shape(scene, emitter)?.intersect(&ray),
+    };
+    hit.map(|hit| {
+        let hit = hit.facing(&ray);
+        (ray, hit)
+    })
+}
+
+/// Veach's power heuristic: the weight of a sample drawn with density `a`
+/// that another strategy could have drawn with density `b`.
+pub fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 > 0.0 { a2 / (a2 + b2) } else { 0.0 }
+}
+
+/// A direction from `point` towards the sphere, spread evenly over the solid
+/// angle it fills, and the density it was picked with; none from inside.
+pub fn sample_sphere(point: Point3<f32>, center: Point3<f32>, radius: f32, u: f32, v: f32) -> Option<(Vector3<f32>, f32)> {
+    let to_center = center - point;
+    let (distance2, radius2) = (to_center.length2(), radius * radius);
+    if distance2 <= radius2 { return None; }
+    let cos_max = (1.0 - radius2 / distance2).sqrt();
+    // `1 - cos_max` without cancelling away for small, far spheres
+    let cap = radius2 / distance2 / (1.0 + cos_max);
+    let cos = 1.0 - u * cap;
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    let frame = Frame::around(to_center / distance2.sqrt());
+    let direction = frame.to_world(vec3(sin * phi.cos(), sin * phi.sin(), cos));
+    Some((direction, 1.0 / (2.0 * PI * cap)))
+}
+
+// The density `sample_sphere` has for every direction towards the sphere.
+fn sphere_pdf(point: Point3<f32>, center: Point3<f32>, radius: f32) -> f32 {
+    let (distance2, radius2) = ((center - point).length2(), radius * radius);
+    if distance2 <= radius2 { return 0.0; }
+    let cos_max = (1.0 - radius2 / distance2).sqrt();
+    1.0 / (2.0 * PI * radius2 / distance2 / (1.0 + cos_max))
+}
+
+fn on_sphere(point: Point3<f32>, center: Point3<f32>, radius: f32) -> bool {
+    ((point - center).length() - radius).abs() <= 1e-3 * radius.max(1.0)
+}
@@ -0,0 +1,162 @@
+use cgmath::*;
+
+use std::collections::HashMap;
+
+use super::{Hit, Ray, Scene, EPSILON};
+use super::light;
+use super::math;
+use super::microfacet::Frame;
+use super::rng::Rng;
+use super::settings::RenderSettings;
+
+// pixels between the camera rays that seed the cache, along each axis
+const STRIDE: u32 = 4;
+// how close together and how far apart records may be, as fractions of
+// their distance to the camera
+const MIN_SPACING: f32 = 0.01;
+const MAX_SPACING: f32 = 0.5;
+
+// The light arriving at a point from around its normal.
+#[derive(Debug, Clone)]
+struct Record {
+    point: Point3<f32>,
+    normal: Vector3<f32>,
+    // the harmonic mean distance to what the point's rays hit, which is
+    // how fast its light is likely to change away from it
+    spacing: f32,
+    irradiance: Vector3<f32>,
+}
+
+/// Ward's irradiance cache: the light surfaces get from everything around
+/// them is gathered at sparse points, each with many rays, and blended
+/// between nearby ones wherever it is needed. Records are kept in a hashed
+/// octree, each at the level whose cells are about as big as its reach.
+#[derive(Debug, Default)]
+pub struct IrradianceCache {
+    rays: usize,
+    error: f32,
+    records: Vec<Record>,
+    cells: HashMap<(i32, i32, i32, i32), Vec<usize>>,
+    levels: Vec<i32>,
+}
+impl IrradianceCache {
+    /// Seeds a cache for the scene from camera rays spread over the image,
+    /// and from the rays mirrors and glass bounce them along. `error` is
+    /// how far apart records may be, relative to their spacing: smaller is
+    /// slower and smoother.
+    pub fn new(scene: &Scene, settings: &RenderSettings, rays: usize, error: f32) -> IrradianceCache {
+        let mut cache = IrradianceCache { rays: rays, error: error, ..IrradianceCache::default() };
+        let (width, height) = (settings.width as f32, settings.height as f32);
+        for y in (0..settings.height).step_by(STRIDE as usize) {
+            for x in (0..settings.width).step_by(STRIDE as usize) {
+                let (sx, sy) = (x as f32 + 0.5, y as f32 + 0.5);
+                if let Some(ray) = scene.camera.ray(sx / width, sy / height, (0.5, 0.5), 0.0) {
+                    cache.seed(scene, &ray, settings.max_depth);
+                }
+            }
+        }
+        cache
+    }
+
+    /// The light arriving at a hit from around its normal, blended from the
+    /// records near it, or gathered there and then if none are.
+    pub fn irradiance(&self, scene: &Scene, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+        let point = ray.point_at(hit.t);
+        match self.lookup(point, hit.normal) {
+            Some(irradiance) => irradiance,
+            None => gather(scene, ray, hit, self.rays, &mut ray.rng()).irradiance,
+        }
+    }
+
+    fn seed(&mut self, scene: &Scene, ray: &Ray, depth: usize) {
+        let hit = match scene.closest_hit(ray) {
+            Some(hit) => hit,
+            None => return,
+        };
+        let mut rng = ray.rng();
+        if self.lookup(ray.point_at(hit.t), hit.normal).is_none() {
+            let record = gather(scene, ray, &hit, self.rays, &mut rng);
+            self.insert(record);
+        }
+        if depth > 0 {
+            for (bounced, _) in scene.material_at(&hit).scatter(ray, &hit, &mut rng) {
+                self.seed(scene, &bounced, depth - 1);
+            }
+        }
+    }
+
+    fn insert(&mut self, record: Record) {
+        let reach = self.error * record.spacing;
+        let level = reach.log2().ceil() as i32;
+        let size = (level as f32).exp2();
+        let cell = |v: f32| (v / size).floor() as i32;
+        let (low, high) = (record.point + vec3(-reach, -reach, -reach), record.point + vec3(reach, reach, reach));
+        let index = self.records.len();
+        for x in cell(low.x)..cell(high.x) + 1 {
+            for y in cell(low.y)..cell(high.y) + 1 {
+                for z in cell(low.z)..cell(high.z) + 1 {
+                    self.cells.entry((level, x, y, z)).or_default().push(index);
+                }
+            }
+        }
+        if !self.levels.contains(&level) { self.levels.push(level); }
+        self.records.push(record);
+    }
+
+    // Ward's weighted blend of the records that reach the point, if any do.
+    fn lookup(&self, point: Point3<f32>, normal: Vector3<f32>) -> Option<Vector3<f32>> {
+        let (mut sum, mut total) = (Vector3::zero(), 0.0);
+        for &level in &self.levels {
+            let size = (level as f32).exp2();
+            let key = (level, (point.x / size).floor() as i32, (point.y / size).floor() as i32, (point.z / size).floor() as i32);
+            for &i in self.cells.get(&key).map_or(&[][..], |cell| &cell[..]) {
+                let record = &self.records[i];
+                let offset = point - record.point;
+                // records in front of the point see light it can't
+                if offset.dot((normal + record.normal) * 0.5) < -0.05 * record.spacing { continue; }
+                let deviation = offset.length() / record.spacing + (1.0 - normal.dot(record.normal)).max(0.0).sqrt();
+                if deviation * self.error >= 1.0 { continue; }
+                let weight = 1.0 / deviation.max(1e-6);
+                sum = sum + record.irradiance * weight;
+                total += weight;
+            }
+        }
+        if total > 0.0 { Some(sum / total) } else { None }
+    }
+}
+
+// The mean light `rays` rays spread by cosine around the hit's normal find,
+// which is what a white diffuse surface there reflects.
+fn gather(scene: &Scene, ray: &Ray, hit: &Hit, rays: usize, rng: &mut Rng) -> Record {
+    let point = ray.point_at(hit.t);
+    let frame = Frame::around(hit.normal);
+    let origin = point + hit.normal * EPSILON;
+    let rays = rays.max(1);
+    // jittered over a grid of strata
+    let columns = (rays as f32).sqrt().ceil() as usize;
+    let rows = rays.div_ceil(columns);
+    let (mut sum, mut inverse_distances) = (Vector3::zero(), 0.0);
+    for i in 0..rays {
+        let u = ((i % columns) as f32 + rng.next_f32()) / columns as f32;
+        let v = ((i / columns) as f32 + rng.next_f32()) / rows as f32;
+        let gathered = Ray::new(origin, frame.to_world(math::cosine_hemisphere(u, v))).at_time(ray.time);
+        match scene.closest_hit(&gathered) {
+            Some(found) => {
+                sum = sum + radiance(scene, &gathered, &found);
+                inverse_distances += 1.0 / found.t.max(EPSILON);
+            }
+            None => sum = sum + scene.ambient,
+        }
+    }
+    let scale = (point - scene.camera.eye).length();
+    let spacing = (rays as f32 / inverse_distances).min(MAX_SPACING * scale).max(MIN_SPACING * scale);
+    Record { point: point, normal: hit.normal, spacing: spacing, irradiance: sum / rays as f32 }
+}
+
+// The light leaving a hit towards the ray, lit directly and by the flat
+// ambient term alone.
+fn radiance(scene: &Scene, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+    let material = scene.material_at(hit);
+    let lit = light::shade(material, ray, hit, &scene.unshadowed(ray, hit), scene.ambient);
+    lit + material.emitted(ray, hit)
+}
@@ -0,0 +1,146 @@
+use std::cmp::Ordering;
+
+use super::Ray;
+use super::aabb::Aabb;
+use super::accelerator::Accelerator;
+
+const LEAF_SIZE: usize = 2;
+
+#[derive(Debug)]
+enum Node {
+    Leaf { start: usize, count: usize },
+    // the left child always directly follows its parent
+    Split { axis: usize, position: f32, right: usize },
+}
+
+/// A kd-tree over primitive bounds. Unlike a BVH it partitions space rather
+/// than primitives, so a primitive that straddles a split plane is listed on
+/// both sides, and leaves are visited strictly front to back.
+#[derive(Debug)]
+pub struct KdTree {
+    bounds: Option<Aabb>,
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+impl KdTree {
+    pub fn new(bounds: &[Aabb]) -> KdTree {
+        let mut tree = KdTree {
+            bounds: Aabb::from_points(bounds.iter().flat_map(|b| vec![b.min, b.max])),
+            nodes: vec![],
+            indices: vec![],
+        };
+        if let Some(root) = tree.bounds {
+            let all = (0..bounds.len()).collect();
+            let max_depth = 8 + (1.3 * (bounds.len() as f32).log2()) as usize;
+            tree.build(bounds, all, root, max_depth);
+        }
+        tree
+    }
+
+    fn build(&mut self, bounds: &[Aabb], primitives: Vec<usize>, node_bounds: Aabb, depth: usize) {
+        let index = self.nodes.len();
+        if primitives.len() <= LEAF_SIZE || depth == 0 {
+            self.push_leaf(primitives);
+            return;
+        }
+
+        // split at the median centroid along the longest side of the node
+        let extent = node_bounds.max - node_bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let mut centers: Vec<f32> = primitives.iter().map(|&i| bounds[i].center()[axis]).collect();
+        centers.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let position = centers[centers.len() / 2];
+        if position <= node_bounds.min[axis] || position >= node_bounds.max[axis] {
+            self.push_leaf(primitives);
+            return;
+        }
+
+        let left: Vec<usize> = primitives.iter().cloned().filter(|&i| bounds[i].min[axis] <= position).collect();
+        let right: Vec<usize> = primitives.iter().cloned().filter(|&i| bounds[i].max[axis] >= position).collect();
+        if left.len() == primitives.len() && right.len() == primitives.len() {
+            // every primitive straddles the plane, so splitting gains nothing
+            self.push_leaf(primitives);
+            return;
+        }
+
+        let mut left_bounds = node_bounds;
+        let mut right_bounds = node_bounds;
+        left_bounds.max[axis] = position;
+        right_bounds.min[axis] = position;
+
+        self.nodes.push(Node::Split { axis: axis, position: position, right: 0 });
+        self.build(bounds, left, left_bounds, depth - 1);
+        let right_index = self.nodes.len();
+        self.build(bounds, right, right_bounds, depth - 1);
+        if let Node::Split { ref mut right, .. } = self.nodes[index] {
+            *right = right_index;
+        }
+    }
+
+    fn push_leaf(&mut self, primitives: Vec<usize>) {
+        self.nodes.push(Node::Leaf { start: self.indices.len(), count: primitives.len() });
+        self.indices.extend(primitives);
+    }
+
+    // Walks the leaves the ray passes through in order, calling `leaf` with
+    // each leaf's primitives and the far end of the ray's stretch inside it.
+    // Stops as soon as `leaf` returns true.
+    fn walk<F: FnMut(&[usize], f32) -> bool>(&self, ray: &Ray, t_max: f32, mut leaf: F) {
+        let (t_enter, t_exit) = match self.bounds.and_then(|b| b.slabs(ray)) {
+            Some(range) => range,
+            None => return,
+        };
+        let mut stack = vec![(0, t_enter.max(0.0), t_exit.min(t_max))];
+        while let Some((mut index, t_min, mut t_far)) = stack.pop() {
+            if t_min > t_far { continue; }
+            loop {
+                match self.nodes[index] {
+                    Node::Leaf { start, count } => {
+                        if leaf(&self.indices[start..start + count], t_far) { return; }
+                        break;
+                    }
+                    Node::Split { axis, position, right } => {
+                        let origin = ray.point[axis];
+                        let direction = ray.direction[axis];
+                        let left_first = origin < position || (origin == position && direction <= 0.0);
+                        let (near, far) = if left_first { (index + 1, right) } else { (right, index + 1) };
+                        let t_split = (position - origin) / direction;
+                        if t_split > t_far || t_split <= 0.0 {
+                            index = near;
+                        } else if t_split < t_min {
+                            index = far;
+                        } else {
+                            stack.push((far, t_split, t_far));
+                            index = near;
+                            t_far = t_split;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Accelerator for KdTree {
+    fn closest(&self, ray: &Ray, t_max: f32, visit: &mut dyn FnMut(usize) -> Option<f32>) {
+        let mut best = t_max;
+        self.walk(ray, t_max, |primitives, t_far| {
+            for &i in primitives {
+                if let Some(t) = visit(i) {
+                    best = best.min(t);
+                }
+            }
+            // nothing in later leaves can beat a hit inside this one
+            best <= t_far
+        });
+    }
+
+    fn any(&self, ray: &Ray, t_max: f32, test: &mut dyn FnMut(usize) -> bool) -> bool {
+        let mut found = false;
+        self.walk(ray, t_max, |primitives, _| {
+            found = primitives.iter().any(|&i| test(i));
+            found
+        });
+        found
+    }
+}
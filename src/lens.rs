@@ -0,0 +1,37 @@
+/// Brown-Conrady lens distortion: radial terms `k1`..`k3` and tangential
+/// terms `p1`, `p2`, as calibration tools like OpenCV report them, acting
+/// on coordinates normalized by the focal length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distortion {
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    pub p1: f32,
+    pub p2: f32,
+}
+impl Distortion {
+    pub fn radial(k1: f32, k2: f32, k3: f32) -> Distortion {
+        Distortion { k1: k1, k2: k2, k3: k3, p1: 0.0, p2: 0.0 }
+    }
+
+    /// Where a point lands once distorted.
+    pub fn distort(&self, x: f32, y: f32) -> (f32, f32) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        (x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+         y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y)
+    }
+
+    /// The point that distorts to `(x, y)`. The model has no closed-form
+    /// inverse, so it is found by fixed-point iteration, which converges
+    /// quickly for the mild distortion of real lenses.
+    pub fn undistort(&self, x: f32, y: f32) -> (f32, f32) {
+        let (mut u, mut v) = (x, y);
+        for _ in 0..20 {
+            let (dx, dy) = self.distort(u, v);
+            u += x - dx;
+            v += y - dy;
+        }
+        (u, v)
+    }
+}
@@ -0,0 +1,1593 @@
+// structs are built spelling out every field, even from a same-named variable
+#![allow(clippy::redundant_field_names)]
+// `!(x > 0.0)` turns away NaN as well
+#![allow(clippy::neg_cmp_op_on_partial_ord)]
+
+extern crate cgmath;
+extern crate image;
+#[cfg(feature = "gpu")]
+extern crate pollster;
+extern crate rayon;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+extern crate wide;
+
+pub mod aabb;
+pub mod accelerator;
+pub mod bench;
+pub mod bezier;
+mod bidirectional;
+mod bvh;
+pub mod cache;
+pub mod cone;
+pub mod csg;
+pub mod cube_map;
+pub mod curves;
+pub mod cylinder;
+pub mod disc;
+pub mod film;
+#[cfg(feature = "gpu")]
+mod gpu;
+pub mod graph;
+mod guiding;
+mod grid;
+pub mod heightfield;
+pub mod instance;
+pub mod integrator;
+mod irradiance;
+mod kdtree;
+pub mod lens;
+pub mod light;
+mod light_tree;
+pub mod material;
+pub mod math;
+pub mod mesh;
+pub mod metaballs;
+pub mod microfacet;
+pub mod obj;
+pub mod point_cloud;
+pub mod quad;
+mod restir;
+mod rng;
+pub mod sampler;
+pub mod sdf;
+pub mod settings;
+mod sphere_set;
+pub mod stereo;
+pub mod subdivision;
+mod tiles;
+pub mod torus;
+pub mod transform;
+mod wavefront;
+
+use cgmath::*;
+use image::*;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::{atomic, mpsc, Arc};
+use std::sync::atomic::AtomicUsize;
+
+use aabb::{Aabb, Bounded, Cuboid};
+use cache::Cache;
+use accelerator::{Accelerator, AcceleratorKind};
+use cone::Cone;
+use csg::{Solid, Span};
+use cylinder::Cylinder;
+use disc::Disc;
+use film::Film;
+use graph::Node;
+use guiding::Guide;
+use heightfield::Heightfield;
+use instance::Instance;
+use integrator::Integrator;
+use irradiance::IrradianceCache;
+use lens::Distortion;
+use light::Light;
+use light_tree::LightTree;
+use material::{Clay, Lambert, Material, Media, Shared, WithMaterial};
+use mesh::Mesh;
+use quad::Quad;
+use restir::Reservoirs;
+use rng::Rng;
+use sdf::{Sdf, SdfObject};
+use sampler::{Estimate, Sampler};
+use settings::RenderSettings;
+use sphere_set::{SphereSet, Spheres};
+use tiles::Tile;
+use torus::Torus;
+use transform::Transformed;
+
+#[derive(Debug, Clone)]
+pub struct Ray {
+    point: Point3<f32>,
+    direction: Vector3<f32>,
+    // when in the shutter interval the ray is cast, from 0 as it opens to 1
+    // as it closes
+    time: f32,
+    // what transparent objects the ray is inside of
+    media: Media,
+    // for rays a path tracer bounced at random, the probability density of
+    // their direction, to weigh what they find against sampling lights
+    pdf: Option<f32>,
+    // for camera rays, the pixel they were cast for
+    pixel: Option<(u32, u32)>,
+    // how many times the path has bounced of each kind so far, when the
+    // render limits them
+    bounced: [usize; 3],
+    // the render seed and sample the path was cast for, mixed together, so
+    // different seeds and samples along the same ray draw different numbers
+    path: u64,
+}
+impl Ray {
+    fn new(point: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            point: point,
+            direction: direction.normalize(),
+            time: 0.0,
+            media: Media::default(),
+            pdf: None,
+            pixel: None,
+            bounced: [0; 3],
+            path: 0,
+        }
+    }
+
+    fn at_time(self, time: f32) -> Ray {
+        Ray { time: time, ..self }
+    }
+
+    fn in_media(self, media: Media) -> Ray {
+        Ray { media: media, ..self }
+    }
+
+    fn sampled(self, pdf: f32) -> Ray {
+        Ray { pdf: Some(pdf), ..self }
+    }
+
+    fn for_pixel(self, x: u32, y: u32) -> Ray {
+        Ray { pixel: Some((x, y)), ..self }
+    }
+
+    fn for_sample(self, seed: u64, sample: usize) -> Ray {
+        Ray { path: Rng::stream(seed, sample as u64).next_u64(), ..self }
+    }
+
+    fn point_at(&self, t: f32) -> Point3<f32> {
+        self.point + (self.direction * t)
+    }
+
+    // Random numbers for whatever happens to the ray. They follow from the
+    // ray itself and the path it is on, so each bounce of each sample gets
+    // its own however it is traced.
+    fn rng(&self) -> Rng {
+        let bits = |a: f32, b: f32| ((a.to_bits() as u64) << 32) | b.to_bits() as u64;
+        let seed = bits(self.point.x, self.point.y) ^ bits(self.point.z, self.time).rotate_left(16) ^ self.path;
+        Rng::stream(seed, bits(self.direction.x, self.direction.y) ^ (self.direction.z.to_bits() as u64).rotate_left(48))
+    }
+}
+
+// The kinds of bounce a render can limit a path's depth in separately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounce {
+    Diffuse,
+    Glossy,
+    Transmission,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sphere {
+    center: Point3<f32>,
+    radius: f32,
+    color: Vector3<f32>,
+}
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32, color: Vector3<f32>) -> Sphere {
+        Sphere {
+            center: center,
+            radius: radius,
+            color: color,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Plane {
+    point: Point3<f32>,
+    normal: Vector3<f32>,
+    color: Vector3<f32>,
+}
+impl Plane {
+    pub fn new(point: Point3<f32>, normal: Vector3<f32>, color: Vector3<f32>) -> Plane {
+        Plane {
+            point: point,
+            normal: normal.normalize(),
+            color: color,
+        }
+    }
+}
+
+// Offset applied to secondary ray origins so a surface doesn't shadow itself.
+const EPSILON: f32 = 1e-4;
+
+// How many times light may be scattered inside a translucent object before
+// it is given up on as absorbed.
+const MAX_WALK: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    t: f32,
+    // shapes give the normal on the outside of the surface; the scene turns
+    // it to face the ray before anything is shaded
+    normal: Vector3<f32>,
+    color: Vector3<f32>,
+    // `None` for the scene's default material
+    material: Option<Arc<dyn Material>>,
+    // whether the ray hit the outside of the surface
+    front: bool,
+}
+impl Hit {
+    // The hit with its normal turned to face the ray, so the side the ray
+    // came from is lit.
+    fn facing(self, ray: &Ray) -> Hit {
+        let front = self.normal.dot(ray.direction) < 0.0;
+        Hit { normal: if front { self.normal } else { -self.normal }, front: front, ..self }
+    }
+}
+
+// Send + Sync so a scene can be traced from many threads at once.
+pub trait Intersect: Send + Sync {
+    fn intersect(&self, ray: &Ray) -> Option<Hit>;
+
+    /// World space bounds, or `None` for unbounded objects like planes.
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Whether the object has a material of its own that gives off light.
+    fn emits(&self) -> bool {
+        false
+    }
+
+    /// A point picked by `u` and `v` in the unit square, spread evenly over
+    /// the surface, with the normal there and the surface's area, for shapes
+    /// a path tracer can aim at when they glow. Objects that move are where
+    /// they are at `time` in the shutter interval.
+    fn sample_surface(&self, _u: f32, _v: f32, _time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        None
+    }
+}
+
+impl<T: Intersect + ?Sized> Intersect for Arc<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        (**self).intersect(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+
+    fn emits(&self) -> bool {
+        (**self).emits()
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        (**self).sample_surface(u, v, time)
+    }
+}
+
+impl<T: Intersect + ?Sized> Intersect for Box<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        (**self).intersect(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+
+    fn emits(&self) -> bool {
+        (**self).emits()
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        (**self).sample_surface(u, v, time)
+    }
+}
+
+impl Sphere {
+    // Where along the ray its closest approach to the center is, and how
+    // far either side of that the ray is inside the sphere, if it meets it.
+    fn chord(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let l = self.center - ray.point;
+        let v = l.dot(ray.direction);
+        let r2 = self.radius * self.radius;
+        // from the part of `l` across the ray rather than `l.dot(l) - v * v`,
+        // which loses most of its precision far from the ray's origin
+        let across = l - ray.direction * v;
+        let d2 = across.dot(across);
+        if d2 > r2 { return None; }
+        Some((v, (r2 - d2).sqrt()))
+    }
+}
+
+impl Intersect for Sphere {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let l = self.center - ray.point;
+        let inside = l.dot(l) < inner_radius2(self.radius);
+        if l.dot(ray.direction) < 0.0 && !inside { return None; }
+        let (v, d) = self.chord(ray)?;
+
+        // the near side, or the far one from inside or from right on the
+        // surface heading in
+        let t = if inside || v < d { v + d } else { v - d };
+        Some(Hit {
+            t: t,
+            normal: (ray.point_at(t) - self.center).normalize(),
+            color: self.color,
+            material: None,
+            front: true,
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+}
+
+// How far inside a sphere, squared, a ray has to start to count as starting
+// inside it. Ones starting within half of EPSILON of the surface are outside,
+// so rays leaving the surface don't hit it again.
+fn inner_radius2(radius: f32) -> f32 {
+    let r = (radius - EPSILON / 2.0).max(0.0);
+    r * r
+}
+
+impl Bounded for Sphere {
+    fn aabb(&self) -> Aabb {
+        Aabb::around_sphere(self.center, self.radius)
+    }
+}
+
+impl Solid for Sphere {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let (v, d) = match self.chord(ray) {
+            Some((v, d)) if v + d >= 0.0 => (v, d),
+            _ => return vec![],
+        };
+        let hit = |t: f32| Hit {
+            t: t,
+            normal: (ray.point_at(t) - self.center) / self.radius,
+            color: self.color,
+            material: None,
+            front: true,
+        };
+        vec![Span { enter: hit(v - d), exit: hit(v + d) }]
+    }
+}
+
+impl Intersect for Plane {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let denom = self.normal.dot(ray.direction);
+        if denom.abs() < EPSILON { return None; }
+
+        let t = (self.point - ray.point).dot(self.normal) / denom;
+        if t < 0.0 { return None; }
+
+        Some(Hit { t: t, normal: self.normal, color: self.color, material: None, front: true })
+    }
+}
+
+/// How a camera maps the image onto rays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // parallel rays through a view this many units high
+    Orthographic(f32),
+    // fisheyes with this field of view in radians across the image
+    // circle, which may be more than half a turn; equidistant ones keep
+    // angles from the center evenly spaced, equisolid ones areas
+    FisheyeEquidistant(f32),
+    FisheyeEquisolid(f32),
+    // the whole sphere of directions, longitude across the image and
+    // latitude down it, for 360 degree viewers; the view direction is at
+    // the center and the up vector is the pole. Meant for 2:1 images.
+    Equirectangular,
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    eye: Point3<f32>,
+    center: Point3<f32>,
+    up: Vector3<f32>,
+    // radians turned counter-clockwise about the view direction
+    roll: f32,
+    near: f32,
+    far: f32,
+    fovy: f32,
+    // a horizontal field of view to keep instead, which sets `fovy` from
+    // the aspect ratio
+    fovx: Option<f32>,
+    aspect_ratio: f32,
+    // set by hand rather than taken from the image being rendered
+    fixed_aspect: bool,
+    projection: Projection,
+    // the name of something to keep in focus, measured at each render
+    focus_target: Option<String>,
+    // bends perspective rays the way a real lens would, to match footage
+    distortion: Option<Distortion>,
+    // lens shift, moving the image across and up in fractions of its half
+    // width and height without turning the camera
+    shift: (f32, f32),
+    // lens tilt, turning the plane of focus this many radians about the
+    // image's horizontal and vertical axes
+    tilt: (f32, f32),
+    // radius of the thin lens; zero is a pinhole, with everything in focus
+    aperture: f32,
+    // how far in front of the lens things are sharp
+    focus_distance: f32,
+    // an aperture of this many blades, rotated this far, instead of a disc
+    blades: Option<(usize, f32)>,
+    // where the eye and center are when the shutter closes, if the camera
+    // moves
+    motion: Option<(Point3<f32>, Point3<f32>)>,
+    // inverse of `transform`, kept up to date by the setters so generating
+    // a ray doesn't invert a matrix
+    to_world: Matrix4<f32>,
+}
+impl Camera {
+    pub fn new(eye: Point3<f32>, center: Point3<f32>) -> Camera {
+        let mut camera = Camera {
+            eye: eye,
+            center: center,
+            up: Vector3::new(0.0, 0.0, 1.0),
+            roll: 0.0,
+            near: 0.1,
+            far: 10.0,
+            fovy: 1.0,
+            fovx: None,
+            aspect_ratio: 1.0,
+            fixed_aspect: false,
+            projection: Projection::Perspective,
+            focus_target: None,
+            distortion: None,
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            aperture: 0.0,
+            focus_distance: (center - eye).length(),
+            blades: None,
+            motion: None,
+            to_world: Matrix4::identity(),
+        };
+        camera.update();
+        camera
+    }
+    /// A camera placed by a view-to-world matrix as DCC tools and glTF
+    /// export them: looking down its local -z with +y up. Scale in the
+    /// matrix is ignored.
+    pub fn from_matrix(view_to_world: Matrix4<f32>, fovy: f32, aspect_ratio: f32, near: f32, far: f32) -> Camera {
+        let eye = transform::transform_point(&view_to_world, Point3::new(0.0, 0.0, 0.0));
+        let forward = transform::transform_vector(&view_to_world, -Vector3::unit_z()).normalize();
+        let mut camera = Camera::new(eye, eye + forward);
+        camera.up = transform::transform_vector(&view_to_world, Vector3::unit_y()).normalize();
+        camera.near = near;
+        camera.far = far;
+        camera.fovy = fovy;
+        camera.aspect_ratio = aspect_ratio;
+        camera.fixed_aspect = true;
+        camera.update();
+        camera
+    }
+    pub fn up(&mut self, up: Vector3<f32>) -> &mut Camera {
+        self.up = up;
+        self.update()
+    }
+    pub fn roll(&mut self, roll: f32) -> &mut Camera {
+        self.roll = roll;
+        self.update()
+    }
+    /// Points the camera `yaw` radians around the world's +z from +x,
+    /// `pitch` above the horizon, rolled by `roll`, keeping the distance
+    /// to its center.
+    pub fn yaw_pitch_roll(&mut self, yaw: f32, pitch: f32, roll: f32) -> &mut Camera {
+        let distance = (self.center - self.eye).length();
+        let forward = vec3(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin());
+        self.center = self.eye + forward * distance;
+        self.up = Vector3::unit_z();
+        self.roll = roll;
+        self.update()
+    }
+    /// Orients the camera by a rotation of the default one, which looks
+    /// along +x with +z up.
+    pub fn orientation(&mut self, rotation: Quaternion<f32>) -> &mut Camera {
+        let distance = (self.center - self.eye).length();
+        self.center = self.eye + (rotation * Vector3::unit_x()) * distance;
+        self.up = rotation * Vector3::unit_z();
+        self.roll = 0.0;
+        self.update()
+    }
+    pub fn near(&mut self, near: f32) -> &mut Camera {
+        self.near = near;
+        self.update()
+    }
+    pub fn far(&mut self, far: f32) -> &mut Camera {
+        self.far = far;
+        self.update()
+    }
+    /// The vertical field of view, in radians.
+    pub fn fovy(&mut self, fovy: f32) -> &mut Camera {
+        assert!(fovy > 0.0 && fovy < std::f32::consts::PI, "field of view must be between 0 and pi radians");
+        self.fovy = fovy;
+        self.fovx = None;
+        self.update()
+    }
+    pub fn fovy_degrees(&mut self, fovy: f32) -> &mut Camera {
+        self.fovy(fovy.to_radians())
+    }
+    /// The horizontal field of view, in radians, which stays put when the
+    /// aspect ratio changes.
+    pub fn fovx(&mut self, fovx: f32) -> &mut Camera {
+        assert!(fovx > 0.0 && fovx < std::f32::consts::PI, "field of view must be between 0 and pi radians");
+        self.fovx = Some(fovx);
+        self.update()
+    }
+    pub fn fovx_degrees(&mut self, fovx: f32) -> &mut Camera {
+        self.fovx(fovx.to_radians())
+    }
+    /// Fixes the aspect ratio; otherwise it follows the rendered image's.
+    pub fn aspect_ratio(&mut self, aspect_ratio: f32) -> &mut Camera {
+        self.aspect_ratio = aspect_ratio;
+        self.fixed_aspect = true;
+        self.update()
+    }
+    // Matches the aspect ratio to a `width` by `height` image, unless it was
+    // fixed.
+    pub fn fit_image(&mut self, width: u32, height: u32) -> &mut Camera {
+        if !self.fixed_aspect {
+            self.aspect_ratio = width as f32 / height as f32;
+        }
+        self.update()
+    }
+    pub fn projection(&mut self, projection: Projection) -> &mut Camera {
+        self.projection = projection;
+        self
+    }
+    pub fn distortion(&mut self, distortion: Distortion) -> &mut Camera {
+        self.distortion = Some(distortion);
+        self
+    }
+    /// Shifts the image without turning the camera, so a level camera can
+    /// frame a tall building and keep its verticals parallel.
+    pub fn shift(&mut self, x: f32, y: f32) -> &mut Camera {
+        self.shift = (x, y);
+        self
+    }
+    /// Tilts the plane of focus away from facing the camera, by `tilt`
+    /// radians about the image's horizontal axis and `swing` about its
+    /// vertical one. A strong tilt with a wide aperture gives the miniature
+    /// look.
+    pub fn tilt(&mut self, tilt: f32, swing: f32) -> &mut Camera {
+        self.tilt = (tilt, swing);
+        self
+    }
+    pub fn aperture(&mut self, aperture: f32) -> &mut Camera {
+        self.aperture = aperture;
+        self
+    }
+    pub fn focus_distance(&mut self, focus_distance: f32) -> &mut Camera {
+        self.focus_distance = focus_distance;
+        self.focus_target = None;
+        self
+    }
+    /// Keeps whatever the scene has named `name` in focus: the focus
+    /// distance is measured to it whenever a render starts, so it stays
+    /// sharp as it moves.
+    pub fn focus_on(&mut self, name: &str) -> &mut Camera {
+        self.focus_target = Some(name.to_string());
+        self
+    }
+    /// Shapes the aperture as a polygon with `blades` sides, rotated by
+    /// `rotation` radians, so out-of-focus highlights take its shape.
+    pub fn blades(&mut self, blades: usize, rotation: f32) -> &mut Camera {
+        self.blades = if blades >= 3 { Some((blades, rotation)) } else { None };
+        self
+    }
+    pub fn moving_to(&mut self, eye: Point3<f32>, center: Point3<f32>) -> &mut Camera {
+        self.motion = Some((eye, center));
+        self
+    }
+    pub fn update(&mut self) -> &mut Camera {
+        if let Some(fovx) = self.fovx {
+            self.fovy = 2.0 * ((fovx / 2.0).tan() / self.aspect_ratio).atan();
+        }
+        self.to_world = self.transform().invert().expect("camera transform must be invertible");
+        self
+    }
+
+    /// The ray through a point on the screen, given as fractions of the
+    /// image's width and height from its top left corner, leaving the lens
+    /// from `lens`, a point in the unit square mapped onto the aperture, at
+    /// `time` in the shutter interval. Points of the image no ray goes
+    /// through, like the corners around a fisheye's circle, have none.
+    fn ray(&self, x: f32, y: f32, lens: (f32, f32), time: f32) -> Option<Ray> {
+        if let Some((eye, center)) = self.motion {
+            if time > 0.0 {
+                let mut camera = self.clone();
+                camera.motion = None;
+                camera.eye = self.eye + (eye - self.eye) * time;
+                camera.center = self.center + (center - self.center) * time;
+                camera.update();
+                return camera.ray(x, y, lens, time);
+            }
+        }
+
+        let screen = (2.0 * x - 1.0, -(2.0 * y - 1.0));
+        let (forward, right, up) = self.basis();
+        match self.projection {
+            Projection::Perspective => {}
+            Projection::Orthographic(extent) => {
+                // everything is in focus: a lens changes nothing without a
+                // point for rays to converge on
+                let (half_width, half_height) = (extent * self.aspect_ratio / 2.0, extent / 2.0);
+                let origin = self.eye + right * (screen.0 * half_width) + up * (screen.1 * half_height);
+                return Some(Ray::new(origin, forward).at_time(time));
+            }
+            Projection::FisheyeEquidistant(fov) | Projection::FisheyeEquisolid(fov) => {
+                let (px, py) = (screen.0 * self.aspect_ratio, screen.1);
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                // the angle away from the view direction
+                let theta = match self.projection {
+                    Projection::FisheyeEquidistant(_) => r * fov / 2.0,
+                    _ => 2.0 * (r * (fov / 4.0).sin()).asin(),
+                };
+                let side = if r > 0.0 { (right * px + up * py) / r } else { right };
+                return Some(Ray::new(self.eye, forward * theta.cos() + side * theta.sin()).at_time(time));
+            }
+            Projection::Equirectangular => {
+                let longitude = screen.0 * std::f32::consts::PI;
+                let latitude = screen.1 * std::f32::consts::FRAC_PI_2;
+                let around = forward * longitude.cos() + right * longitude.sin();
+                return Some(Ray::new(self.eye, around * latitude.cos() + up * latitude.sin()).at_time(time));
+            }
+        }
+
+        let screen = (screen.0 + self.shift.0, screen.1 + self.shift.1);
+        // the image is distorted, so each pixel sees what an ideal lens
+        // would show at the point that distorts onto it
+        let screen = match self.distortion {
+            Some(distortion) => {
+                let tan_y = (self.fovy / 2.0).tan();
+                let tan_x = tan_y * self.aspect_ratio;
+                let (u, v) = distortion.undistort(screen.0 * tan_x, screen.1 * tan_y);
+                (u / tan_x, v / tan_y)
+            }
+            None => screen,
+        };
+        let unproject = |z: f32| {
+            let p = self.to_world * Vector4::new(screen.0, screen.1, z, 1.0);
+            Point3::from_vec((p / p.w).truncate())
+        };
+        let near = unproject(-1.0);
+        let pinhole = Ray::new(near, unproject(1.0) - near);
+        if self.aperture <= 0.0 {
+            return Some(pinhole.at_time(time));
+        }
+
+        // every ray through this pixel meets the pinhole ray on the plane
+        // of focus, wherever on the lens it starts
+        let plane = Quaternion::from_axis_angle(up, Rad { s: self.tilt.1 })
+            * (Quaternion::from_axis_angle(right, Rad { s: self.tilt.0 }) * forward);
+        let on_plane = forward * self.focus_distance;
+        let focus = self.eye + pinhole.direction * (on_plane.dot(plane) / pinhole.direction.dot(plane));
+        let (u, v) = match self.blades {
+            Some((blades, rotation)) => math::regular_polygon(lens.0, lens.1, blades, rotation),
+            None => math::concentric_disc(lens.0, lens.1),
+        };
+        let origin = self.eye + (right * u + up * v) * self.aperture;
+        Some(Ray::new(origin, focus - origin).at_time(time))
+    }
+    // The view direction and the image's right and up, as unit vectors.
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let forward = (self.center - self.eye).normalize();
+        let right = forward.cross(self.view_up()).normalize();
+        (forward, right, right.cross(forward))
+    }
+    // `up` with the roll applied. An up along the view direction says
+    // nothing about which way is up, so some perpendicular stands in for it
+    // rather than degenerating into NaNs.
+    fn view_up(&self) -> Vector3<f32> {
+        let forward = (self.center - self.eye).normalize();
+        let up = if forward.cross(self.up).length() < 1e-6 { math::orthonormal_basis(forward).1 } else { self.up };
+        Quaternion::from_axis_angle(forward, Rad { s: self.roll }) * up
+    }
+    fn transform(&self) -> Matrix4<f32> {
+        let camera = Matrix4::look_at(self.eye, self.center, self.view_up());
+        let projection = perspective(Rad { s: self.fovy }, self.aspect_ratio, self.near, self.far);
+
+        projection * camera
+        //
+        // let n = (self.eye - self.center).normalize();
+        // let u = self.up.cross(n).normalize();
+        // let v = n.cross(u).normalize();
+        //
+        // let height = (self.fovy / 2.0).tan() * 2.0 * self.near;
+        // let width = height * self.aspect_ratio;
+        // let center = Point3::from_vec(((self.eye - n) * self.near));
+    }
+}
+
+/// Something in a scene that can be named, by where it is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Object(usize),
+    Sphere(usize),
+    Instance(usize),
+}
+
+pub struct Scene {
+    camera: Camera,
+    objects: Vec<Box<dyn Intersect>>,
+    lights: Vec<Arc<dyn Light>>,
+    ambient: Vector3<f32>,
+    // what everything without a material of its own is shaded with
+    material: Arc<dyn Material>,
+    // whether anything has its own material, which the GPU renderer can't do
+    shaded: bool,
+    // whether the render in progress shades everything with `Clay`
+    clay: bool,
+    // how the render in progress traces rays
+    integrator: Integrator,
+    // how much light a bounced ray may bring back in the render in progress
+    clamp: Option<f32>,
+    // how many diffuse, glossy and transmission bounces its paths may have
+    depths: Option<(usize, usize, usize)>,
+    // the spheres and objects that give off light, which a path tracer aims
+    // rays at
+    emitters: Vec<Target>,
+    // picks which of the emitters to aim at from a point
+    light_tree: LightTree,
+    // gathered before rendering with the irradiance caching integrator
+    irradiance: Option<IrradianceCache>,
+    // what a guided path tracer has learned of where light comes from
+    guide: Option<Guide>,
+    // the lights a path tracer resampled at each pixel, kept between passes
+    reservoirs: Option<Reservoirs>,
+    // instances of shared geometry are kept apart so they can be moved
+    // between renders; each mesh keeps its own BVH, so moving one only
+    // means rebuilding the top level over the objects
+    instances: Vec<Instance>,
+    // spheres are stored attribute by attribute and intersected four at a
+    // time from a set rebuilt whenever more are added
+    spheres: Spheres,
+    sphere_set: Option<SphereSet>,
+    // plain planes are also kept apart for the GPU renderer
+    planes: Vec<(Point3<f32>, Vector3<f32>, Vector3<f32>)>,
+    // rebuilt over the bounded objects and the instances before each render;
+    // unbounded objects are tested one by one
+    accelerator: Box<dyn Accelerator>,
+    // what the accelerator was last built with, so a scene whose instances
+    // merely moved can be refit instead
+    built: Option<(AcceleratorKind, usize)>,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    names: HashMap<String, Vec<Target>>,
+    // what `named` names and `material` shades: the last thing added, or
+    // every object a node was flattened into
+    last: Vec<Target>,
+    // materials defined once and used by name
+    materials: HashMap<String, Arc<Shared>>,
+}
+impl Scene {
+    pub fn new(camera: Camera) -> Scene {
+        Scene {
+            camera: camera,
+            objects: vec![],
+            lights: vec![],
+            ambient: vec3(0.2, 0.2, 0.2),
+            material: Arc::new(Lambert::default()),
+            shaded: false,
+            clay: false,
+            integrator: Integrator::Whitted,
+            clamp: None,
+            depths: None,
+            emitters: vec![],
+            light_tree: LightTree::default(),
+            irradiance: None,
+            guide: None,
+            reservoirs: None,
+            instances: vec![],
+            spheres: Spheres::default(),
+            sphere_set: None,
+            planes: vec![],
+            accelerator: AcceleratorKind::Bvh.build(&[]),
+            built: None,
+            bounded: vec![],
+            unbounded: vec![],
+            names: HashMap::new(),
+            last: vec![],
+            materials: HashMap::new(),
+        }
+    }
+    pub fn ambient(&mut self, color: Vector3<f32>) -> &mut Scene {
+        self.ambient = color;
+        self
+    }
+    /// Shades everything without a material of its own with `material`.
+    pub fn default_material(&mut self, material: Arc<dyn Material>) -> &mut Scene {
+        material.number_media();
+        self.material = material;
+        self.shaded = true;
+        self
+    }
+    /// Shades the last thing added with `material`.
+    pub fn material(&mut self, material: Arc<dyn Material>) -> &mut Scene {
+        if self.last.is_empty() { return self; }
+        material.number_media();
+        for &target in &self.last {
+            match target {
+                Target::Object(i) => {
+                    let object = self.objects.remove(i);
+                    self.objects.insert(i, Box::new(WithMaterial::new(object, material.clone())));
+                }
+                Target::Sphere(i) => {
+                    self.spheres.materials[i] = Some(material.clone());
+                    self.sphere_set = None;
+                }
+                Target::Instance(i) => self.instances[i].set_material(material.clone()),
+            }
+        }
+        self.shaded = true;
+        self
+    }
+    /// Defines a material in the scene's library, or redefines it for
+    /// everything already using it.
+    pub fn define_material(&mut self, name: &str, material: Arc<dyn Material>) -> &mut Scene {
+        material.number_media();
+        match self.materials.get(name) {
+            Some(shared) => shared.set(material),
+            None => { self.materials.insert(name.to_string(), Arc::new(Shared::new(material))); }
+        }
+        self
+    }
+    /// Shades the last thing added with the library's material of that name.
+    /// Until the name is defined, that is the scene's default material.
+    pub fn material_named(&mut self, name: &str) -> &mut Scene {
+        let default = self.material.clone();
+        let shared = self.materials.entry(name.to_string()).or_insert_with(|| Arc::new(Shared::new(default))).clone();
+        self.material(shared)
+    }
+    /// Names the last thing added, for looking it up later.
+    pub fn named(&mut self, name: &str) -> &mut Scene {
+        if !self.last.is_empty() {
+            self.names.insert(name.to_string(), self.last.clone());
+        }
+        self
+    }
+    // Roughly where a named thing is: the center of its bounds.
+    fn position_of(&self, name: &str) -> Option<Point3<f32>> {
+        let bounds = self.names.get(name)?.iter().map(|&target| match target {
+            Target::Object(i) => self.objects[i].bounds(),
+            Target::Sphere(i) => Some(Aabb::around_sphere(self.spheres.centers[i], self.spheres.radii[i])),
+            Target::Instance(i) => self.instances[i].bounds(),
+        });
+        bounds.fold(None, |all: Option<Aabb>, b| match (all, b) {
+            (Some(all), Some(b)) => Some(all.union(&b)),
+            (all, b) => all.or(b),
+        }).map(|b| b.center())
+    }
+    pub fn add_light<L: Light + 'static>(&mut self, light: L) -> &mut Scene {
+        self.lights.push(Arc::new(light));
+        self
+    }
+    pub fn add_sphere(&mut self, center: Point3<f32>, radius: f32, color: Vector3<f32>) -> &mut Scene {
+        self.spheres.push(center, radius, color);
+        self.last = vec![Target::Sphere(self.spheres.len() - 1)];
+        self
+    }
+    pub fn add_plane(&mut self, point: Point3<f32>, normal: Vector3<f32>, color: Vector3<f32>) -> &mut Scene {
+        self.planes.push((point, normal.normalize(), color));
+        self.add_object(Plane::new(point, normal, color))
+    }
+    pub fn add_box(&mut self, min: Point3<f32>, max: Point3<f32>, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Cuboid::new(min, max, color))
+    }
+    // Open at both ends unless `capped`.
+    pub fn add_cylinder(&mut self, base: Point3<f32>, top: Point3<f32>, radius: f32, capped: bool, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Cylinder::new(base, top, radius, capped, color))
+    }
+    pub fn add_infinite_cylinder(&mut self, point: Point3<f32>, axis: Vector3<f32>, radius: f32, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Cylinder::infinite(point, axis, radius, color))
+    }
+    pub fn add_cone(&mut self, apex: Point3<f32>, axis: Vector3<f32>, half_angle: f32, height: f32, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Cone::new(apex, axis, half_angle, height, color))
+    }
+    pub fn add_disc(&mut self, center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Disc::new(center, normal, radius, color))
+    }
+    pub fn add_quad(&mut self, corner: Point3<f32>, edge_u: Vector3<f32>, edge_v: Vector3<f32>, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Quad::new(corner, edge_u, edge_v, color))
+    }
+    pub fn add_torus(&mut self, center: Point3<f32>, axis: Vector3<f32>, major_radius: f32, minor_radius: f32, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(Torus::new(center, axis, major_radius, minor_radius, color))
+    }
+    pub fn add_sdf<S: Sdf + 'static>(&mut self, sdf: S, color: Vector3<f32>) -> &mut Scene {
+        self.add_object(SdfObject::new(sdf, color))
+    }
+    pub fn add_object<T: Intersect + 'static>(&mut self, object: T) -> &mut Scene {
+        self.objects.push(Box::new(object));
+        self.last = vec![Target::Object(self.objects.len() - 1)];
+        self
+    }
+    pub fn add_heightfield<P: AsRef<Path>>(&mut self, path: P, origin: Point3<f32>, size: Vector3<f32>, color: Vector3<f32>) -> ImageResult<&mut Scene> {
+        let field = Heightfield::from_image(path, origin, size, color)?;
+        Ok(self.add_object(field))
+    }
+    pub fn add_transformed<T: Intersect + 'static>(&mut self, object: T, to_world: Matrix4<f32>) -> &mut Scene {
+        self.add_object(Transformed::new(object, to_world))
+    }
+    pub fn add_moving_sphere(&mut self, start: Point3<f32>, end: Point3<f32>, radius: f32, color: Vector3<f32>) -> &mut Scene {
+        let mut sphere = Transformed::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), radius, color), Matrix4::from_translation(start.to_vec()));
+        sphere.set_motion(Matrix4::from_translation(end.to_vec()));
+        self.add_object(sphere)
+    }
+    pub fn add_instance(&mut self, geometry: &Arc<dyn Intersect>, to_world: Matrix4<f32>, color: Vector3<f32>) -> &mut Scene {
+        self.instances.push(Instance::new(geometry.clone(), to_world, color));
+        self.last = vec![Target::Instance(self.instances.len() - 1)];
+        self
+    }
+    /// Moves the `index`th instance added to the scene.
+    pub fn move_instance(&mut self, index: usize, to_world: Matrix4<f32>) -> &mut Scene {
+        self.instances[index].set_transform(to_world);
+        self
+    }
+    /// Adds every object in the scene graph under `root`; a `material` or
+    /// `named` chained after it applies to all of them.
+    pub fn add_node(&mut self, root: Node) -> &mut Scene {
+        let first = self.objects.len();
+        self.objects.extend(root.flatten(Matrix4::identity()));
+        self.last = (first..self.objects.len()).map(Target::Object).collect();
+        self
+    }
+    pub fn add_mesh(&mut self, mesh: Mesh) -> &mut Scene {
+        self.add_object(mesh)
+    }
+    // Meshes that take a while to build are loaded from `cache`, usually the
+    // render's `settings.cache`, when they were built the same way before.
+    pub fn add_subdivision_surface(&mut self, positions: Vec<Point3<f32>>, faces: Vec<Vec<usize>>, levels: usize, color: Vector3<f32>, cache: Option<&Cache>) -> &mut Scene {
+        let mesh = match cache {
+            Some(cache) => cache.subdivision(positions, faces, levels, color),
+            None => subdivision::catmull_clark(positions, faces, levels, color),
+        };
+        self.add_mesh(mesh)
+    }
+    pub fn add_obj<P: AsRef<Path>>(&mut self, path: P, color: Vector3<f32>, cache: Option<&Cache>) -> io::Result<&mut Scene> {
+        let mesh = match cache {
+            Some(cache) => cache.obj(path.as_ref(), color)?,
+            None => obj::load(path.as_ref(), color)?,
+        };
+        Ok(self.add_mesh(mesh))
+    }
+    // The Utah teapot's format, each patch tessellated into a `resolution`
+    // by `resolution` grid.
+    pub fn add_bpt<P: AsRef<Path>>(&mut self, path: P, resolution: usize, color: Vector3<f32>, cache: Option<&Cache>) -> io::Result<&mut Scene> {
+        let mesh = match cache {
+            Some(cache) => cache.bpt(path.as_ref(), resolution, color)?,
+            None => bezier::tessellate(&bezier::load_bpt(path.as_ref())?, resolution, color),
+        };
+        Ok(self.add_mesh(mesh))
+    }
+    fn build(&mut self, settings: &RenderSettings) {
+        if self.sphere_set.as_ref().map_or(0, |set| set.len()) != self.spheres.len() {
+            self.sphere_set = Some(SphereSet::new(&self.spheres));
+        }
+        self.emitters = if self.clay { vec![] } else {
+            let spheres = (0..self.spheres.len())
+                .filter(|&i| self.spheres.materials[i].as_ref().unwrap_or(&self.material).emits())
+                .map(Target::Sphere);
+            let objects = (0..self.objects.len())
+                .filter(|&i| self.objects[i].emits() && self.objects[i].sample_surface(0.5, 0.5, 0.0).is_some())
+                .map(Target::Object);
+            let instances = (0..self.instances.len())
+                .filter(|&i| self.instances[i].emits() && self.instances[i].sample_surface(0.5, 0.5, 0.0).is_some())
+                .map(Target::Instance);
+            spheres.chain(objects).chain(instances).collect()
+        };
+        let lights: Vec<(Aabb, f32)> = self.emitters.iter()
+            .map(|&emitter| integrator::extent(self, emitter).unwrap_or((Aabb::new(Point3::origin(), Point3::origin()), 0.0)))
+            .collect();
+        self.light_tree = LightTree::new(&lights);
+
+        let kind = settings.accelerator;
+        let mut bounds = vec![];
+        self.bounded.clear();
+        self.unbounded.clear();
+        for i in 0..self.primitive_count() {
+            match self.primitive(i).bounds() {
+                Some(b) => {
+                    bounds.push(b);
+                    self.bounded.push(i);
+                }
+                None => self.unbounded.push(i),
+            }
+        }
+        let count = self.primitive_count() + self.spheres.len();
+        if self.built != Some((kind, count)) || !self.accelerator.refit(&bounds) {
+            self.accelerator = match settings.cache {
+                Some(ref cache) if kind == AcceleratorKind::Bvh => Box::new(cache.bvh(&bounds)),
+                _ => kind.build(&bounds),
+            };
+            self.built = Some((kind, count));
+        }
+    }
+    // Objects, instances, and the sphere set share one index space, in that
+    // order.
+    fn primitive(&self, i: usize) -> &dyn Intersect {
+        let instance = i - self.objects.len().min(i);
+        match self.objects.get(i) {
+            Some(object) => &**object,
+            None if instance < self.instances.len() => &self.instances[instance],
+            None => self.sphere_set.as_ref().unwrap(),
+        }
+    }
+    fn primitive_count(&self) -> usize {
+        self.objects.len() + self.instances.len() + if self.sphere_set.is_some() { 1 } else { 0 }
+    }
+    fn closest_hit(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        {
+            let mut consider = |object: &dyn Intersect| match object.intersect(ray) {
+                Some(hit) if closest.as_ref().is_none_or(|c| hit.t < c.t) => {
+                    let t = hit.t;
+                    closest = Some(hit);
+                    Some(t)
+                }
+                _ => None,
+            };
+            for &i in &self.unbounded {
+                consider(self.primitive(i));
+            }
+            self.accelerator.closest(ray, f32::INFINITY, &mut |i| consider(self.primitive(self.bounded[i])));
+        }
+        closest.map(|hit| hit.facing(ray))
+    }
+    fn in_shadow(&self, ray: &Ray, max_t: f32) -> bool {
+        let blocks = |object: &dyn Intersect| object.intersect(ray).is_some_and(|hit| hit.t < max_t);
+        self.unbounded.iter().any(|&i| blocks(self.primitive(i))) ||
+            self.accelerator.any(ray, max_t, &mut |i| blocks(self.primitive(self.bounded[i])))
+    }
+    // `depth` is how many more times the ray may scatter.
+    fn trace(&self, ray: &Ray, depth: usize) -> Vector3<f32> {
+        if self.integrator == Integrator::Bidirectional {
+            return bidirectional::trace(self, ray, depth);
+        }
+        if ray.media.scattering().is_some() {
+            return match self.walk(ray) {
+                Some((ray, hit, weight)) => weight * self.trace_to(&ray, hit, depth),
+                None => Vector3::zero(),
+            };
+        }
+        self.trace_to(ray, self.closest_hit(ray), depth)
+    }
+    // The color seen along a ray that meets `hit` first.
+    fn trace_to(&self, ray: &Ray, hit: Option<Hit>, depth: usize) -> Vector3<f32> {
+        if let Integrator::AmbientOcclusion { distance, rays } = self.integrator {
+            return hit.map_or(vec3(1.0, 1.0, 1.0), |hit| integrator::occlusion(self, ray, &hit, distance, rays));
+        }
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return self.ambient,
+        };
+        let mut color = self.shade(ray, &hit, &self.unshadowed(ray, &hit));
+        let mut rng = ray.rng();
+        let bounces = if depth > 0 { self.bounces(ray, &hit, &mut rng) } else { vec![] };
+        color = color + integrator::emission(self, ray, &hit);
+        color = color + integrator::emitter_light(self, ray, &hit, &mut rng);
+        for (bounced, weight) in bounces {
+            let light = self.trace(&bounced, depth - 1);
+            if let Some(ref guide) = self.guide { guide.learn(&bounced, light); }
+            color = color + self.clamped(weight * light);
+        }
+        if !ray.media.is_empty() {
+            color = color * ray.media.transmittance(hit.t);
+        }
+        color
+    }
+    // The light a bounced ray adds, turned down to the render's clamp.
+    fn clamped(&self, light: Vector3<f32>) -> Vector3<f32> {
+        let brightness = (light.x + light.y + light.z) / 3.0;
+        match self.clamp {
+            Some(clamp) if brightness > clamp => light * (clamp / brightness),
+            _ => light,
+        }
+    }
+    // A random walk through the medium a ray is in, from scattering to
+    // scattering in directions picked evenly, until it meets a surface. The
+    // distance to each is drawn for one channel at a time, picked in
+    // proportion to how much of it is left, and weighted by how likely it is
+    // for all of them. Returns the last leg of the walk, what it meets, and
+    // how much of each channel is left, or `None` if it wanders for too long.
+    fn walk(&self, ray: &Ray) -> Option<(Ray, Option<Hit>, Vector3<f32>)> {
+        let (extinction, albedo) = ray.media.scattering().unwrap();
+        let mut rng = ray.rng();
+        let mut ray = ray.clone();
+        let mut weight = vec3(1.0, 1.0, 1.0);
+        let left = |d: f32| vec3((-extinction.x * d).exp(), (-extinction.y * d).exp(), (-extinction.z * d).exp());
+        for _ in 0..MAX_WALK {
+            let total = weight.x + weight.y + weight.z;
+            if !(total > 0.0) { return None; }
+            let odds = weight / total;
+            let u = rng.next_f32();
+            let channel = if u < odds.x { 0 } else if u < odds.x + odds.y { 1 } else { 2 };
+            let distance = -(1.0 - rng.next_f32()).ln() / extinction[channel];
+
+            let hit = self.closest_hit(&ray);
+            let t = hit.as_ref().map_or(f32::INFINITY, |h| h.t);
+            if distance >= t {
+                let passed = left(t);
+                return Some((ray, hit, weight * (1.0 / odds.dot(passed)) * passed));
+            }
+            let passed = left(distance);
+            weight = weight * (1.0 / odds.dot(extinction * passed)) * albedo * extinction * passed;
+            let direction = math::uniform_sphere(rng.next_f32(), rng.next_f32());
+            ray = Ray { bounced: ray.bounced, path: ray.path, ..Ray::new(ray.point_at(distance), direction).at_time(ray.time).in_media(ray.media) };
+        }
+        None
+    }
+    // The rays that carry more light to a hit: those its material scatters
+    // and, when path tracing, one it picks at random.
+    fn bounces(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        let material = self.material_at(hit);
+        let mut rays = material.scatter(ray, hit, rng);
+        let scattered = rays.len();
+        if self.integrator == Integrator::PathTracing {
+            match self.guide {
+                // the guide only learns the light arriving outside surfaces
+                Some(ref guide) if hit.front => rays.extend(guide.sample(material, ray, hit, rng)),
+                _ => rays.extend(material.sample(ray, hit, rng)),
+            }
+        }
+        for &mut (ref mut bounced, _) in &mut rays {
+            bounced.path = ray.path;
+        }
+        let (diffuse, glossy, transmission) = match self.depths {
+            Some(depths) => depths,
+            None => return rays,
+        };
+        // rays scattered for sure are mirror-like; those sampled at random
+        // are diffuse unless their material says otherwise
+        rays.into_iter().enumerate().filter_map(|(i, (mut bounced, weight))| {
+            let kind = if bounced.direction.dot(hit.normal) < 0.0 {
+                Bounce::Transmission
+            } else if i < scattered || material.glossy(ray, hit, bounced.direction) {
+                Bounce::Glossy
+            } else {
+                Bounce::Diffuse
+            };
+            bounced.bounced = ray.bounced;
+            bounced.bounced[kind as usize] += 1;
+            let limit = match kind {
+                Bounce::Diffuse => diffuse,
+                Bounce::Glossy => glossy,
+                Bounce::Transmission => transmission,
+            };
+            if bounced.bounced[kind as usize] > limit { None } else { Some((bounced, weight)) }
+        }).collect()
+    }
+    // The density a path tracer's bounce off the hit picks `direction` with.
+    fn bounce_pdf(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        let material = self.material_at(hit);
+        match self.guide {
+            Some(ref guide) if hit.front => guide.pdf(material, ray, hit, direction),
+            _ => material.pdf(ray, hit, direction),
+        }
+    }
+    // The ambient light that shades a hit directly. A path tracer has none;
+    // light from the sky reaches it along the bounced rays instead. With an
+    // irradiance cache it is the light from everything around.
+    fn surface_ambient(&self, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+        match self.integrator {
+            Integrator::PathTracing | Integrator::Bidirectional => Vector3::zero(),
+            Integrator::IrradianceCache { .. } => match self.irradiance {
+                Some(ref cache) => cache.irradiance(self, ray, hit),
+                None => self.ambient,
+            },
+            _ => self.ambient,
+        }
+    }
+    fn material_at<'a>(&'a self, hit: &'a Hit) -> &'a dyn Material {
+        if self.clay { return &Clay; }
+        hit.material.as_ref().map_or(&*self.material, |m| &**m)
+    }
+    // The shadow rays from a hit towards every light, how far away the light
+    // is along each, and how much of it each brings.
+    fn light_rays(&self, ray: &Ray, hit: &Hit) -> Vec<(Ray, f32, Vector3<f32>)> {
+        let intersection_point = ray.point_at(hit.t);
+        // where on lights with a size to aim, apart from what the hit bounces
+        let mut rng = Rng::stream(ray.rng().next_u64(), 1);
+        let mut rays = vec![];
+        for light in &self.lights {
+            let samples = light.samples();
+            for _ in 0..samples {
+                let (light_direction, distance, radiance) = match light.illuminate(intersection_point, rng.next_f32(), rng.next_f32()) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                // from the side of the surface the light is on, which is the
+                // far side for light coming through it
+                let side = if hit.normal.dot(light_direction) < 0.0 { -hit.normal } else { hit.normal };
+                let shadow = Ray::new(intersection_point + side * EPSILON, light_direction).at_time(ray.time);
+                rays.push((shadow, distance, radiance / samples as f32));
+            }
+        }
+        rays
+    }
+    // The lights that reach a hit unshadowed, as where they come from and
+    // how much of them arrives.
+    fn unshadowed(&self, ray: &Ray, hit: &Hit) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        self.light_rays(ray, hit).into_iter()
+            .filter(|&(ref light_ray, distance, _)| !self.in_shadow(light_ray, distance))
+            .map(|(light_ray, _, radiance)| (light_ray.direction, radiance))
+            .collect()
+    }
+    // What a hit shows under the ambient light and the lights reaching it.
+    fn shade(&self, ray: &Ray, hit: &Hit, lights: &[(Vector3<f32>, Vector3<f32>)]) -> Vector3<f32> {
+        light::shade(self.material_at(hit), ray, hit, lights, self.surface_ambient(ray, hit))
+    }
+    #[cfg(feature = "gpu")]
+    fn render_gpu(&self, settings: &RenderSettings) -> Result<Vec<Vector3<f32>>, String> {
+        if self.objects.len() != self.planes.len() || !self.instances.is_empty() {
+            return Err("the scene has shapes other than spheres and planes".to_string());
+        }
+        if self.shaded || self.clay {
+            return Err("the scene has materials other than the default".to_string());
+        }
+        if self.integrator != Integrator::Whitted {
+            return Err("the GPU renderer only does Whitted-style ray tracing".to_string());
+        }
+        // it traces one ray through the center of each pixel, once
+        if settings.samples > 1 || settings.passes > 1 || settings.adaptive.is_some() || settings.filter != film::Filter::Box {
+            return Err("the GPU renderer takes one sample per pixel, with no passes, adaptive sampling or filter".to_string());
+        }
+        if self.lights.len() > 1 {
+            return Err("the scene has more than one light".to_string());
+        }
+        if self.camera.projection != Projection::Perspective {
+            return Err("the camera isn't a perspective one".to_string());
+        }
+        if self.camera.aperture > 0.0 || self.camera.motion.is_some() || self.camera.distortion.is_some() || self.camera.shift != (0.0, 0.0) {
+            return Err("the camera has lens effects or motion blur".to_string());
+        }
+        let scene = gpu::SceneData {
+            inverse_camera: self.camera.to_world,
+            ambient: self.ambient,
+            light: match self.lights.first() {
+                Some(light) => Some(light.position().ok_or("the light isn't a point light".to_string())?),
+                None => None,
+            },
+            spheres: (0..self.spheres.len())
+                .map(|i| (self.spheres.centers[i], self.spheres.radii[i], self.spheres.colors[i]))
+                .collect(),
+            planes: self.planes.clone(),
+        };
+        gpu::render(&scene, settings.width, settings.height)
+    }
+    #[cfg(not(feature = "gpu"))]
+    fn render_gpu(&self, _settings: &RenderSettings) -> Result<Vec<Vector3<f32>>, String> {
+        Err("built without the `gpu` feature".to_string())
+    }
+    // One pass over a tile, as samples positioned in pixels from the image's
+    // top-left corner. Pixels are sampled in rounds; with adaptive sampling,
+    // the ones that are still noisy after a round get another.
+    fn render_tile(&self, tile: Tile, pass: usize, sampler: &dyn Sampler, settings: &RenderSettings) -> TileSamples {
+        let (width, height) = (settings.width as f32, settings.height as f32);
+        let pixels = tile.pixels();
+        let mut estimates = vec![Estimate::new(); pixels.len()];
+        let mut pending: Vec<usize> = (0..pixels.len()).collect();
+        let per_pass = settings.adaptive.map_or(settings.samples, |(_, max)| max);
+        let mut samples = vec![];
+        while !pending.is_empty() {
+            let (mut owners, mut positions, mut rays) = (vec![], vec![], vec![]);
+            for &i in &pending {
+                let (x, y) = pixels[i];
+                let n = settings.adaptive.map_or(settings.samples, |(_, max)| settings.samples.min(max - estimates[i].count()));
+                let first = pass * per_pass + estimates[i].count();
+                let lenses = sampler.points(x, y, first, n, sampler::LENS);
+                let times = sampler.points(x, y, first, n, sampler::TIME);
+                for (k, (((dx, dy), lens), (time, _))) in sampler::offsets(sampler, x, y, first, n).into_iter().zip(lenses).zip(times).enumerate() {
+                    let (sx, sy) = (x as f32 + 0.5 + dx, y as f32 + 0.5 + dy);
+                    match self.camera.ray(sx / width, sy / height, lens, time) {
+                        Some(ray) => {
+                            owners.push(i);
+                            positions.push((sx, sy));
+                            rays.push(ray.for_pixel(x, y).for_sample(settings.seed, first + k));
+                        }
+                        // nothing is seen there, not even the background
+                        None => {
+                            estimates[i].add(Vector3::zero());
+                            samples.push((i, sx, sy, Vector3::zero()));
+                        }
+                    }
+                }
+            }
+            let colors: Vec<Vector3<f32>> = if settings.wavefront {
+                wavefront::trace(self, &rays, settings.max_depth)
+            } else {
+                rays.iter().map(|ray| self.trace(ray, settings.max_depth)).collect()
+            };
+            for ((&i, &(sx, sy)), color) in owners.iter().zip(&positions).zip(colors) {
+                estimates[i].add(color);
+                samples.push((i, sx, sy, color));
+            }
+
+            pending = match settings.adaptive {
+                Some((threshold, max)) => pending.into_iter()
+                    .filter(|&i| estimates[i].count() < max && estimates[i].noisy(threshold))
+                    .collect(),
+                None => vec![],
+            };
+        }
+        samples.into_iter()
+            .filter(|&(i, _, _, color)| settings.rejection.is_none_or(|factor| !estimates[i].outlier(color, factor)))
+            .map(|(_, sx, sy, color)| (sx, sy, color))
+            .collect()
+    }
+    fn autofocus(&mut self) {
+        let name = match self.camera.focus_target {
+            Some(ref name) => name.clone(),
+            None => return,
+        };
+        match self.position_of(&name) {
+            Some(target) => {
+                let (forward, _, _) = self.camera.basis();
+                self.camera.focus_distance = (target - self.camera.eye).dot(forward).max(self.camera.near);
+            }
+            None => eprintln!("nothing named {:?} to focus on; focus left as it was", name),
+        }
+    }
+    // Renders to `settings.output`, which is kept up to date as the render
+    // goes, and returns the finished image.
+    pub fn render(&mut self, settings: &RenderSettings) -> Image {
+        let (width, height) = (settings.width, settings.height);
+        self.camera.fit_image(width, height);
+        self.autofocus();
+        self.clay = settings.clay;
+        self.integrator = settings.integrator;
+        self.clamp = settings.clamp;
+        self.depths = settings.depths;
+        if settings.gpu {
+            match self.render_gpu(settings) {
+                Ok(colors) => {
+                    let mut img = ImageBuffer::new(width, height);
+                    for (i, color) in colors.into_iter().enumerate() {
+                        img.put_pixel(i as u32 % width, i as u32 / width, to_rgb(color));
+                    }
+                    save(&img, &settings.output);
+                    return img;
+                }
+                Err(e) => eprintln!("GPU rendering unavailable ({}); falling back to the CPU", e),
+            }
+        }
+
+        self.build(settings);
+        self.irradiance = None;
+        if let Integrator::IrradianceCache { rays, error } = self.integrator {
+            self.irradiance = Some(IrradianceCache::new(self, settings, rays, error));
+        }
+        self.guide = None;
+        if settings.guiding && self.integrator == Integrator::PathTracing {
+            let bounds = self.bounded.iter().filter_map(|&i| self.primitive(i).bounds())
+                .fold(None, |a: Option<Aabb>, b| Some(a.map_or(b, |a| a.union(&b))));
+            self.guide = bounds.map(Guide::new);
+        }
+        self.reservoirs = match settings.resampling {
+            Some(candidates) if self.integrator == Integrator::PathTracing => Some(Reservoirs::new(width, height, candidates)),
+            _ => None,
+        };
+        let mut pool = rayon::ThreadPoolBuilder::new();
+        if let Some(threads) = settings.threads {
+            pool = pool.num_threads(threads);
+        }
+        let pool = pool.build().expect("couldn't start render threads");
+
+        let sampler = settings.sampler.build(settings.seed);
+        let sampler: &dyn Sampler = &*sampler;
+        let tiles = tiles::split(width, height, settings.tile_size);
+        let mut film = Film::new(width, height, settings.filter);
+        let mut img = ImageBuffer::new(width, height);
+        let scene: &Scene = self;
+        for pass in 0..settings.passes {
+            // every worker takes the next unclaimed tile until none are
+            // left; finished tiles come back to this thread, which alone
+            // owns the image
+            let next = AtomicUsize::new(0);
+            let (sender, receiver) = mpsc::channel();
+            pool.in_place_scope(|scope| {
+                for _ in 0..pool.current_num_threads() {
+                    let sender = sender.clone();
+                    let (tiles, next) = (&tiles, &next);
+                    scope.spawn(move |_| loop {
+                        let i = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        let tile = match tiles.get(i) {
+                            Some(&tile) => tile,
+                            None => break,
+                        };
+                        let samples = scene.render_tile(tile, pass, sampler, settings);
+                        if sender.send((i, samples)).is_err() { break; }
+                    });
+                }
+                drop(sender);
+
+                // tiles are splatted in order, whatever order they finish
+                // in, so overlapping filters always sum the same way and
+                // a seed always gives the same image
+                let mut finished: Vec<Option<TileSamples>> = vec![None; tiles.len()];
+                let mut splatted = 0;
+                for (done, (i, samples)) in receiver.iter().enumerate() {
+                    finished[i] = Some(samples);
+                    while let Some(samples) = finished.get_mut(splatted).and_then(|s| s.take()) {
+                        let tile = tiles[splatted];
+                        for (x, y, color) in samples {
+                            film.add(x, y, color);
+                        }
+                        // the filter spreads samples a little past the tile
+                        let (x_min, y_min, x_max, y_max) = film.reach(tile.x, tile.y, tile.x + tile.width, tile.y + tile.height);
+                        for y in y_min..y_max {
+                            for x in x_min..x_max {
+                                img.put_pixel(x, y, to_rgb(film.color(x, y)));
+                            }
+                        }
+                        splatted += 1;
+                    }
+                    if settings.progress {
+                        eprint!("\rpass {}/{}: rendered {}/{} tiles", pass + 1, settings.passes, done + 1, tiles.len());
+                    }
+                    if settings.checkpoint.is_some_and(|n| n > 0 && (done + 1) % n == 0) {
+                        save(&img, &settings.output);
+                    }
+                }
+            });
+            save(&img, &settings.output);
+            if let Some(ref guide) = scene.guide { guide.refresh(); }
+            if let Some(ref reservoirs) = scene.reservoirs { reservoirs.refresh(); }
+        }
+        if settings.progress { eprintln!(); }
+        img
+    }
+}
+
+/// A rendered image.
+pub type Image = ImageBuffer<image::Rgb<u8>, Vec<u8>>;
+
+// Where in the image each of a tile's samples landed, and what it saw.
+type TileSamples = Vec<(f32, f32, Vector3<f32>)>;
+
+fn to_rgb(color: Vector3<f32>) -> image::Rgb<u8> {
+    image::Rgb([
+        (color[0].min(1.0) * 255.0) as u8,
+        (color[1].min(1.0) * 255.0) as u8,
+        (color[2].min(1.0) * 255.0) as u8])
+}
+
+// Writes next to `path` first and renames over it, so a viewer watching the
+// file never sees a half-written image.
+fn save(img: &Image, path: &Path) {
+    let partial = path.with_extension("partial.png");
+    {
+        let fout = &mut File::create(&partial).unwrap();
+
+        // Write the contents of this image to the Writer in PNG format.
+        DynamicImage::ImageRgb8(img.clone()).save(fout, image::PNG).unwrap();
+    }
+    fs::rename(&partial, path).unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> Scene {
+        Scene::new(Camera::new(Point3::new(-5.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)))
+    }
+
+    #[test]
+    fn named_nodes_name_everything_in_them() {
+        let mut node = Node::new(Matrix4::from_translation(vec3(0.0, 10.0, 0.0)));
+        node.add_object(Sphere::new(Point3::new(-1.0, 0.0, 0.0), 1.0, vec3(1.0, 1.0, 1.0)));
+        node.add_object(Sphere::new(Point3::new(1.0, 0.0, 0.0), 1.0, vec3(1.0, 1.0, 1.0)));
+        let mut scene = scene();
+        scene.add_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, vec3(1.0, 1.0, 1.0))
+            .add_node(node)
+            .named("node")
+            .material(Arc::new(Lambert::default()));
+        assert_eq!(scene.position_of("node"), Some(Point3::new(0.0, 10.0, 0.0)));
+        // the material went to the node's objects, not the sphere before it
+        assert!(scene.spheres.materials[0].is_none());
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use std::{env, process};
+
+    use super::*;
+    use super::light::PointLight;
+    use super::material::Emissive;
+    use super::quad::Quad;
+    use super::transform::Transformed;
+
+    fn scene() -> Scene {
+        let mut scene = Scene::new(Camera::new(Point3::new(-5.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)));
+        scene
+            .ambient(vec3(0.3, 0.3, 0.3))
+            .add_light(PointLight::new(Point3::new(-0.5, -2.0, 0.0), vec3(1.0, 1.0, 1.0), 1.0))
+            .add_sphere(Point3::new(4.0, 0.0, 3.0), 3.0, vec3(1.0, 0.23, 0.47))
+            .add_sphere(Point3::new(1.0, 0.0, 0.0), 1.0, vec3(0.21, 0.1, 0.47))
+            .add_sphere(Point3::new(0.0, 1.5, 1.5), 0.5, vec3(1.0, 1.0, 1.0))
+            .material(Arc::new(Emissive::new(vec3(1.0, 0.9, 0.8), 4.0)))
+            .add_plane(Point3::new(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0), vec3(0.8, 0.8, 0.8));
+        scene
+    }
+
+    fn render(mut scene: Scene, threads: usize, configure: &dyn Fn(&mut RenderSettings)) -> Image {
+        let mut settings = RenderSettings::new(24, 24);
+        settings
+            .output(env::temp_dir().join(format!("raytracer-test-{}-{}.png", process::id(), threads)))
+            .threads(threads)
+            .tile_size(4)
+            .integrator(Integrator::PathTracing)
+            .samples(4)
+            .seed(3);
+        configure(&mut settings);
+        let image = scene.render(&settings);
+        let _ = fs::remove_file(&settings.output);
+        image
+    }
+
+    #[test]
+    fn a_seed_renders_the_same_on_any_number_of_threads() {
+        let configurations: [fn(&mut RenderSettings); 4] = [
+            |_| {},
+            |settings| { settings.wavefront(true); },
+            |settings| { settings.adaptive(0.01, 16).max_depth(3); },
+            |settings| { settings.progressive(2).guiding(true).resampling(4); },
+        ];
+        for (i, configure) in configurations.iter().enumerate() {
+            assert!(render(scene(), 1, configure).into_raw() == render(scene(), 4, configure).into_raw(), "configuration {}", i);
+        }
+    }
+
+    // A light over a matte floor: a square quad two units up, facing down,
+    // added by `add`.
+    fn lit_floor(add: &dyn Fn(&mut Scene)) -> Image {
+        let mut scene = Scene::new(Camera::new(Point3::new(0.0, -4.0, 3.0), Point3::new(0.0, 0.0, 0.0)));
+        scene.ambient(vec3(0.0, 0.0, 0.0))
+            .add_plane(Point3::new(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(0.8, 0.8, 0.8));
+        add(&mut scene);
+        scene.material(Arc::new(Emissive::new(vec3(1.0, 1.0, 1.0), 4.0)));
+        render(scene, 1, &|settings| { settings.samples(16); })
+    }
+
+    #[test]
+    fn transformed_emitters_light_like_any_other() {
+        let white = vec3(1.0, 1.0, 1.0);
+        let small = || Quad::new(Point3::new(-0.25, -0.25, 0.0), vec3(0.0, 0.5, 0.0), vec3(0.5, 0.0, 0.0), white);
+        let to_world = Matrix4::from_translation(vec3(0.0, 0.0, 2.0)) * Matrix4::from_scale(2.0);
+        let plain = lit_floor(&|scene| { scene.add_quad(Point3::new(-0.5, -0.5, 2.0), vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), white); });
+        let transformed = lit_floor(&|scene| { scene.add_object(Transformed::new(small(), to_world)); });
+        let geometry: Arc<dyn Intersect> = Arc::new(small());
+        let instanced = lit_floor(&|scene| { scene.add_instance(&geometry, to_world, white); });
+        assert!(plain.pixels().any(|p| p[0] > 32));
+        // lit by the same samples of the same light, give or take rounding
+        for image in &[transformed, instanced] {
+            let most = plain.pixels().zip(image.pixels())
+                .map(|(a, b)| (a[0] as i32 - b[0] as i32).abs())
+                .max().unwrap();
+            assert!(most <= 2, "pixels differ by up to {}", most);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+use std::fmt::Debug;
+
+use super::{Hit, Ray};
+use super::integrator;
+use super::math;
+use super::material::Material;
+
+/// Something that lights the scene without being anything a ray can hit.
+pub trait Light: Send + Sync + Debug {
+    /// The light reaching `point` from here, unless something is in the
+    /// way: the direction it comes from, how far off it is, and how much of
+    /// it arrives, where white is what materials take a light to deliver.
+    /// For lights with a size, `u` and `v` in the unit square pick where on
+    /// it the light comes from, and it is one sample of `samples`: its
+    /// color times its intensity, the radiance it gives off, times the
+    /// solid angle the sample stands for, so the average over many samples
+    /// is all the light arriving from it.
+    fn illuminate(&self, point: Point3<f32>, u: f32, v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)>;
+
+    /// How many shadow rays each point is lit through, each bringing an
+    /// even share of the light.
+    fn samples(&self) -> usize {
+        1
+    }
+
+    /// Where the light is and how strongly it shines, if it shines from a
+    /// single point equally every way and however far, which is the only
+    /// light the GPU renderer draws.
+    fn position(&self) -> Option<(Point3<f32>, Vector3<f32>)> {
+        None
+    }
+}
+
+/// A light that shines equally every way from one point, as brightly
+/// however far away it is unless it is given a falloff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointLight {
+    pub position: Point3<f32>,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    // the power of the distance the light is divided by
+    pub falloff: f32,
+}
+impl PointLight {
+    pub fn new(position: Point3<f32>, color: Vector3<f32>, intensity: f32) -> PointLight {
+        PointLight {
+            position: position,
+            color: color,
+            intensity: intensity,
+            falloff: 0.0,
+        }
+    }
+
+    /// Light that falls off with the square of the distance, as real light
+    /// does; `intensity` is what arrives from a unit away.
+    pub fn inverse_square(self) -> PointLight {
+        self.falloff(2.0)
+    }
+
+    /// Light that falls off with the distance to the given power, for a
+    /// gentler or harsher falloff than the real one; 0 for none.
+    pub fn falloff(self, exponent: f32) -> PointLight {
+        PointLight { falloff: exponent, ..self }
+    }
+}
+
+impl Light for PointLight {
+    fn illuminate(&self, point: Point3<f32>, _u: f32, _v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)> {
+        let to_light = self.position - point;
+        let distance = to_light.length();
+        Some((to_light.normalize(), distance, self.color * (self.intensity * attenuation(distance, self.falloff))))
+    }
+
+    fn position(&self) -> Option<(Point3<f32>, Vector3<f32>)> {
+        if self.falloff == 0.0 { Some((self.position, self.color * self.intensity)) } else { None }
+    }
+}
+
+/// A light so far away, like the sun, that it reaches everything from the
+/// same direction and as brightly. `direction` is the way the light goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+}
+impl DirectionalLight {
+    pub fn new(direction: Vector3<f32>, color: Vector3<f32>, intensity: f32) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.normalize(),
+            color: color,
+            intensity: intensity,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    // shadow rays go on forever
+    fn illuminate(&self, _point: Point3<f32>, _u: f32, _v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)> {
+        Some((-self.direction, f32::INFINITY, self.color * self.intensity))
+    }
+}
+
+/// A light shining from one point in a cone around `direction`, like a
+/// stage light: at full strength out to `inner` radians from its axis,
+/// fading smoothly to nothing at `outer`. Like a point light, it can fall
+/// off with distance too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotLight {
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    pub inner: f32,
+    pub outer: f32,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    // the power of the distance the light is divided by
+    pub falloff: f32,
+}
+impl SpotLight {
+    pub fn new(position: Point3<f32>, direction: Vector3<f32>, inner: f32, outer: f32, color: Vector3<f32>, intensity: f32) -> SpotLight {
+        SpotLight {
+            position: position,
+            direction: direction.normalize(),
+            inner: inner.min(outer),
+            outer: outer,
+            color: color,
+            intensity: intensity,
+            falloff: 0.0,
+        }
+    }
+
+    /// Light that falls off with the square of the distance; `intensity` is
+    /// what arrives from a unit away.
+    pub fn inverse_square(self) -> SpotLight {
+        self.falloff(2.0)
+    }
+
+    /// Light that falls off with the distance to the given power; 0 for
+    /// none.
+    pub fn falloff(self, exponent: f32) -> SpotLight {
+        SpotLight { falloff: exponent, ..self }
+    }
+
+    // How much of the light goes off at an angle with cosine `cos` to the
+    // axis.
+    fn spread(&self, cos: f32) -> f32 {
+        let (cos_inner, cos_outer) = (self.inner.cos(), self.outer.cos());
+        if cos >= cos_inner { return 1.0; }
+        if cos <= cos_outer { return 0.0; }
+        let x = (cos - cos_outer) / (cos_inner - cos_outer);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn illuminate(&self, point: Point3<f32>, _u: f32, _v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)> {
+        let to_light = self.position - point;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let spread = self.spread(self.direction.dot(-direction));
+        if spread <= 0.0 { return None; }
+        Some((direction, distance, self.color * (self.intensity * spread * attenuation(distance, self.falloff))))
+    }
+}
+
+/// The shape of an area light.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// The parallelogram spanned by the edges from the corner, facing
+    /// their cross product.
+    Rectangle { corner: Point3<f32>, edge_u: Vector3<f32>, edge_v: Vector3<f32> },
+    Disc { center: Point3<f32>, normal: Vector3<f32>, radius: f32 },
+}
+
+/// A light with a size, like a window or a softbox, shining out of the
+/// front of its shape. Points it is partly hidden from are lit by the part
+/// they see, so shadows soften at their edges, through `samples` shadow
+/// rays to points spread over it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaLight {
+    pub shape: Shape,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub samples: usize,
+}
+impl AreaLight {
+    pub fn rectangle(corner: Point3<f32>, edge_u: Vector3<f32>, edge_v: Vector3<f32>, color: Vector3<f32>, intensity: f32) -> AreaLight {
+        AreaLight::new(Shape::Rectangle { corner: corner, edge_u: edge_u, edge_v: edge_v }, color, intensity)
+    }
+
+    pub fn disc(center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: Vector3<f32>, intensity: f32) -> AreaLight {
+        AreaLight::new(Shape::Disc { center: center, normal: normal.normalize(), radius: radius }, color, intensity)
+    }
+
+    fn new(shape: Shape, color: Vector3<f32>, intensity: f32) -> AreaLight {
+        AreaLight {
+            shape: shape,
+            color: color,
+            intensity: intensity,
+            samples: 16,
+        }
+    }
+
+    // The point picked by `u` and `v` on the shape, spread evenly over it,
+    // the way it faces, and the shape's area.
+    fn point(&self, u: f32, v: f32) -> (Point3<f32>, Vector3<f32>, f32) {
+        match self.shape {
+            Shape::Rectangle { corner, edge_u, edge_v } => {
+                let facing = edge_u.cross(edge_v);
+                (corner + edge_u * u + edge_v * v, facing.normalize(), facing.length())
+            }
+            Shape::Disc { center, normal, radius } => {
+                let (x, y) = math::concentric_disc(u, v);
+                let (tangent, bitangent) = math::orthonormal_basis(normal);
+                (center + (tangent * x + bitangent * y) * radius, normal, PI * radius * radius)
+            }
+        }
+    }
+}
+
+impl Light for AreaLight {
+    fn illuminate(&self, point: Point3<f32>, u: f32, v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)> {
+        let (on, facing, area) = self.point(u, v);
+        let to_light = on - point;
+        let distance2 = to_light.length2();
+        let direction = to_light / distance2.sqrt();
+        // the solid angle the point stands for, seen from this far and
+        // this much askew
+        let cos = -facing.dot(direction);
+        if cos <= 0.0 { return None; }
+        Some((direction, distance2.sqrt(), self.color * (self.intensity * cos * area / distance2)))
+    }
+
+    fn samples(&self) -> usize {
+        self.samples.max(1)
+    }
+}
+
+/// A ball of light, like a bulb. Points it is partly hidden from are lit by
+/// the part of it they see, through `samples` shadow rays in directions
+/// spread evenly over it, so the bigger it is the softer the shadows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SphereLight {
+    pub center: Point3<f32>,
+    pub radius: f32,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub samples: usize,
+}
+impl SphereLight {
+    pub fn new(center: Point3<f32>, radius: f32, color: Vector3<f32>, intensity: f32) -> SphereLight {
+        SphereLight {
+            center: center,
+            radius: radius,
+            color: color,
+            intensity: intensity,
+            samples: 16,
+        }
+    }
+}
+
+impl Light for SphereLight {
+    // from inside, it is lit as if from the center by a hemisphere of it,
+    // all that a point just outside sees
+    fn illuminate(&self, point: Point3<f32>, u: f32, v: f32) -> Option<(Vector3<f32>, f32, Vector3<f32>)> {
+        let radiance = self.color * self.intensity;
+        let to_center = self.center - point;
+        let (direction, pdf) = match integrator::sample_sphere(point, self.center, self.radius, u, v) {
+            Some(sample) => sample,
+            None => return Some((to_center.normalize(), to_center.length(), radiance * (2.0 * PI))),
+        };
+        // the near side of the sphere that way
+        let b = direction.dot(to_center);
+        let distance = b - (b * b - to_center.length2() + self.radius * self.radius).max(0.0).sqrt();
+        Some((direction, distance, radiance * (1.0 / pdf)))
+    }
+
+    fn samples(&self) -> usize {
+        self.samples.max(1)
+    }
+}
+
+// How much of a light falling off with the given power of the distance is
+// left that far away.
+fn attenuation(distance: f32, falloff: f32) -> f32 {
+    if falloff == 0.0 { 1.0 } else { distance.max(1e-4).powf(-falloff) }
+}
+
+/// What the material shows at a hit under the ambient light and the lights
+/// arriving from each of the given directions with the given strengths:
+/// the material lit by the ambient light alone, plus what it makes of each
+/// light in white, times that light's strength.
+pub fn shade(material: &dyn Material, ray: &Ray, hit: &Hit, lights: &[(Vector3<f32>, Vector3<f32>)], ambient: Vector3<f32>) -> Vector3<f32> {
+    lights.iter().fold(material.shade(ray, hit, None, ambient), |color, &(direction, radiance)| {
+        color + radiance * material.shade(ray, hit, Some(direction), Vector3::zero())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::material::{Clay, Lambert, Phong};
+
+    #[test]
+    fn one_white_light_shades_as_the_material_does() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 2.0), vec3(0.3, 0.0, -1.0));
+        let hit = Hit { t: 2.0, normal: vec3(0.0, 0.0, 1.0), color: vec3(0.9, 0.5, 0.2), material: None, front: true };
+        let light = vec3(-0.4, 0.2, 1.0).normalize();
+        let ambient = vec3(0.3, 0.3, 0.3);
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(Lambert::new(vec3(0.8, 0.8, 0.8))),
+            Box::new(Clay),
+            Box::new(Phong::new(vec3(0.6, 0.6, 0.6), vec3(0.4, 0.4, 0.4), 20.0)),
+            Box::new(Phong::blinn(vec3(0.6, 0.6, 0.6), vec3(0.4, 0.4, 0.4), 80.0)),
+        ];
+        for material in &materials {
+            let direct = material.shade(&ray, &hit, Some(light), ambient);
+            let summed = shade(&**material, &ray, &hit, &[(light, vec3(1.0, 1.0, 1.0))], ambient);
+            assert!((direct - summed).length() < 1e-6, "{:?}: {:?} against {:?}", material, direct, summed);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use cgmath::*;
+
+use std::cmp::Ordering;
+
+use super::aabb::Aabb;
+
+#[derive(Debug)]
+enum Node {
+    // which of the emitters it is
+    Leaf(usize),
+    Split(usize, usize),
+}
+
+/// A bounding volume hierarchy over a scene's emitters, each node knowing
+/// how much light everything under it gives off, for picking emitters in
+/// proportion to how much they are likely to light a point: brighter and
+/// closer ones win, so scenes with thousands of emitters spend their shadow
+/// rays on the few that matter. Emitters' bounds also find which of them a
+/// point is on without testing every one.
+#[derive(Debug, Default)]
+pub struct LightTree {
+    nodes: Vec<Node>,
+    bounds: Vec<Aabb>,
+    power: Vec<f32>,
+    parents: Vec<usize>,
+    // the leaf of each emitter
+    leaves: Vec<usize>,
+}
+impl LightTree {
+    /// A tree over emitters with the given bounds and brightness times area.
+    pub fn new(emitters: &[(Aabb, f32)]) -> LightTree {
+        let mut tree = LightTree { leaves: vec![0; emitters.len()], ..LightTree::default() };
+        if !emitters.is_empty() {
+            let mut order: Vec<usize> = (0..emitters.len()).collect();
+            tree.build(emitters, &mut order, 0);
+        }
+        tree
+    }
+
+    fn build(&mut self, emitters: &[(Aabb, f32)], order: &mut [usize], parent: usize) -> usize {
+        let node = self.nodes.len();
+        let bounds = order.iter().skip(1).fold(emitters[order[0]].0, |b, &i| b.union(&emitters[i].0));
+        self.nodes.push(Node::Leaf(order[0]));
+        self.bounds.push(bounds);
+        self.power.push(order.iter().map(|&i| emitters[i].1).sum());
+        self.parents.push(parent);
+        if order.len() == 1 {
+            self.leaves[order[0]] = node;
+            return node;
+        }
+
+        // halve along the longest axis of the emitters' centers
+        let centers = Aabb::from_points(order.iter().map(|&i| emitters[i].0.center())).unwrap();
+        let extent = centers.max - centers.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let half = order.len() / 2;
+        order.select_nth_unstable_by(half, |&a, &b| {
+            emitters[a].0.center()[axis].partial_cmp(&emitters[b].0.center()[axis]).unwrap_or(Ordering::Equal)
+        });
+        let (left, right) = order.split_at_mut(half);
+        let left = self.build(emitters, left, node);
+        let right = self.build(emitters, right, node);
+        self.nodes[node] = Node::Split(left, right);
+        node
+    }
+
+    /// An emitter picked by `u` in `[0, 1)` for lighting `point`, and the
+    /// chance it had of being picked.
+    pub fn pick(&self, point: Point3<f32>, mut u: f32) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() { return None; }
+        let (mut node, mut chance) = (0, 1.0);
+        loop {
+            match self.nodes[node] {
+                Node::Leaf(emitter) => return Some((emitter, chance)),
+                Node::Split(left, right) => {
+                    let odds = self.left_odds(point, left, right);
+                    if u < odds {
+                        u /= odds;
+                        node = left;
+                        chance *= odds;
+                    } else {
+                        u = ((u - odds) / (1.0 - odds)).min(1.0 - 1e-6);
+                        node = right;
+                        chance *= 1.0 - odds;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The chance `pick` has of picking the emitter for lighting `point`.
+    pub fn chance(&self, point: Point3<f32>, emitter: usize) -> f32 {
+        let mut node = self.leaves[emitter];
+        let mut chance = 1.0;
+        while node != 0 {
+            let parent = self.parents[node];
+            if let Node::Split(left, right) = self.nodes[parent] {
+                let odds = self.left_odds(point, left, right);
+                chance *= if node == left { odds } else { 1.0 - odds };
+            }
+            node = parent;
+        }
+        chance
+    }
+
+    /// The emitters whose bounds hold the point.
+    pub fn around(&self, point: Point3<f32>) -> Vec<usize> {
+        let mut found = vec![];
+        let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+        while let Some(node) = stack.pop() {
+            if !holds(&self.bounds[node], point) { continue; }
+            match self.nodes[node] {
+                Node::Leaf(emitter) => found.push(emitter),
+                Node::Split(left, right) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+        found
+    }
+
+    fn left_odds(&self, point: Point3<f32>, left: usize, right: usize) -> f32 {
+        let (l, r) = (self.importance(point, left), self.importance(point, right));
+        if l + r > 0.0 { l / (l + r) } else { 0.5 }
+    }
+
+    // How much light the node's emitters likely give the point: their power
+    // over the squared distance to them, which is never taken as less than
+    // how big they are.
+    fn importance(&self, point: Point3<f32>, node: usize) -> f32 {
+        let bounds = &self.bounds[node];
+        let radius2 = (bounds.max - bounds.min).length2() * 0.25;
+        self.power[node] / (bounds.center() - point).length2().max(radius2)
+    }
+}
+
+// Whether the point is in the box, give or take a little.
+fn holds(bounds: &Aabb, point: Point3<f32>) -> bool {
+    let margin = 1e-3 * (1.0 + (bounds.max - bounds.min).length());
+    (0..3).all(|axis| point[axis] >= bounds.min[axis] - margin && point[axis] <= bounds.max[axis] + margin)
+}
@@ -0,0 +1,982 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::Aabb;
+use super::math;
+use super::microfacet::{Charlie, Frame, Ggx};
+use super::rng::Rng;
+
+/// How a surface responds to light. The geometry supplies the hit and its
+/// color; the material decides what is seen there.
+pub trait Material: Send + Sync + Debug {
+    /// The color seen along `ray` at `hit`, lit by the ambient light and,
+    /// unless it is shadowed, the light in direction `light`.
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32>;
+
+    /// Rays carrying more light to `hit`, such as a reflection, each with
+    /// how much of what it brings back is seen. Materials that pick their
+    /// rays at random draw from `rng`.
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        vec![]
+    }
+
+    /// The light the surface gives off itself towards where `ray` came from,
+    /// lit or not.
+    fn emitted(&self, _ray: &Ray, _hit: &Hit) -> Vector3<f32> {
+        vec3(0.0, 0.0, 0.0)
+    }
+
+    /// Whether the surface gives off any light, so a path tracer can aim
+    /// rays at it.
+    fn emits(&self) -> bool {
+        false
+    }
+
+    /// For path tracing, the BRDF times the cosine for light arriving at
+    /// `hit` from `direction`. Unless overridden, what `shade` makes of a
+    /// light that way, over the pi lights are taken to deliver.
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> Vector3<f32> {
+        self.shade(ray, hit, Some(direction), vec3(0.0, 0.0, 0.0)) / PI
+    }
+
+    /// The probability density of `sample` picking `direction`.
+    fn pdf(&self, _ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        hit.normal.dot(direction).max(0.0) / PI
+    }
+
+    /// For path tracing, a ray picked at random to bring light to `hit` from
+    /// elsewhere in the scene, with how much of what it brings is seen: the
+    /// BRDF times the cosine over the probability of the direction. Unless
+    /// overridden, directions are picked by the cosine of their angle to the
+    /// normal.
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        let direction = Frame::around(hit.normal).to_world(math::cosine_hemisphere(rng.next_f32(), rng.next_f32()));
+        bounce(self, ray, hit, direction)
+    }
+
+    /// Whether `sample` picking `direction` is more likely a glossy
+    /// reflection, like a highlight, than a diffuse one, for renders that
+    /// limit how many of each a path has.
+    fn glossy(&self, _ray: &Ray, _hit: &Hit, _direction: Vector3<f32>) -> bool {
+        false
+    }
+
+    /// Gives each transparent medium the material is made of an id of its
+    /// own, unless it has one, so rays can tell which they are inside.
+    /// Scenes do this for every material they are given.
+    fn number_media(&self) {}
+}
+
+/// A matte surface: the hit's color under ambient light, plus a diffuse
+/// term that falls off with the angle to the light. Each is scaled by its
+/// own coefficient.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambert {
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+}
+impl Lambert {
+    pub fn new(diffuse: Vector3<f32>) -> Lambert {
+        Lambert::with_ambient(vec3(1.0, 1.0, 1.0), diffuse)
+    }
+
+    pub fn with_ambient(ambient: Vector3<f32>, diffuse: Vector3<f32>) -> Lambert {
+        Lambert {
+            ambient: ambient,
+            diffuse: diffuse,
+        }
+    }
+}
+impl Default for Lambert {
+    fn default() -> Lambert {
+        Lambert::new(vec3(0.5, 0.4, 0.5))
+    }
+}
+
+impl Material for Lambert {
+    fn shade(&self, _ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        let lambert = light.map_or(0.0, |l| hit.normal.dot(l).max(0.0));
+        hit.color * (self.ambient * ambient + self.diffuse * lambert)
+    }
+}
+
+/// A neutral gray matte surface that ignores the hit's color, for looking
+/// at a scene's lighting and shapes apart from its materials.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clay;
+
+impl Material for Clay {
+    fn shade(&self, _ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        let lambert = light.map_or(0.0, |l| hit.normal.dot(l).max(0.0));
+        (ambient + vec3(1.0, 1.0, 1.0) * lambert) * 0.7
+    }
+}
+
+/// How `Phong` places its highlight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    /// Around the mirror direction of the light.
+    Phong,
+    /// Where the normal meets the half vector between the light and the
+    /// viewer. Cheaper, and what older scenes were tuned for; it needs about
+    /// four times the shininess for a highlight of the same size.
+    BlinnPhong,
+}
+
+/// A shiny, plastic-looking surface: Lambert's diffuse term plus a
+/// highlight, tighter the higher `shininess` is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phong {
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+    pub shininess: f32,
+    pub highlight: Highlight,
+}
+impl Phong {
+    pub fn new(diffuse: Vector3<f32>, specular: Vector3<f32>, shininess: f32) -> Phong {
+        Phong {
+            ambient: vec3(1.0, 1.0, 1.0),
+            diffuse: diffuse,
+            specular: specular,
+            shininess: shininess,
+            highlight: Highlight::Phong,
+        }
+    }
+
+    pub fn blinn(diffuse: Vector3<f32>, specular: Vector3<f32>, shininess: f32) -> Phong {
+        Phong { highlight: Highlight::BlinnPhong, ..Phong::new(diffuse, specular, shininess) }
+    }
+
+    // The chance of sampling the highlight rather than the diffuse term, and
+    // the exponent of the cosine lobe around the mirror direction sampled.
+    fn lobe(&self, hit: &Hit) -> (f32, f32) {
+        let mean = |v: Vector3<f32>| (v.x + v.y + v.z) / 3.0;
+        let (specular, diffuse) = (mean(self.specular), mean(hit.color * self.diffuse));
+        let odds = if specular + diffuse > 0.0 { specular / (specular + diffuse) } else { 0.0 };
+        let exponent = match self.highlight {
+            Highlight::Phong => self.shininess,
+            Highlight::BlinnPhong => self.shininess / 4.0,
+        };
+        (odds, exponent)
+    }
+}
+
+impl Material for Phong {
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        let diffuse = Lambert::with_ambient(self.ambient, self.diffuse).shade(ray, hit, light, ambient);
+        let light = match light {
+            Some(l) if hit.normal.dot(l) > 0.0 => l,
+            _ => return diffuse,
+        };
+        let view = -ray.direction;
+        let highlight = match self.highlight {
+            Highlight::Phong => math::reflect(light, hit.normal).dot(view),
+            Highlight::BlinnPhong => hit.normal.dot((light + view).normalize()),
+        }.max(0.0);
+        diffuse + self.specular * highlight.powf(self.shininess)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        let (odds, exponent) = self.lobe(hit);
+        let cos = math::reflect(-ray.direction, hit.normal).dot(direction).max(0.0);
+        let lobe = (exponent + 1.0) / (2.0 * PI) * cos.powf(exponent);
+        odds * lobe + (1.0 - odds) * hit.normal.dot(direction).max(0.0) / PI
+    }
+
+    fn glossy(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> bool {
+        let (odds, exponent) = self.lobe(hit);
+        let cos = math::reflect(-ray.direction, hit.normal).dot(direction).max(0.0);
+        odds * (exponent + 1.0) / (2.0 * PI) * cos.powf(exponent) > (1.0 - odds) * hit.normal.dot(direction).max(0.0) / PI
+    }
+
+    // Directions around the mirror direction as often as the highlight is
+    // bright compared to the diffuse term, and by the cosine otherwise.
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        let (odds, exponent) = self.lobe(hit);
+        let (u, v) = (rng.next_f32(), rng.next_f32());
+        let direction = if rng.next_f32() < odds {
+            let cos = u.powf(1.0 / (exponent + 1.0));
+            let sin = (1.0 - cos * cos).max(0.0).sqrt();
+            let phi = 2.0 * PI * v;
+            let mirror = Frame::around(math::reflect(-ray.direction, hit.normal));
+            mirror.to_world(vec3(sin * phi.cos(), sin * phi.sin(), cos))
+        } else {
+            Frame::around(hit.normal).to_world(math::cosine_hemisphere(u, v))
+        };
+        bounce(self, ray, hit, direction)
+    }
+}
+
+/// A mirror-like surface. `reflectivity` of what is seen comes from the
+/// reflected ray, the rest from Lambert shading of the surface itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mirror {
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub reflectivity: f32,
+}
+impl Mirror {
+    pub fn new(diffuse: Vector3<f32>, reflectivity: f32) -> Mirror {
+        Mirror {
+            ambient: vec3(1.0, 1.0, 1.0),
+            diffuse: diffuse,
+            reflectivity: reflectivity,
+        }
+    }
+}
+
+impl Material for Mirror {
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        Lambert::with_ambient(self.ambient, self.diffuse).shade(ray, hit, light, ambient) * (1.0 - self.reflectivity)
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, _rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        if self.reflectivity <= 0.0 { return vec![]; }
+        reflected(ray, hit, hit.normal).into_iter().map(|r| (r, vec3(1.0, 1.0, 1.0) * self.reflectivity)).collect()
+    }
+}
+
+/// A clear, transmissive surface like glass or water. Rays are bent by
+/// Snell's law going in and out, and reflected where they can't get out.
+/// Elsewhere what is seen is part reflection, part refraction, with more
+/// reflection towards grazing angles.
+///
+/// Where transparent objects overlap, like water filling a glass, the one
+/// with the highest `priority` is what the overlap is made of; the surfaces
+/// of the others inside it are passed straight through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glass {
+    // index of refraction, against the vacuum outside
+    pub ior: f32,
+    pub priority: u32,
+    // how much of each channel is absorbed per unit of distance travelled
+    // inside, so thick parts are darker than thin ones
+    pub absorption: Vector3<f32>,
+    // 0 for clear glass; above it, rays are bent by microfacets picked at
+    // random, blurring what is seen through it
+    pub roughness: f32,
+    id: MediumId,
+}
+impl Glass {
+    pub fn new(ior: f32) -> Glass {
+        Glass::nested(ior, 0)
+    }
+
+    pub fn nested(ior: f32, priority: u32) -> Glass {
+        Glass {
+            ior: ior,
+            priority: priority,
+            absorption: vec3(0.0, 0.0, 0.0),
+            roughness: 0.0,
+            id: MediumId::default(),
+        }
+    }
+
+    /// Frosted glass, with GGX microfacets of the given roughness.
+    pub fn frosted(ior: f32, roughness: f32) -> Glass {
+        Glass { roughness: roughness, ..Glass::new(ior) }
+    }
+
+    /// Colored glass, absorbing light by the Beer-Lambert law.
+    pub fn tinted(ior: f32, absorption: Vector3<f32>) -> Glass {
+        Glass { absorption: absorption, ..Glass::new(ior) }
+    }
+
+    fn medium(&self) -> Medium {
+        Medium {
+            id: self.id.get(),
+            ior: self.ior,
+            priority: self.priority,
+            absorption: self.absorption,
+            extinction: vec3(0.0, 0.0, 0.0),
+            albedo: vec3(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Material for Glass {
+    fn shade(&self, _ray: &Ray, _hit: &Hit, _light: Option<Vector3<f32>>, _ambient: Vector3<f32>) -> Vector3<f32> {
+        vec3(0.0, 0.0, 0.0)
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        let white = vec3(1.0, 1.0, 1.0);
+        let medium = self.medium();
+        let beyond = if hit.front { ray.media.with(medium) } else { ray.media.without(medium) };
+
+        // the surface of something inside a medium that outranks it
+        if ray.media.outranks(medium) {
+            let point = ray.point_at(hit.t) + ray.direction * EPSILON;
+            return vec![(Ray::new(point, ray.direction).at_time(ray.time).in_media(beyond), white)];
+        }
+
+        // the facet of a rough surface the ray meets, and how much of what
+        // leaves it in a direction it lets through
+        let normal = hit.normal;
+        let frame = Frame::around(normal);
+        let (facet, ggx) = if self.roughness > 0.0 {
+            let ggx = Ggx::new(self.roughness);
+            (ggx.sample(&frame, rng.next_f32(), rng.next_f32()), Some(ggx))
+        } else {
+            (normal, None)
+        };
+        let through = |r: &Ray| match ggx {
+            Some(ggx) => {
+                let g = ggx.g(&frame, ray.direction, r.direction);
+                white * (ray.direction.dot(facet).abs() * g / (ray.direction.dot(normal).abs() * facet.dot(normal)))
+            }
+            None => white,
+        };
+
+        let (n1, n2) = (ray.media.ior(), beyond.ior());
+        let reflected = reflected(ray, hit, facet);
+        let refracted = match refracted(ray, hit, facet, n1 / n2) {
+            Some(refracted) => refracted.in_media(beyond),
+            None => return reflected.into_iter().map(|r| { let w = through(&r); (r, w) }).collect(),
+        };
+        // Schlick's approximation takes the angle on the less dense side
+        let outside = if n1 <= n2 { ray.direction } else { refracted.direction };
+        let reflectance = schlick(facet.dot(outside).abs(), n1, n2);
+        let mut rays = vec![];
+        if let Some(r) = reflected {
+            let w = through(&r) * reflectance;
+            rays.push((r, w));
+        }
+        let w = through(&refracted) * (1.0 - reflectance);
+        rays.push((refracted, w));
+        rays
+    }
+
+    fn number_media(&self) {
+        self.id.number();
+    }
+}
+
+/// A physically based surface in glTF's metallic-roughness convention, so
+/// imported assets look as they do elsewhere. The base color is multiplied
+/// with the hit's. Metals take their specular color from it and have no
+/// diffuse term; everything else reflects 4% head on, more at grazing angles.
+/// Lights count as delivering enough light to show a white surface facing
+/// them as white.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pbr {
+    pub base_color: Vector3<f32>,
+    pub metallic: f32,
+    pub roughness: f32,
+    // from 0 to 1, how much rougher the surface is along `tangent`, which
+    // stretches highlights that way as on brushed metal
+    pub anisotropy: f32,
+    // projected onto the surface wherever it is hit
+    pub tangent: Vector3<f32>,
+    // the color of a layer of fibers over the surface, like the fuzz on
+    // velvet, which shines at grazing angles; black for none
+    pub sheen: Vector3<f32>,
+    pub sheen_roughness: f32,
+    // from 0 to 1, how much of the surface is under a thin film, like soap
+    // or oil, whose reflections interfere into shifting colors
+    pub iridescence: f32,
+    pub film_ior: f32,
+    // in nanometres, around the wavelengths of visible light
+    pub film_thickness: f32,
+}
+impl Pbr {
+    pub fn new(base_color: Vector3<f32>, metallic: f32, roughness: f32) -> Pbr {
+        Pbr {
+            base_color: base_color,
+            metallic: metallic,
+            roughness: roughness,
+            anisotropy: 0.0,
+            tangent: Vector3::unit_x(),
+            sheen: vec3(0.0, 0.0, 0.0),
+            sheen_roughness: 0.0,
+            iridescence: 0.0,
+            film_ior: 1.3,
+            film_thickness: 400.0,
+        }
+    }
+
+    /// Brushed along `tangent`, as glTF's anisotropy extension has it.
+    pub fn brushed(base_color: Vector3<f32>, metallic: f32, roughness: f32, anisotropy: f32, tangent: Vector3<f32>) -> Pbr {
+        Pbr { anisotropy: anisotropy, tangent: tangent, ..Pbr::new(base_color, metallic, roughness) }
+    }
+
+    /// Cloth: a rough, dielectric base under a sheen, as glTF's sheen
+    /// extension has it.
+    pub fn fabric(base_color: Vector3<f32>, sheen: Vector3<f32>, sheen_roughness: f32) -> Pbr {
+        Pbr { sheen: sheen, sheen_roughness: sheen_roughness, ..Pbr::new(base_color, 0.0, 1.0) }
+    }
+
+    /// Under a thin film, as glTF's iridescence extension has it.
+    pub fn iridescent(base_color: Vector3<f32>, metallic: f32, roughness: f32, film_ior: f32, film_thickness: f32) -> Pbr {
+        Pbr {
+            iridescence: 1.0,
+            film_ior: film_ior,
+            film_thickness: film_thickness,
+            ..Pbr::new(base_color, metallic, roughness)
+        }
+    }
+
+    // the color reflected head on
+    fn f0(&self, base: Vector3<f32>) -> Vector3<f32> {
+        vec3(0.04, 0.04, 0.04) * (1.0 - self.metallic) + base * self.metallic
+    }
+
+    // `reflectance` of the surface at an angle with cosine `cos`, with the
+    // part under the film replaced by the film's
+    fn film(&self, reflectance: Vector3<f32>, cos: f32, f0: Vector3<f32>) -> Vector3<f32> {
+        if self.iridescence <= 0.0 { return reflectance; }
+        let film = thin_film(cos, self.film_ior, self.film_thickness, f0);
+        reflectance * (1.0 - self.iridescence) + film * self.iridescence
+    }
+
+    // The distribution of the surface's microfacets, and the frame it is
+    // laid out in where it is hit.
+    fn microfacets(&self, hit: &Hit) -> (Ggx, Frame) {
+        if self.anisotropy > 0.0 {
+            let alpha = self.roughness * self.roughness;
+            let along = (alpha + (1.0 - alpha) * self.anisotropy * self.anisotropy).sqrt();
+            (Ggx::anisotropic(along, self.roughness), Frame::with_tangent(hit.normal, self.tangent))
+        } else {
+            (Ggx::new(self.roughness), Frame::around(hit.normal))
+        }
+    }
+
+    // The chance of sampling the specular term rather than the diffuse one.
+    fn specular_odds(&self, ray: &Ray, hit: &Hit) -> f32 {
+        let mean = |v: Vector3<f32>| (v.x + v.y + v.z) / 3.0;
+        let base = hit.color * self.base_color;
+        let specular = mean(fresnel(self.f0(base), hit.normal.dot(-ray.direction).max(0.0)));
+        let diffuse = mean(base * (1.0 - self.metallic));
+        if specular + diffuse > 0.0 { specular / (specular + diffuse) } else { 0.0 }
+    }
+}
+
+impl Material for Pbr {
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        let base = hit.color * self.base_color;
+        let diffuse = base * (1.0 - self.metallic);
+        let f0 = self.f0(base);
+        let lit = (diffuse + self.film(f0, hit.normal.dot(-ray.direction).abs(), f0)) * ambient;
+        let light = match light {
+            Some(l) if hit.normal.dot(l) > 0.0 => l,
+            _ => return lit,
+        };
+
+        // the Cook-Torrance microfacet BRDF, times pi for the light
+        let view = -ray.direction;
+        let half = (light + view).normalize();
+        let (cos_light, cos_view) = (hit.normal.dot(light), hit.normal.dot(view).max(1e-4));
+        let (ggx, frame) = self.microfacets(hit);
+        let highlight = ggx.d(&frame, half) * ggx.g(&frame, light, view) / (4.0 * cos_light * cos_view);
+        let cos = view.dot(half).max(0.0);
+        let specular = self.film(fresnel(f0, cos), cos, f0) * (highlight * PI);
+        let mut reflected = diffuse + specular;
+        if self.sheen != vec3(0.0, 0.0, 0.0) {
+            let charlie = Charlie::new(self.sheen_roughness);
+            let frame = Frame::around(hit.normal);
+            reflected = reflected + self.sheen * (charlie.d(&frame, half) * charlie.v(&frame, light, view) * PI);
+        }
+        lit + reflected * cos_light
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        let cos = hit.normal.dot(direction);
+        if cos <= 0.0 { return 0.0; }
+        let (ggx, frame) = self.microfacets(hit);
+        let view = -ray.direction;
+        let half = (direction + view).normalize();
+        let specular = ggx.d(&frame, half) * hit.normal.dot(half).max(0.0) / (4.0 * view.dot(half).abs().max(1e-4));
+        let odds = self.specular_odds(ray, hit);
+        odds * specular + (1.0 - odds) * cos / PI
+    }
+
+    fn glossy(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> bool {
+        let (ggx, frame) = self.microfacets(hit);
+        let view = -ray.direction;
+        let half = (direction + view).normalize();
+        let specular = ggx.d(&frame, half) * hit.normal.dot(half).max(0.0) / (4.0 * view.dot(half).abs().max(1e-4));
+        let odds = self.specular_odds(ray, hit);
+        odds * specular > (1.0 - odds) * hit.normal.dot(direction).max(0.0) / PI
+    }
+
+    // Microfacet normals by GGX as often as the specular term is bright
+    // compared to the diffuse one, and directions by the cosine otherwise.
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        let odds = self.specular_odds(ray, hit);
+        let (u, v) = (rng.next_f32(), rng.next_f32());
+        let direction = if rng.next_f32() < odds {
+            let (ggx, frame) = self.microfacets(hit);
+            math::reflect(-ray.direction, ggx.sample(&frame, u, v))
+        } else {
+            Frame::around(hit.normal).to_world(math::cosine_hemisphere(u, v))
+        };
+        bounce(self, ray, hit, direction)
+    }
+}
+
+/// A glowing surface, like a lamp or hot metal, that gives off `color` times
+/// `intensity` times the hit's color and reflects nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emissive {
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+}
+impl Emissive {
+    pub fn new(color: Vector3<f32>, intensity: f32) -> Emissive {
+        Emissive {
+            color: color,
+            intensity: intensity,
+        }
+    }
+}
+
+impl Material for Emissive {
+    fn shade(&self, _ray: &Ray, _hit: &Hit, _light: Option<Vector3<f32>>, _ambient: Vector3<f32>) -> Vector3<f32> {
+        vec3(0.0, 0.0, 0.0)
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &Hit) -> Vector3<f32> {
+        hit.color * self.color * self.intensity
+    }
+
+    fn emits(&self) -> bool {
+        self.intensity > 0.0 && self.color != vec3(0.0, 0.0, 0.0)
+    }
+}
+
+/// A translucent surface like wax, marble or skin. Light goes in through a
+/// matte surface and wanders about inside, scattered at random, until it
+/// comes out again wherever it does, lit by what shines on it there. How far
+/// light goes between scatterings on average is set per channel, so red
+/// can carry further than blue as it does in skin; `color` is how much of
+/// it is left after each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subsurface {
+    pub color: Vector3<f32>,
+    pub mean_free_path: Vector3<f32>,
+    id: MediumId,
+}
+impl Subsurface {
+    pub fn new(color: Vector3<f32>, mean_free_path: Vector3<f32>) -> Subsurface {
+        Subsurface {
+            color: color,
+            mean_free_path: mean_free_path,
+            id: MediumId::default(),
+        }
+    }
+
+    fn medium(&self) -> Medium {
+        let p = self.mean_free_path;
+        Medium {
+            id: self.id.get(),
+            ior: 1.0,
+            priority: 0,
+            absorption: vec3(0.0, 0.0, 0.0),
+            extinction: vec3(1.0 / p.x.max(1e-6), 1.0 / p.y.max(1e-6), 1.0 / p.z.max(1e-6)),
+            albedo: self.color,
+        }
+    }
+}
+
+impl Material for Subsurface {
+    // Seen from outside, all of the surface's light comes from inside; seen
+    // from inside, where a walk comes out, it is lit as a matte surface.
+    fn shade(&self, _ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        if hit.front { return vec3(0.0, 0.0, 0.0); }
+        let lambert = light.map_or(0.0, |l| (-hit.normal).dot(l).max(0.0));
+        hit.color * (ambient + vec3(1.0, 1.0, 1.0) * lambert)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        if hit.front { return 0.0; }
+        (-hit.normal).dot(direction).max(0.0) / PI
+    }
+
+    // Where a walk comes out, light comes in from outside.
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        if hit.front { return None; }
+        let outward = -hit.normal;
+        let direction = Frame::around(outward).to_world(math::cosine_hemisphere(rng.next_f32(), rng.next_f32()));
+        let point = ray.point_at(hit.t) + outward * EPSILON;
+        let pdf = outward.dot(direction).max(0.0) / PI;
+        Some((Ray::new(point, direction).at_time(ray.time).in_media(ray.media.without(self.medium())).sampled(pdf), hit.color))
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        if !hit.front { return vec![]; }
+        let inward = -hit.normal;
+        let direction = Frame::around(inward).to_world(math::cosine_hemisphere(rng.next_f32(), rng.next_f32()));
+        let point = ray.point_at(hit.t) + inward * EPSILON;
+        vec![(Ray::new(point, direction).at_time(ray.time).in_media(ray.media.with(self.medium())), vec3(1.0, 1.0, 1.0))]
+    }
+
+    fn number_media(&self) {
+        self.id.number();
+    }
+}
+
+/// Different materials on the outside and the inside of a surface, like the
+/// two faces of a leaf. Every other material looks the same from both sides.
+#[derive(Debug)]
+pub struct TwoSided {
+    pub front: Arc<dyn Material>,
+    pub back: Arc<dyn Material>,
+}
+impl TwoSided {
+    pub fn new(front: Arc<dyn Material>, back: Arc<dyn Material>) -> TwoSided {
+        TwoSided {
+            front: front,
+            back: back,
+        }
+    }
+
+    /// Black from behind, which shows up surfaces facing the wrong way.
+    pub fn one_sided(front: Arc<dyn Material>) -> TwoSided {
+        let black = vec3(0.0, 0.0, 0.0);
+        TwoSided::new(front, Arc::new(Lambert::with_ambient(black, black)))
+    }
+
+    fn side(&self, hit: &Hit) -> &dyn Material {
+        if hit.front { &*self.front } else { &*self.back }
+    }
+}
+
+impl Material for TwoSided {
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        self.side(hit).shade(ray, hit, light, ambient)
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        self.side(hit).scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+        self.side(hit).emitted(ray, hit)
+    }
+
+    fn emits(&self) -> bool {
+        self.front.emits() || self.back.emits()
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> Vector3<f32> {
+        self.side(hit).eval(ray, hit, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        self.side(hit).pdf(ray, hit, direction)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        self.side(hit).sample(ray, hit, rng)
+    }
+
+    fn glossy(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> bool {
+        self.side(hit).glossy(ray, hit, direction)
+    }
+
+    fn number_media(&self) {
+        self.front.number_media();
+        self.back.number_media();
+    }
+}
+
+/// A material in a scene's library, shared by everything that uses it by
+/// name. Defining the name again changes it for all of them at once.
+#[derive(Debug)]
+pub struct Shared {
+    material: RwLock<Arc<dyn Material>>,
+}
+impl Shared {
+    pub fn new(material: Arc<dyn Material>) -> Shared {
+        Shared { material: RwLock::new(material) }
+    }
+
+    pub fn get(&self) -> Arc<dyn Material> {
+        self.material.read().unwrap().clone()
+    }
+
+    pub fn set(&self, material: Arc<dyn Material>) {
+        *self.material.write().unwrap() = material;
+    }
+}
+
+impl Material for Shared {
+    fn shade(&self, ray: &Ray, hit: &Hit, light: Option<Vector3<f32>>, ambient: Vector3<f32>) -> Vector3<f32> {
+        self.get().shade(ray, hit, light, ambient)
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vec<(Ray, Vector3<f32>)> {
+        self.get().scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Vector3<f32> {
+        self.get().emitted(ray, hit)
+    }
+
+    fn emits(&self) -> bool {
+        self.get().emits()
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> Vector3<f32> {
+        self.get().eval(ray, hit, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> f32 {
+        self.get().pdf(ray, hit, direction)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Option<(Ray, Vector3<f32>)> {
+        self.get().sample(ray, hit, rng)
+    }
+
+    fn glossy(&self, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> bool {
+        self.get().glossy(ray, hit, direction)
+    }
+
+    fn number_media(&self) {
+        self.get().number_media();
+    }
+}
+
+// Which medium a transparent material is, numbered once it is given to a
+// scene and kept by every copy of it made after that. Zero until then.
+#[derive(Debug, Default)]
+struct MediumId(AtomicUsize);
+impl MediumId {
+    fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn number(&self) {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        if self.get() == 0 {
+            let _ = self.0.compare_exchange(0, NEXT.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Clone for MediumId {
+    fn clone(&self) -> MediumId {
+        MediumId(AtomicUsize::new(self.get()))
+    }
+}
+
+impl PartialEq for MediumId {
+    fn eq(&self, other: &MediumId) -> bool {
+        self.get() == other.get()
+    }
+}
+
+/// A transparent medium a ray is inside of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Medium {
+    // which material it is
+    id: usize,
+    ior: f32,
+    priority: u32,
+    absorption: Vector3<f32>,
+    // for media that scatter light, how likely it is to be scattered per
+    // unit of distance, and how much of it is left when it is; zero for
+    // media that only absorb
+    extinction: Vector3<f32>,
+    albedo: Vector3<f32>,
+}
+
+/// The transparent media a ray is travelling through, in the order it
+/// entered them. Outside of all of them is a vacuum.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Media(Vec<Medium>);
+impl Media {
+    /// The index of refraction where the ray is: that of the medium with the
+    /// highest priority, or the latest entered of those.
+    pub fn ior(&self) -> f32 {
+        self.top().map_or(1.0, |m| m.ior)
+    }
+
+    /// How much of each channel of the light is left after `distance`
+    /// through the medium the ray is in.
+    pub fn transmittance(&self, distance: f32) -> Vector3<f32> {
+        let a = self.top().map_or(vec3(0.0, 0.0, 0.0), |m| m.absorption);
+        vec3((-a.x * distance).exp(), (-a.y * distance).exp(), (-a.z * distance).exp())
+    }
+
+    /// The extinction and albedo of the medium the ray is in, if it is one
+    /// that scatters light.
+    pub fn scattering(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.top().filter(|m| m.extinction != vec3(0.0, 0.0, 0.0)).map(|m| (m.extinction, m.albedo))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn top(&self) -> Option<Medium> {
+        self.0.iter().fold(None, |top: Option<Medium>, &m| match top {
+            Some(t) if t.priority > m.priority => Some(t),
+            _ => Some(m),
+        })
+    }
+
+    // Whether a medium other than `medium` has a higher priority than it.
+    fn outranks(&self, medium: Medium) -> bool {
+        self.0.iter().any(|m| m.id != medium.id && m.priority > medium.priority)
+    }
+
+    fn with(&self, medium: Medium) -> Media {
+        let mut media = self.clone();
+        media.0.push(medium);
+        media
+    }
+
+    fn without(&self, medium: Medium) -> Media {
+        let mut media = self.clone();
+        if let Some(i) = media.0.iter().rposition(|m| m.id == medium.id) {
+            media.0.remove(i);
+        }
+        media
+    }
+}
+
+// The ray a path tracer bounces off `hit` in `direction`, weighted by the
+// material's BRDF times the cosine over the probability of picking it, or
+// `None` if it carries no light.
+fn bounce<M: Material + ?Sized>(material: &M, ray: &Ray, hit: &Hit, direction: Vector3<f32>) -> Option<(Ray, Vector3<f32>)> {
+    let pdf = material.pdf(ray, hit, direction);
+    if !(pdf > 0.0) { return None; }
+    let weight = material.eval(ray, hit, direction) / pdf;
+    if weight == vec3(0.0, 0.0, 0.0) { return None; }
+    let point = ray.point_at(hit.t) + hit.normal * EPSILON;
+    Some((Ray::new(point, direction).at_time(ray.time).in_media(ray.media.clone()).sampled(pdf), weight))
+}
+
+// Schlick's approximation of the Fresnel reflectance going from a medium of
+// index `n1` into one of `n2`, for light at an angle with cosine `cos` to the
+// surface on the less dense side.
+fn schlick(cos: f32, n1: f32, n2: f32) -> f32 {
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
+// Schlick's approximation for a surface reflecting `f0` head on.
+fn fresnel(f0: Vector3<f32>, cos: f32) -> Vector3<f32> {
+    f0 + (vec3(1.0, 1.0, 1.0) - f0) * (1.0 - cos).powi(5)
+}
+
+// The reflectance of a film of index `ior` and `thickness` nanometres thick
+// over a surface reflecting `f0` head on, for light at an angle with cosine
+// `cos` to it. Light reflected off the film's top and off the surface below
+// interferes, by the Airy formula, at a wavelength standing in for each
+// channel. Metals are treated as dielectrics of the same `f0`.
+fn thin_film(cos: f32, ior: f32, thickness: f32, f0: Vector3<f32>) -> Vector3<f32> {
+    let sin2 = 1.0 - cos * cos;
+    let cos_film = (1.0 - sin2 / (ior * ior)).max(0.0).sqrt();
+    let channel = |f0: f32, wavelength: f32| {
+        let root = f0.sqrt().min(0.99);
+        let base = (1.0 + root) / (1.0 - root);
+        let cos_base = (1.0 - sin2 / (base * base)).max(0.0).sqrt();
+        let phase = 4.0 * PI * ior * thickness * cos_film / wavelength;
+        let airy = |r12: f32, r23: f32| {
+            let cross = 2.0 * r12 * r23 * phase.cos();
+            (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+        };
+        // the average over both polarizations
+        let s = airy((cos - ior * cos_film) / (cos + ior * cos_film),
+                     (ior * cos_film - base * cos_base) / (ior * cos_film + base * cos_base));
+        let p = airy((ior * cos - cos_film) / (ior * cos + cos_film),
+                     (base * cos_film - ior * cos_base) / (base * cos_film + ior * cos_base));
+        (s + p) / 2.0
+    };
+    vec3(channel(f0.x, 650.0), channel(f0.y, 532.0), channel(f0.z, 450.0))
+}
+
+// The ray mirrored off `facet` of the hit's surface, still in the media it
+// was in, or `None` if that would send it through the surface.
+fn reflected(ray: &Ray, hit: &Hit, facet: Vector3<f32>) -> Option<Ray> {
+    let normal = hit.normal;
+    let direction = math::reflect(-ray.direction, facet);
+    if direction.dot(normal) <= 0.0 { return None; }
+    Some(Ray::new(ray.point_at(hit.t) + normal * EPSILON, direction).at_time(ray.time).in_media(ray.media.clone()))
+}
+
+// The ray bent through `facet` of the hit's surface, where the ratio of the
+// indices of refraction is `eta`, or `None` on total internal reflection or
+// if it would come back out on the side it came from.
+fn refracted(ray: &Ray, hit: &Hit, facet: Vector3<f32>, eta: f32) -> Option<Ray> {
+    let normal = hit.normal;
+    let cos_i = -facet.dot(ray.direction);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 { return None; }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let direction = ray.direction * eta + facet * (eta * cos_i - cos_t);
+    if direction.dot(normal) >= 0.0 { return None; }
+    Some(Ray::new(ray.point_at(hit.t) + normal * -EPSILON, direction).at_time(ray.time))
+}
+
+/// An object shaded with its own material instead of the scene's default.
+#[derive(Debug)]
+pub struct WithMaterial<T> {
+    object: T,
+    material: Arc<dyn Material>,
+}
+impl<T: Intersect> WithMaterial<T> {
+    pub fn new(object: T, material: Arc<dyn Material>) -> WithMaterial<T> {
+        WithMaterial {
+            object: object,
+            material: material,
+        }
+    }
+}
+
+impl<T: Intersect> Intersect for WithMaterial<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        self.object.intersect(ray).map(|hit| Hit { material: Some(self.material.clone()), ..hit })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.object.bounds()
+    }
+
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        self.object.sample_surface(u, v, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use std::sync::Arc;
+
+    use super::*;
+    use super::super::{Hit, Ray};
+    use super::super::rng::Rng;
+
+    #[test]
+    fn copies_of_a_glass_are_the_same_medium() {
+        let glass = Glass::new(1.5);
+        glass.number_media();
+        let hit = |front| Hit { t: 1.0, normal: vec3(0.0, 0.0, 1.0), color: vec3(1.0, 1.0, 1.0), material: None, front: front };
+        let entering = Ray::new(Point3::new(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let (inside, _) = glass.scatter(&entering, &hit(true), &mut Rng::new(1)).pop().unwrap();
+        assert!(!inside.media.is_empty());
+        // leaving through a copy of it, wrapped up, still leaves the glass
+        let copy = TwoSided::new(Arc::new(glass.clone()), Arc::new(Shared::new(Arc::new(glass.clone()))));
+        let (outside, _) = copy.scatter(&inside, &hit(false), &mut Rng::new(1)).pop().unwrap();
+        assert!(outside.media.is_empty());
+        // while another glass is another medium
+        let other = Glass::new(1.5);
+        other.number_media();
+        let (still_inside, _) = other.scatter(&inside, &hit(false), &mut Rng::new(1)).pop().unwrap();
+        assert!(!still_inside.media.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use cgmath::*;
+
+use std::f64::consts::PI;
+
+// Coefficients and discriminants closer to zero than this are treated as zero.
+const ROOT_EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < ROOT_EPSILON
+}
+
+/// Real roots of `a x^2 + b x + c`, in no particular order.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if is_zero(a) {
+        return if is_zero(b) { vec![] } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        vec![]
+    } else if discriminant == 0.0 {
+        vec![-b / (2.0 * a)]
+    } else {
+        // avoid cancellation between -b and the square root
+        let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+        vec![q / a, c / q]
+    }
+}
+
+/// Real roots of `a x^3 + b x^2 + c x + d`, in no particular order.
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if is_zero(a) { return solve_quadratic(b, c, d); }
+
+    // normal form x^3 + A x^2 + B x + C, then substitute x = y - A/3 to
+    // eliminate the quadratic term: y^3 + 3p y + 2q = 0
+    let (a, b, c) = (b / a, c / a, d / a);
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let mut roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // three real roots
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + PI / 3.0).cos(), -t * (phi - PI / 3.0).cos()]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    for root in &mut roots {
+        *root -= a / 3.0;
+    }
+    roots
+}
+
+/// Real roots of `a x^4 + b x^3 + c x^2 + d x + e`, in no particular order.
+/// Uses Ferrari's method followed by Newton polishing, which recovers most of
+/// the precision lost to cancellation in the closed form.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if is_zero(a) { return solve_cubic(b, c, d, e); }
+
+    // normal form x^4 + A x^3 + B x^2 + C x + D, then substitute x = y - A/4
+    // to eliminate the cubic term: y^4 + p y^2 + q y + r = 0
+    let (qa, qb, qc, qd) = (b / a, c / a, d / a, e / a);
+    let sq_a = qa * qa;
+    let p = -3.0 / 8.0 * sq_a + qb;
+    let q = sq_a * qa / 8.0 - qa * qb / 2.0 + qc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * qb / 16.0 - qa * qc / 4.0 + qd;
+
+    let mut roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(1.0, 0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // one real root of the resolvent cubic splits the quartic into two
+        // quadratics
+        let z = solve_cubic(1.0, -p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) { 0.0 } else if u > 0.0 { u.sqrt() } else { return vec![]; };
+        let v = if is_zero(v) { 0.0 } else if v > 0.0 { v.sqrt() } else { return vec![]; };
+        let v = if q < 0.0 { -v } else { v };
+        let mut roots = solve_quadratic(1.0, v, z - u);
+        roots.extend(solve_quadratic(1.0, -v, z + u));
+        roots
+    };
+
+    for root in &mut roots {
+        let mut x = *root - qa / 4.0;
+        for _ in 0..2 {
+            let f = (((x + qa) * x + qb) * x + qc) * x + qd;
+            let df = ((4.0 * x + 3.0 * qa) * x + 2.0 * qb) * x + qc;
+            if df == 0.0 { break; }
+            x -= f / df;
+        }
+        *root = x;
+    }
+    roots
+}
+
+/// Slab test of a ray against the box `min`..`max`, taking the reciprocal
+/// of the ray direction so a traversal can compute it once per ray. Returns
+/// where the ray enters and leaves the box, if it overlaps it in front of
+/// the origin.
+pub fn ray_slabs(origin: Point3<f32>, inv_direction: Vector3<f32>, min: Point3<f32>, max: Point3<f32>) -> Option<(f32, f32)> {
+    let mut t_enter = f32::NEG_INFINITY;
+    let mut t_exit = f32::INFINITY;
+    for axis in 0..3 {
+        let inv = inv_direction[axis];
+        let mut t0 = (min[axis] - origin[axis]) * inv;
+        let mut t1 = (max[axis] - origin[axis]) * inv;
+        if inv < 0.0 { std::mem::swap(&mut t0, &mut t1); }
+        t_enter = t_enter.max(t0);
+        t_exit = t_exit.min(t1);
+    }
+    if t_exit < t_enter.max(0.0) { return None; }
+    Some((t_enter, t_exit))
+}
+
+/// Component-wise reciprocal of a ray direction, for `ray_slabs`.
+pub fn inverse_direction(direction: Vector3<f32>) -> Vector3<f32> {
+    vec3(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z)
+}
+
+/// `v` mirrored about the unit normal `n`. Both point away from the surface, or
+/// both at it.
+pub fn reflect(v: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
+    n * (2.0 * n.dot(v)) - v
+}
+
+/// Two unit vectors that, together with the unit vector `w`, form a
+/// right-handed orthonormal basis.
+pub fn orthonormal_basis(w: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if w.x.abs() > 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let u = helper.cross(w).normalize();
+    let v = w.cross(u);
+    (u, v)
+}
+
+/// Maps a point of the unit square onto the unit disc with Shirley's
+/// concentric mapping, which keeps stratified samples evenly spread.
+pub fn concentric_disc(u: f32, v: f32) -> (f32, f32) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let quarter = PI as f32 / 4.0;
+    let (r, theta) = if a.abs() > b.abs() { (a, quarter * (b / a)) } else { (b, 2.0 * quarter - quarter * (a / b)) };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Maps a point of the unit square onto the hemisphere around +z, denser
+/// towards the pole by the cosine of the angle to it.
+pub fn cosine_hemisphere(u: f32, v: f32) -> Vector3<f32> {
+    let (x, y) = concentric_disc(u, v);
+    vec3(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+/// Maps a point of the unit square evenly onto the unit sphere.
+pub fn uniform_sphere(u: f32, v: f32) -> Vector3<f32> {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI as f32 * v;
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps a point of the unit square evenly onto a regular polygon with
+/// `sides` corners on the unit circle, the first at angle `rotation`.
+pub fn regular_polygon(u: f32, v: f32, sides: usize, rotation: f32) -> (f32, f32) {
+    // `u` picks the wedge between the center and one edge, and what's
+    // left of it where in that wedge
+    let n = sides as f32;
+    let wedge = ((u * n) as usize).min(sides - 1);
+    let u = u * n - wedge as f32;
+    let corner = |i: usize| {
+        let angle = rotation + 2.0 * PI as f32 * i as f32 / n;
+        (angle.cos(), angle.sin())
+    };
+    let (a, b) = (corner(wedge), corner(wedge + 1));
+    let s = u.sqrt();
+    (s * ((1.0 - v) * a.0 + v * b.0), s * ((1.0 - v) * a.1 + v * b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every root found is one expected and every expected one is found,
+    // however many times a repeated root comes back
+    fn assert_roots(found: Vec<f64>, expected: &[f64]) {
+        let near = |a: f64, b: f64| (a - b).abs() < 1e-6;
+        assert!(found.iter().all(|&x| expected.iter().any(|&e| near(x, e))), "found {:?}, expected {:?}", found, expected);
+        assert!(expected.iter().all(|&e| found.iter().any(|&x| near(x, e))), "found {:?}, expected {:?}", found, expected);
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(solve_quadratic(1.0, -4.0, 3.0), &[1.0, 3.0]);
+        assert_roots(solve_quadratic(2.0, 0.0, -8.0), &[-2.0, 2.0]);
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn quadratic_repeated_root() {
+        assert_roots(solve_quadratic(1.0, -2.0, 1.0), &[1.0]);
+    }
+
+    #[test]
+    fn quadratic_near_zero_leading_coefficient() {
+        assert_roots(solve_quadratic(1e-12, 2.0, -4.0), &[2.0]);
+        assert_roots(solve_quadratic(1e-12, 1e-12, 1.0), &[]);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        assert_roots(solve_cubic(1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        assert_roots(solve_cubic(2.0, 0.0, 0.0, -16.0), &[2.0]);
+        assert_roots(solve_cubic(1.0, 0.0, 1.0, 0.0), &[0.0]);
+    }
+
+    #[test]
+    fn cubic_repeated_roots() {
+        assert_roots(solve_cubic(1.0, -4.0, 5.0, -2.0), &[1.0, 2.0]);
+        assert_roots(solve_cubic(1.0, -6.0, 12.0, -8.0), &[2.0]);
+    }
+
+    #[test]
+    fn cubic_near_zero_leading_coefficient() {
+        assert_roots(solve_cubic(1e-12, 1.0, -4.0, 3.0), &[1.0, 3.0]);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        assert_roots(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]);
+        assert_roots(solve_quartic(1.0, 0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]);
+        assert_roots(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0), &[]);
+        assert_roots(solve_quartic(1.0, -1.0, 0.0, 0.0, 0.0), &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn quartic_repeated_roots() {
+        assert_roots(solve_quartic(1.0, 0.0, -2.0, 0.0, 1.0), &[-1.0, 1.0]);
+        assert_roots(solve_quartic(1.0, -6.0, 13.0, -12.0, 4.0), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn quartic_near_zero_leading_coefficient() {
+        assert_roots(solve_quartic(1e-12, 1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+    }
+}
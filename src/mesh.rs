@@ -0,0 +1,231 @@
+use cgmath::*;
+
+use std::io::{self, Read, Write};
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::cache::{read_f32, read_point, read_u64, write_f32, write_point, write_u64};
+
+/// An indexed triangle mesh. `normals` are per-vertex and share the indices
+/// of `positions`; faces index into both.
+#[derive(Debug)]
+pub struct Mesh {
+    positions: Vec<Point3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    faces: Vec<[usize; 3]>,
+    color: Vector3<f32>,
+    // the total area of the faces up to and including each
+    areas: Vec<f32>,
+    bounds: Option<Aabb>,
+    bvh: Bvh,
+}
+impl Mesh {
+    /// Builds a mesh. If `normals` is empty, smooth vertex normals are
+    /// computed by averaging the normals of the faces around each vertex.
+    pub fn new(positions: Vec<Point3<f32>>, normals: Vec<Vector3<f32>>, faces: Vec<[usize; 3]>, color: Vector3<f32>) -> Mesh {
+        let normals = if normals.is_empty() {
+            vertex_normals(&positions, &faces)
+        } else {
+            assert_eq!(normals.len(), positions.len(), "mesh needs one normal per vertex");
+            normals.into_iter().map(|n| n.normalize()).collect()
+        };
+        let face_bounds: Vec<Aabb> = faces.iter()
+            .map(|f| Aabb::from_points(f.iter().map(|&i| positions[i])).unwrap())
+            .collect();
+        Mesh {
+            areas: cumulative_areas(&positions, &faces),
+            bounds: Aabb::from_points(positions.iter().cloned()),
+            bvh: Bvh::new(&face_bounds),
+            positions: positions,
+            normals: normals,
+            faces: faces,
+            color: color,
+        }
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u64(w, self.positions.len() as u64)?;
+        for (&p, n) in self.positions.iter().zip(&self.normals) {
+            write_point(w, p)?;
+            write_point(w, Point3::from_vec(*n))?;
+        }
+        write_u64(w, self.faces.len() as u64)?;
+        for face in &self.faces {
+            for &i in face {
+                write_u64(w, i as u64)?;
+            }
+        }
+        for &c in &[self.color.x, self.color.y, self.color.z] {
+            write_f32(w, c)?;
+        }
+        self.bvh.write(w)
+    }
+
+    /// Reads a mesh written by `write`, BVH included, so nothing is rebuilt.
+    pub fn read(r: &mut dyn Read) -> io::Result<Mesh> {
+        let mut positions = vec![];
+        let mut normals = vec![];
+        for _ in 0..read_u64(r)? {
+            positions.push(read_point(r)?);
+            normals.push(read_point(r)?.to_vec());
+        }
+        let mut faces = vec![];
+        for _ in 0..read_u64(r)? {
+            let mut face = [0; 3];
+            for i in face.iter_mut() {
+                *i = read_u64(r)? as usize;
+            }
+            if face.iter().any(|&i| i >= positions.len()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "mesh face out of range"));
+            }
+            faces.push(face);
+        }
+        let color = vec3(read_f32(r)?, read_f32(r)?, read_f32(r)?);
+        Ok(Mesh {
+            areas: cumulative_areas(&positions, &faces),
+            bounds: Aabb::from_points(positions.iter().cloned()),
+            bvh: Bvh::read(r, faces.len())?,
+            positions: positions,
+            normals: normals,
+            faces: faces,
+            color: color,
+        })
+    }
+}
+
+impl Intersect for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<(f32, usize, f32, f32)> = None;
+        self.bvh.closest(ray, f32::INFINITY, |i| {
+            let face = self.faces[i];
+            let hit = intersect_triangle(ray, self.positions[face[0]], self.positions[face[1]], self.positions[face[2]]);
+            match hit {
+                Some((t, u, v)) if closest.is_none_or(|c| t < c.0) => {
+                    closest = Some((t, i, u, v));
+                    Some(t)
+                }
+                _ => None,
+            }
+        });
+
+        closest.map(|(t, i, u, v)| {
+            let face = self.faces[i];
+            let normal = (self.normals[face[0]] * (1.0 - u - v) +
+                          self.normals[face[1]] * u +
+                          self.normals[face[2]] * v).normalize();
+            Hit { t: t, normal: normal, color: self.color, material: None, front: true }
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    // A face picked in proportion to its area, then a point spread evenly
+    // over it, with the normal shading there.
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        let total = *self.areas.last()?;
+        if !(total > 0.0) { return None; }
+        let target = u * total;
+        let i = self.areas.partition_point(|&a| a <= target).min(self.faces.len() - 1);
+        let before = if i == 0 { 0.0 } else { self.areas[i - 1] };
+        let u = ((target - before) / (self.areas[i] - before)).clamp(0.0, 1.0);
+
+        let face = self.faces[i];
+        let root = u.sqrt();
+        let (b1, b2) = (root * (1.0 - v), root * v);
+        let b0 = 1.0 - b1 - b2;
+        let point = Point3::from_vec(self.positions[face[0]].to_vec() * b0 + self.positions[face[1]].to_vec() * b1 + self.positions[face[2]].to_vec() * b2);
+        let normal = (self.normals[face[0]] * b0 + self.normals[face[1]] * b1 + self.normals[face[2]] * b2).normalize();
+        Some((point, normal, total))
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection. Returns the ray parameter and
+/// the barycentric coordinates of the hit relative to `p1` and `p2`.
+pub fn intersect_triangle(ray: &Ray, p0: Point3<f32>, p1: Point3<f32>, p2: Point3<f32>) -> Option<(f32, f32, f32)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < EPSILON * EPSILON { return None; }
+
+    let inv_det = 1.0 / det;
+    let s = ray.point - p0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) { return None; }
+
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 { return None; }
+
+    let t = edge2.dot(q) * inv_det;
+    if t < 0.0 { return None; }
+    Some((t, u, v))
+}
+
+fn cumulative_areas(positions: &[Point3<f32>], faces: &[[usize; 3]]) -> Vec<f32> {
+    let mut total = 0.0;
+    faces.iter().map(|face| {
+        total += (positions[face[1]] - positions[face[0]]).cross(positions[face[2]] - positions[face[0]]).length() * 0.5;
+        total
+    }).collect()
+}
+
+fn vertex_normals(positions: &[Point3<f32>], faces: &[[usize; 3]]) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::zero(); positions.len()];
+    for face in faces {
+        // the unnormalized cross product weights each face by its area
+        let n = (positions[face[1]] - positions[face[0]])
+            .cross(positions[face[2]] - positions[face[0]]);
+        for &i in face {
+            normals[i] = normals[i] + n;
+        }
+    }
+    normals.into_iter()
+        .map(|n| if n.length2() > 0.0 { n.normalize() } else { n })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a pyramid with a square base
+    fn pyramid() -> Mesh {
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0), Point3::new(0.5, 0.5, 1.0),
+        ];
+        let faces = vec![[0, 2, 1], [0, 3, 2], [0, 1, 4], [1, 2, 4], [2, 3, 4], [3, 0, 4]];
+        Mesh::new(positions, vec![], faces, vec3(0.2, 0.4, 0.6))
+    }
+
+    fn written(mesh: &Mesh) -> Vec<u8> {
+        let mut bytes = vec![];
+        mesh.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = pyramid();
+        let copy = Mesh::read(&mut &written(&mesh)[..]).unwrap();
+        assert_eq!(format!("{:?}", copy), format!("{:?}", mesh));
+    }
+
+    #[test]
+    fn rejects_faces_out_of_range() {
+        let mut mesh = pyramid();
+        mesh.faces[2][1] = 5;
+        let error = Mesh::read(&mut &written(&mesh)[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = written(&pyramid());
+        assert!(Mesh::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+
+// Samples taken across the smallest ball while searching for a crossing.
+const STEPS_PER_BALL: f32 = 8.0;
+const MAX_STEPS: usize = 1024;
+const REFINE_ITERATIONS: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ball {
+    center: Point3<f32>,
+    radius: f32,
+    strength: f32,
+}
+
+/// A blobby surface: each ball contributes `strength * (1 - r²/R²)³` to a
+/// field within its radius `R`, and the surface is where the summed field
+/// equals `threshold`.
+#[derive(Debug, PartialEq)]
+pub struct Metaballs {
+    balls: Vec<Ball>,
+    threshold: f32,
+    color: Vector3<f32>,
+}
+impl Metaballs {
+    pub fn new(threshold: f32, color: Vector3<f32>) -> Metaballs {
+        Metaballs {
+            balls: vec![],
+            threshold: threshold,
+            color: color,
+        }
+    }
+    pub fn add_ball(&mut self, center: Point3<f32>, radius: f32, strength: f32) -> &mut Metaballs {
+        self.balls.push(Ball { center: center, radius: radius, strength: strength });
+        self
+    }
+
+    fn field(&self, p: Point3<f32>) -> f32 {
+        self.balls.iter().fold(0.0, |sum, ball| {
+            let x = (p - ball.center).length2() / (ball.radius * ball.radius);
+            if x < 1.0 { sum + ball.strength * (1.0 - x).powi(3) } else { sum }
+        })
+    }
+
+    fn gradient(&self, p: Point3<f32>) -> Vector3<f32> {
+        self.balls.iter().fold(Vector3::zero(), |sum, ball| {
+            let r2 = ball.radius * ball.radius;
+            let offset = p - ball.center;
+            let x = offset.length2() / r2;
+            if x < 1.0 {
+                sum + offset * (-6.0 * ball.strength * (1.0 - x).powi(2) / r2)
+            } else {
+                sum
+            }
+        })
+    }
+}
+
+impl Intersect for Metaballs {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        // the field is zero outside every ball, so only search the stretch of
+        // the ray that passes through their spheres of influence
+        let mut t_min = f32::INFINITY;
+        let mut t_max = f32::NEG_INFINITY;
+        let mut smallest = f32::INFINITY;
+        for ball in &self.balls {
+            let l = ball.center - ray.point;
+            let v = l.dot(ray.direction);
+            let d2 = l.length2() - v * v;
+            let r2 = ball.radius * ball.radius;
+            if d2 > r2 { continue; }
+            let d = (r2 - d2).sqrt();
+            t_min = t_min.min(v - d);
+            t_max = t_max.max(v + d);
+            smallest = smallest.min(ball.radius);
+        }
+        let t_min = t_min.max(0.0);
+        if t_max <= t_min { return None; }
+
+        let step = (smallest / STEPS_PER_BALL).max((t_max - t_min) / MAX_STEPS as f32);
+        let g = |t: f32| self.field(ray.point_at(t)) - self.threshold;
+        let mut t0 = t_min;
+        let mut g0 = g(t0);
+        while t0 < t_max {
+            let t1 = (t0 + step).min(t_max);
+            let g1 = g(t1);
+            if g0.signum() != g1.signum() {
+                // bisect the bracketed crossing
+                let (mut lo, mut hi, mut g_lo) = (t0, t1, g0);
+                for _ in 0..REFINE_ITERATIONS {
+                    let mid = 0.5 * (lo + hi);
+                    let g_mid = g(mid);
+                    if g_mid.signum() == g_lo.signum() {
+                        lo = mid;
+                        g_lo = g_mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                let t = 0.5 * (lo + hi);
+                // the field falls off outward, so the outward normal opposes its gradient
+                let normal = -self.gradient(ray.point_at(t)).normalize();
+                return Some(Hit { t: t, normal: normal, color: self.color, material: None, front: true });
+            }
+            t0 = t1;
+            g0 = g1;
+        }
+        None
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.balls.iter().fold(None, |b: Option<Aabb>, ball| {
+            let sphere = Aabb::around_sphere(ball.center, ball.radius);
+            Some(b.map_or(sphere, |b| b.union(&sphere)))
+        })
+    }
+}
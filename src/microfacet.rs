@@ -0,0 +1,118 @@
+use cgmath::*;
+
+use std::f32::consts::PI;
+
+use super::math;
+
+/// An orthonormal frame on a surface, with the normal as its z axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub tangent: Vector3<f32>,
+    pub bitangent: Vector3<f32>,
+    pub normal: Vector3<f32>,
+}
+impl Frame {
+    /// A frame around `normal` with an arbitrary tangent, for surfaces that
+    /// look the same in every direction along them.
+    pub fn around(normal: Vector3<f32>) -> Frame {
+        let (tangent, bitangent) = math::orthonormal_basis(normal);
+        Frame { tangent: tangent, bitangent: bitangent, normal: normal }
+    }
+
+    /// A frame around `normal` with the tangent as close to `tangent` as the
+    /// surface allows.
+    pub fn with_tangent(normal: Vector3<f32>, tangent: Vector3<f32>) -> Frame {
+        let along = tangent - normal * normal.dot(tangent);
+        if along.length2() < 1e-12 { return Frame::around(normal); }
+        let tangent = along.normalize();
+        Frame { tangent: tangent, bitangent: normal.cross(tangent), normal: normal }
+    }
+
+    pub fn to_local(&self, v: Vector3<f32>) -> Vector3<f32> {
+        vec3(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    pub fn to_world(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals on a rough
+/// surface, with Smith's shadowing and masking. Roughness goes from 0, a
+/// perfect mirror, to 1, and can differ along a frame's tangent and
+/// bitangent, which stretches highlights like those on brushed metal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ggx {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+impl Ggx {
+    pub fn new(roughness: f32) -> Ggx {
+        Ggx::anisotropic(roughness, roughness)
+    }
+
+    pub fn anisotropic(roughness_x: f32, roughness_y: f32) -> Ggx {
+        // perceptually linear roughness; a tiny alpha keeps the density finite
+        let alpha = |r: f32| (r * r).max(1e-4);
+        Ggx { alpha_x: alpha(roughness_x), alpha_y: alpha(roughness_y) }
+    }
+
+    /// The density of microfacet normals at `m`.
+    pub fn d(&self, frame: &Frame, m: Vector3<f32>) -> f32 {
+        let m = frame.to_local(m);
+        if m.z <= 0.0 { return 0.0; }
+        let x = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * x * x)
+    }
+
+    /// The fraction of microfacets seen from direction `v`, on either side.
+    pub fn g1(&self, frame: &Frame, v: Vector3<f32>) -> f32 {
+        let v = frame.to_local(v);
+        let z2 = (v.z * v.z).max(1e-8);
+        let a2 = (self.alpha_x * v.x).powi(2) + (self.alpha_y * v.y).powi(2);
+        2.0 / (1.0 + (1.0 + a2 / z2).sqrt())
+    }
+
+    /// The fraction seen from both directions at once.
+    pub fn g(&self, frame: &Frame, a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        self.g1(frame, a) * self.g1(frame, b)
+    }
+
+    /// A microfacet normal picked by `u` and `v` in the unit square with
+    /// probability `d(m)` times its cosine to the normal.
+    pub fn sample(&self, frame: &Frame, u: f32, v: f32) -> Vector3<f32> {
+        // the isotropic distribution's slopes, stretched
+        let r = (u / (1.0 - u).max(1e-8)).sqrt();
+        let phi = 2.0 * PI * v;
+        let m = vec3(-self.alpha_x * r * phi.cos(), -self.alpha_y * r * phi.sin(), 1.0).normalize();
+        frame.to_world(m)
+    }
+}
+
+/// The "Charlie" distribution of Estevez and Kulla, for the fibers of cloth
+/// rather than facets: most of them lie flat along the surface, so it shines
+/// towards grazing angles instead of head on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Charlie {
+    alpha: f32,
+}
+impl Charlie {
+    pub fn new(roughness: f32) -> Charlie {
+        Charlie { alpha: (roughness * roughness).max(1e-3) }
+    }
+
+    /// The density of fiber normals at `m`.
+    pub fn d(&self, frame: &Frame, m: Vector3<f32>) -> f32 {
+        let cos = frame.to_local(m).z;
+        let sin2 = (1.0 - cos * cos).max(0.0);
+        let inverse = 1.0 / self.alpha;
+        (2.0 + inverse) * sin2.powf(inverse * 0.5) / (2.0 * PI)
+    }
+
+    /// Neubelt and Pettineo's visibility term, which stands in for the
+    /// masking and the BRDF's usual `1 / (4 cos_l cos_v)` together.
+    pub fn v(&self, frame: &Frame, a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        let (a, b) = (frame.to_local(a).z.max(0.0), frame.to_local(b).z.max(0.0));
+        1.0 / (4.0 * (a + b - a * b)).max(1e-4)
+    }
+}
@@ -0,0 +1,188 @@
+use cgmath::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use super::mesh::Mesh;
+
+/// Loads the positions, normals, and faces of a Wavefront `.obj` file into a
+/// single mesh. Polygons are fan-triangulated; texture coordinates, groups,
+/// and materials are ignored.
+pub fn load(path: &Path, color: Vector3<f32>) -> io::Result<Mesh> {
+    let file = File::open(path)?;
+    parse(BufReader::new(file), color)
+}
+
+fn parse<R: BufRead>(reader: R, color: Vector3<f32>) -> io::Result<Mesh> {
+    let mut obj_positions = vec![];
+    let mut obj_normals = vec![];
+
+    // obj faces index positions and normals separately, so every distinct
+    // (position, normal) pair becomes one mesh vertex
+    let mut vertices: HashMap<(usize, Option<usize>), usize> = HashMap::new();
+    let mut sources = vec![];
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut has_normals = true;
+    let mut faces = vec![];
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => obj_positions.push(Point3::from_vec(parse_vector(tokens, number)?)),
+            Some("vn") => obj_normals.push(parse_vector(tokens, number)?),
+            Some("f") => {
+                let mut face = vec![];
+                for token in tokens {
+                    let (p, n) = parse_face_vertex(token, obj_positions.len(), obj_normals.len(), number)?;
+                    has_normals = has_normals && n.is_some();
+                    let index = *vertices.entry((p, n)).or_insert_with(|| {
+                        sources.push(p);
+                        positions.push(obj_positions[p]);
+                        normals.push(n.map_or(Vector3::zero(), |n| obj_normals[n]));
+                        positions.len() - 1
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(invalid(number, "face needs at least three vertices"));
+                }
+                for i in 1..face.len() - 1 {
+                    faces.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !has_normals {
+        // normals are computed instead, so vertices that only differed in
+        // their normal are one vertex again; kept apart they'd show a seam
+        let mut merged: HashMap<usize, usize> = HashMap::new();
+        let mut merged_positions = vec![];
+        let remap: Vec<usize> = sources.iter().map(|&p| *merged.entry(p).or_insert_with(|| {
+            merged_positions.push(obj_positions[p]);
+            merged_positions.len() - 1
+        })).collect();
+        for face in &mut faces {
+            for index in face.iter_mut() { *index = remap[*index]; }
+        }
+        positions = merged_positions;
+        normals.clear();
+    }
+    Ok(Mesh::new(positions, normals, faces, color))
+}
+
+fn invalid(number: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("obj line {}: {}", number + 1, message))
+}
+
+fn parse_vector<'a, I: Iterator<Item = &'a str>>(tokens: I, number: usize) -> io::Result<Vector3<f32>> {
+    let mut v = [0.0; 3];
+    let mut count = 0;
+    for token in tokens.take(3) {
+        v[count] = token.parse::<f32>().map_err(|_| invalid(number, "malformed number"))?;
+        count += 1;
+    }
+    if count < 3 {
+        return Err(invalid(number, "expected three coordinates"));
+    }
+    Ok(vec3(v[0], v[1], v[2]))
+}
+
+// Parses `p`, `p/t`, `p//n`, or `p/t/n` into zero-based position and normal
+// indices, resolving negative (relative) indices.
+fn parse_face_vertex(token: &str, positions: usize, normals: usize, number: usize) -> io::Result<(usize, Option<usize>)> {
+    let mut parts = token.split('/');
+    let p = resolve_index(parts.next().unwrap_or(""), positions, number)?;
+    let n = match parts.nth(1) {
+        Some(n) if !n.is_empty() => Some(resolve_index(n, normals, number)?),
+        _ => None,
+    };
+    Ok((p, n))
+}
+
+fn resolve_index(token: &str, count: usize, number: usize) -> io::Result<usize> {
+    let index = token.parse::<isize>().map_err(|_| invalid(number, "malformed index"))?;
+    let resolved = if index < 0 { count as isize + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as isize {
+        return Err(invalid(number, "index out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::*;
+    use super::super::{Intersect, Ray};
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    fn mesh(obj: &str) -> io::Result<Mesh> {
+        parse(obj.as_bytes(), vec3(1.0, 1.0, 1.0))
+    }
+
+    // the normal seen straight down onto the unit square at `x`, `y`
+    fn normal_at(mesh: &Mesh, x: f32, y: f32) -> Option<Vector3<f32>> {
+        mesh.intersect(&Ray::new(Point3::new(x, y, 1.0), vec3(0.0, 0.0, -1.0))).map(|hit| hit.normal)
+    }
+
+    fn area(mesh: &Mesh) -> f32 {
+        mesh.sample_surface(0.5, 0.5, 0.0).unwrap().2
+    }
+
+    #[test]
+    fn quads_are_split_into_triangles() {
+        let quad = mesh(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap();
+        assert!((area(&quad) - 1.0).abs() < 1e-6);
+        assert!(normal_at(&quad, 0.75, 0.25).is_some());
+        assert!(normal_at(&quad, 0.25, 0.75).is_some());
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let relative = mesh(&format!("{}f -4 -3 -2 -1\nv 5 5 5\n", SQUARE)).unwrap();
+        assert!((area(&relative) - 1.0).abs() < 1e-6);
+        assert!(normal_at(&relative, 0.25, 0.75).is_some());
+        assert!(mesh(&format!("{}f -5 -3 -2\n", SQUARE)).is_err());
+    }
+
+    #[test]
+    fn given_normals_are_kept() {
+        let tilted = vec3(0.0, 0.6, 0.8);
+        let obj = format!("{}vn 0 0.6 0.8\nf 1//1 2//1 3//1 4//1\n", SQUARE);
+        let normal = normal_at(&mesh(&obj).unwrap(), 0.25, 0.75).unwrap();
+        assert!((normal - tilted).length() < 1e-6);
+    }
+
+    #[test]
+    fn missing_normals_are_computed() {
+        let up = vec3(0.0, 0.0, 1.0);
+        let normal = normal_at(&mesh(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap(), 0.25, 0.75).unwrap();
+        assert!((normal - up).length() < 1e-6);
+        // one vertex without a normal means none of them have one
+        let obj = format!("{}vn 0 0.6 0.8\nf 1//1 2//1 3 4//1\n", SQUARE);
+        let normal = normal_at(&mesh(&obj).unwrap(), 0.25, 0.75).unwrap();
+        assert!((normal - up).length() < 1e-6);
+        // a fold along the diagonal is shaded smoothly across it, whatever
+        // else the faces on either side index at the shared corners
+        let fold = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0.5\nvt 0 0\nvt 1 1\nvn 0 0 1\n";
+        for faces in &["f 1/1 2/1 3/1\nf 1/2 3/2 4/2\n", "f 1/1/1 2/1/1 3/1/1\nf 1/2 3/2 4/2\n"] {
+            let normal = normal_at(&mesh(&format!("{}{}", fold, faces)).unwrap(), 0.75, 0.25).unwrap();
+            assert!(normal.z < 0.999, "{:?} is flat for {:?}", normal, faces);
+        }
+    }
+
+    #[test]
+    fn malformed_faces_are_rejected() {
+        assert!(mesh(&format!("{}f 1 2\n", SQUARE)).is_err());
+        assert!(mesh(&format!("{}f 1 2 5\n", SQUARE)).is_err());
+        assert!(mesh(&format!("{}f 1 2 x\n", SQUARE)).is_err());
+        assert!(mesh("v 0 0\n").is_err());
+    }
+}
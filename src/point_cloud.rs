@@ -0,0 +1,137 @@
+use cgmath::*;
+
+use std::cmp::Ordering;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+use super::math::{inverse_direction, ray_slabs};
+
+const LEAF_SIZE: usize = 4;
+
+/// How each point is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointShape {
+    Sphere,
+    /// A flat splat that always faces the incoming ray.
+    Disc,
+}
+
+#[derive(Debug)]
+struct Node {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+    // interior nodes keep their left child right after themselves
+    right: usize,
+}
+
+/// Many equally sized points sharing one color, with a bounding volume
+/// hierarchy over the points built up front so each ray only tests a handful
+/// of them.
+#[derive(Debug)]
+pub struct PointCloud {
+    points: Vec<Point3<f32>>,
+    radius: f32,
+    shape: PointShape,
+    color: Vector3<f32>,
+    nodes: Vec<Node>,
+}
+impl PointCloud {
+    pub fn new(points: Vec<Point3<f32>>, radius: f32, shape: PointShape, color: Vector3<f32>) -> PointCloud {
+        let mut cloud = PointCloud {
+            points: points,
+            radius: radius,
+            shape: shape,
+            color: color,
+            nodes: vec![],
+        };
+        if !cloud.points.is_empty() {
+            let count = cloud.points.len();
+            cloud.build(0, count);
+        }
+        cloud
+    }
+
+    fn build(&mut self, start: usize, count: usize) -> usize {
+        let pad = vec3(self.radius, self.radius, self.radius);
+        let first = self.points[start];
+        let bounds = self.points[start..start + count].iter()
+            .fold(Aabb::new(first, first), |b, &p| b.union(&Aabb::new(p, p)));
+        let bounds = Aabb::new(bounds.min + -pad, bounds.max + pad);
+
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds: bounds, start: start, count: count, right: 0 });
+        if count <= LEAF_SIZE { return index; }
+
+        // split at the median along the longest axis
+        let extent = bounds.max - bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let half = count / 2;
+        self.points[start..start + count].select_nth_unstable_by(half, |a, b| {
+            a[axis].partial_cmp(&b[axis]).unwrap_or(Ordering::Equal)
+        });
+
+        self.build(start, half);
+        let right = self.build(start + half, count - half);
+        self.nodes[index].count = 0;
+        self.nodes[index].right = right;
+        index
+    }
+
+    fn intersect_point(&self, ray: &Ray, center: Point3<f32>) -> Option<f32> {
+        let l = center - ray.point;
+        let v = l.dot(ray.direction);
+        let d2 = l.dot(l) - v * v;
+        let r2 = self.radius * self.radius;
+        if d2 > r2 { return None; }
+        let t = match self.shape {
+            // from inside, the far side
+            PointShape::Sphere => {
+                let half = (r2 - d2).sqrt();
+                if v - half < 0.0 { v + half } else { v - half }
+            }
+            PointShape::Disc => v,
+        };
+        if t < 0.0 { None } else { Some(t) }
+    }
+}
+
+impl Intersect for PointCloud {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        if self.nodes.is_empty() { return None; }
+
+        let mut closest: Option<(f32, usize)> = None;
+        let inv_direction = inverse_direction(ray.direction);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray_slabs(ray.point, inv_direction, node.bounds.min, node.bounds.max) {
+                Some((t_enter, _)) if closest.is_none_or(|c| t_enter < c.0) => {}
+                _ => continue,
+            }
+            if node.count == 0 {
+                stack.push(node.right);
+                stack.push(index + 1);
+                continue;
+            }
+            for i in node.start..node.start + node.count {
+                match self.intersect_point(ray, self.points[i]) {
+                    Some(t) if closest.is_none_or(|c| t < c.0) => closest = Some((t, i)),
+                    _ => {}
+                }
+            }
+        }
+
+        closest.map(|(t, i)| {
+            let normal = match self.shape {
+                PointShape::Sphere => (ray.point_at(t) - self.points[i]).normalize(),
+                PointShape::Disc => -ray.direction,
+            };
+            Hit { t: t, normal: normal, color: self.color, material: None, front: true }
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.bounds)
+    }
+}
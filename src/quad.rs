@@ -0,0 +1,66 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::{Aabb, Bounded};
+
+/// A parallelogram spanning `corner + u * edge_u + v * edge_v` for
+/// `u, v` in `[0, 1]`; with perpendicular edges it is a rectangle.
+#[derive(Debug, PartialEq)]
+pub struct Quad {
+    pub corner: Point3<f32>,
+    pub edge_u: Vector3<f32>,
+    pub edge_v: Vector3<f32>,
+    normal: Vector3<f32>,
+    color: Vector3<f32>,
+}
+impl Quad {
+    pub fn new(corner: Point3<f32>, edge_u: Vector3<f32>, edge_v: Vector3<f32>, color: Vector3<f32>) -> Quad {
+        Quad {
+            corner: corner,
+            edge_u: edge_u,
+            edge_v: edge_v,
+            normal: edge_u.cross(edge_v),
+            color: color,
+        }
+    }
+
+    /// Like `intersect` but also returns the hit's coordinates on the quad.
+    pub fn intersect_uv(&self, ray: &Ray) -> Option<(f32, f32, f32)> {
+        let denom = self.normal.dot(ray.direction);
+        if denom.abs() < EPSILON * self.normal.length() { return None; }
+
+        let t = (self.corner - ray.point).dot(self.normal) / denom;
+        if t < 0.0 { return None; }
+
+        // project the hit onto the (possibly skewed) edges
+        let q = ray.point_at(t) - self.corner;
+        let w = self.normal / self.normal.dot(self.normal);
+        let u = w.dot(q.cross(self.edge_v));
+        let v = w.dot(self.edge_u.cross(q));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) { return None; }
+        Some((t, u, v))
+    }
+}
+
+impl Intersect for Quad {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        self.intersect_uv(ray).map(|(t, _, _)| {
+            Hit { t: t, normal: self.normal.normalize(), color: self.color, material: None, front: true }
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+
+    fn sample_surface(&self, u: f32, v: f32, _time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        Some((self.corner + self.edge_u * u + self.edge_v * v, self.normal.normalize(), self.normal.length()))
+    }
+}
+
+impl Bounded for Quad {
+    fn aabb(&self) -> Aabb {
+        let c = self.corner;
+        Aabb::from_points(vec![c, c + self.edge_u, c + self.edge_v, c + self.edge_u + self.edge_v]).unwrap()
+    }
+}
@@ -0,0 +1,197 @@
+use cgmath::*;
+
+use std::mem;
+use std::sync::{Mutex, RwLock};
+
+use super::{Hit, Ray, Scene, Target, EPSILON};
+use super::integrator;
+use super::material::Material;
+use super::rng::Rng;
+
+// how many of the previous pass's pixels around a camera ray's own are
+// reused, besides its own, and from how far away
+const NEIGHBOURS: usize = 4;
+const RADIUS: f32 = 10.0;
+// how many candidates a reused reservoir may count for, per candidate of a
+// fresh one, so old picks fade out
+const HISTORY: usize = 20;
+
+// A point on an emitter a surface might be lit from.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    point: Point3<f32>,
+    normal: Vector3<f32>,
+    emitted: Vector3<f32>,
+    // spheres only glow outwards, as far as anything outside them sees
+    one_sided: bool,
+}
+
+// One candidate picked out of a stream of them, each with odds in proportion
+// to its weight, and what is needed to weigh the pick.
+#[derive(Debug, Clone, Default)]
+struct Reservoir {
+    picked: Option<(Candidate, f32)>,
+    weight_sum: f32,
+    count: usize,
+}
+impl Reservoir {
+    fn add(&mut self, candidate: Candidate, target: f32, weight: f32, count: usize, rng: &mut Rng) {
+        self.weight_sum += weight;
+        self.count += count;
+        if weight > 0.0 && rng.next_f32() * self.weight_sum < weight {
+            self.picked = Some((candidate, target));
+        }
+    }
+
+    // What the pick's light is scaled by, standing in for one over the
+    // density it was picked with.
+    fn weight(&self) -> f32 {
+        match self.picked {
+            Some((_, target)) if target > 0.0 => self.weight_sum / (self.count as f32 * target),
+            _ => 0.0,
+        }
+    }
+}
+
+// What a camera ray resampled, where it hit, for the next pass.
+#[derive(Debug, Clone)]
+struct Kept {
+    reservoir: Reservoir,
+    point: Point3<f32>,
+    normal: Vector3<f32>,
+    // a pixel's many samples keep whichever has the smallest key, so the
+    // order they are traced in doesn't matter
+    key: u64,
+}
+
+/// Direct light picked by reservoir resampling, as in ReSTIR: many
+/// candidate points on emitters are weighed by the unshadowed light each
+/// would give, one is picked in proportion and only it is shadow tested.
+/// Camera rays also draw on what their own and nearby pixels picked on the
+/// previous pass, so the candidates add up over the passes and across the
+/// image. Reuse trusts neighbours' lights to reach the same as theirs did,
+/// which can leave a little too much light at the edges of shadows.
+#[derive(Debug)]
+pub struct Reservoirs {
+    width: u32,
+    height: u32,
+    candidates: usize,
+    previous: RwLock<Vec<Option<Kept>>>,
+    current: Mutex<Vec<Option<Kept>>>,
+}
+impl Reservoirs {
+    pub fn new(width: u32, height: u32, candidates: usize) -> Reservoirs {
+        let pixels = (width * height) as usize;
+        Reservoirs {
+            width: width,
+            height: height,
+            candidates: candidates,
+            previous: RwLock::new(vec![None; pixels]),
+            current: Mutex::new(vec![None; pixels]),
+        }
+    }
+
+    /// Makes this pass's picks the ones the next pass reuses.
+    pub fn refresh(&self) {
+        let mut current = self.current.lock().unwrap();
+        let fresh = vec![None; current.len()];
+        *self.previous.write().unwrap() = mem::replace(&mut *current, fresh);
+    }
+
+    /// The light reaching a hit straight from the emitter resampling picks.
+    pub fn light(&self, scene: &Scene, ray: &Ray, hit: &Hit, rng: &mut Rng) -> Vector3<f32> {
+        let material = scene.material_at(hit);
+        let point = ray.point_at(hit.t) + hit.normal * EPSILON;
+        let target = |candidate: &Candidate| brightness(unshadowed(material, ray, hit, point, candidate));
+
+        let mut reservoir = Reservoir::default();
+        for _ in 0..self.candidates {
+            let (emitter, chance) = match scene.light_tree.pick(point, rng.next_f32()) {
+                Some((i, chance)) => (scene.emitters[i], chance),
+                None => continue,
+            };
+            let (on, normal, area) = match integrator::surface_point(scene, emitter, rng.next_f32(), rng.next_f32(), ray.time) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let (onto, light) = match integrator::onto(scene, emitter, on, normal, ray.time) {
+                Some(onto) => onto,
+                None => continue,
+            };
+            let candidate = Candidate {
+                point: onto.point_at(light.t),
+                normal: normal,
+                emitted: scene.material_at(&light).emitted(&onto, &light),
+                one_sided: matches!(emitter, Target::Sphere(_)),
+            };
+            let t = target(&candidate);
+            reservoir.add(candidate, t, t * area / chance, 1, rng);
+        }
+        if let Some(pixel) = ray.pixel {
+            self.reuse(&mut reservoir, pixel, ray.point_at(hit.t), hit, &target, rng);
+        }
+
+        let light = match reservoir.picked {
+            Some((candidate, _)) => {
+                let to = candidate.point - point;
+                let distance = to.length();
+                let shadow = Ray::new(point, to).at_time(ray.time);
+                if scene.in_shadow(&shadow, distance * (1.0 - 1e-3)) {
+                    reservoir.weight_sum = 0.0;
+                    Vector3::zero()
+                } else {
+                    unshadowed(material, ray, hit, point, &candidate) * reservoir.weight()
+                }
+            }
+            None => Vector3::zero(),
+        };
+        if let Some((x, y)) = ray.pixel {
+            let kept = Kept { reservoir: reservoir, point: ray.point_at(hit.t), normal: hit.normal, key: ray.rng().next_u64() };
+            let mut current = self.current.lock().unwrap();
+            let slot = &mut current[(y * self.width + x) as usize];
+            if slot.as_ref().is_none_or(|old| kept.key < old.key) { *slot = Some(kept); }
+        }
+        light
+    }
+
+    // Streams the picks made around the pixel on the previous pass into the
+    // reservoir, from surfaces much like the hit's.
+    fn reuse<F: Fn(&Candidate) -> f32>(&self, reservoir: &mut Reservoir, pixel: (u32, u32), point: Point3<f32>, hit: &Hit, target: &F, rng: &mut Rng) {
+        let previous = self.previous.read().unwrap();
+        for i in 0..NEIGHBOURS + 1 {
+            let (x, y) = if i == 0 { pixel } else {
+                let offset = |c: u32, size: u32, u: f32| {
+                    ((c as f32 + (u * 2.0 - 1.0) * RADIUS).round().max(0.0) as u32).min(size - 1)
+                };
+                (offset(pixel.0, self.width, rng.next_f32()), offset(pixel.1, self.height, rng.next_f32()))
+            };
+            let kept = match previous[(y * self.width + x) as usize] {
+                Some(ref kept) => kept,
+                None => continue,
+            };
+            if kept.normal.dot(hit.normal) < 0.9 || (kept.point - point).length() > 0.05 * hit.t.max(1.0) { continue; }
+            let candidate = match kept.reservoir.picked {
+                Some((candidate, _)) => candidate,
+                None => continue,
+            };
+            let count = kept.reservoir.count.min(HISTORY * self.candidates);
+            let t = target(&candidate);
+            reservoir.add(candidate, t, t * kept.reservoir.weight() * count as f32, count, rng);
+        }
+    }
+}
+
+// The light a candidate would give the hit if nothing were in the way.
+fn unshadowed(material: &dyn Material, ray: &Ray, hit: &Hit, point: Point3<f32>, candidate: &Candidate) -> Vector3<f32> {
+    let to = candidate.point - point;
+    let distance2 = to.length2();
+    if distance2 == 0.0 { return Vector3::zero(); }
+    let direction = to / distance2.sqrt();
+    let cos = -candidate.normal.dot(direction);
+    let cos = if candidate.one_sided { cos.max(0.0) } else { cos.abs() };
+    material.eval(ray, hit, direction) * candidate.emitted * (cos / distance2)
+}
+
+fn brightness(light: Vector3<f32>) -> f32 {
+    (light.x + light.y + light.z) / 3.0
+}
@@ -0,0 +1,50 @@
+/// A small, fast pseudo-random generator (PCG32, XSH-RR). Seeded
+/// explicitly so every pixel's samples are reproducible no matter which
+/// thread draws them, and split into streams: generators with the same
+/// seed but different stream ids give unrelated sequences, so separate
+/// pieces of work never share random numbers.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    // odd; picks the stream
+    increment: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::stream(seed, 0)
+    }
+
+    pub fn stream(seed: u64, stream: u64) -> Rng {
+        // scrambled so nearby stream ids, which PCG would make look alike,
+        // give unrelated streams
+        let mut rng = Rng { state: 0, increment: (mix(stream) << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(mix(seed));
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+// splitmix64's finalizer
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,325 @@
+use cgmath::*;
+
+use super::rng::Rng;
+
+// Independent sample dimensions. Each is a pair, a point in the unit square.
+pub const PIXEL: usize = 0;
+pub const LENS: usize = 1;
+pub const LIGHT: usize = 2;
+pub const TIME: usize = 3;
+
+const MASK_SIZE: usize = 64;
+
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+/// A source of sample points. Every pixel has its own sequence, and every
+/// dimension its own points within it, so a sample's position in the pixel
+/// isn't correlated with where it lands on the lens or a light.
+pub trait Sampler: Send + Sync {
+    /// Samples `first..first + count` of pixel `(x, y)`'s sequence in
+    /// `dimension`, as points in `[0, 1)` squared. The same arguments always
+    /// give the same points.
+    fn points(&self, x: u32, y: u32, first: usize, count: usize, dimension: usize) -> Vec<(f32, f32)>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+impl SamplerKind {
+    /// A sampler whose every random choice follows from `seed`.
+    pub fn build(&self, seed: u64) -> Box<dyn Sampler> {
+        match *self {
+            SamplerKind::Stratified => Box::new(Stratified { seed: seed }),
+            SamplerKind::Halton => Box::new(Halton { seed: seed }),
+            SamplerKind::Sobol => Box::new(Sobol { seed: seed }),
+            SamplerKind::BlueNoise => Box::new(BlueNoise::new(seed)),
+        }
+    }
+}
+
+/// Where samples `first..first + count` of a pixel go, as offsets in pixels
+/// from its center. A lone first sample goes through the center, so a
+/// single-sample render is sharp.
+pub fn offsets(sampler: &dyn Sampler, x: u32, y: u32, first: usize, count: usize) -> Vec<(f32, f32)> {
+    if first == 0 && count == 1 {
+        return vec![(0.0, 0.0)];
+    }
+    sampler.points(x, y, first, count, PIXEL).into_iter().map(|(u, v)| (u - 0.5, v - 0.5)).collect()
+}
+
+// The render seed's stream for one pixel, dimension and run of samples.
+// Streams are tied to the work rather than to the thread doing it, so the
+// numbers drawn never depend on scheduling.
+fn rng(seed: u64, x: u32, y: u32, first: usize, dimension: usize) -> Rng {
+    Rng::stream(seed, ((dimension as u64) << 56) ^ ((first as u64) << 40) ^ ((y as u64) << 20) ^ x as u64)
+}
+
+/// The square is cut into one cell per sample and each sample lands
+/// somewhere random in its own cell, which covers it more evenly than
+/// purely random samples. Each call is stratified on its own.
+#[derive(Debug, Clone, Copy)]
+pub struct Stratified {
+    seed: u64,
+}
+impl Sampler for Stratified {
+    fn points(&self, x: u32, y: u32, first: usize, count: usize, dimension: usize) -> Vec<(f32, f32)> {
+        let mut rng = rng(self.seed, x, y, first, dimension);
+        strata(count).into_iter().map(|(x, y, width, height)| {
+            (x + width * rng.next_f32(), y + height * rng.next_f32())
+        }).collect()
+    }
+}
+
+// Splits the unit square into `samples` cells, as `(x, y, width, height)`:
+// about a square root's worth of equal rows, each split into equal cells.
+fn strata(samples: usize) -> Vec<(f32, f32, f32, f32)> {
+    let rows = ((samples as f32).sqrt() as usize).max(1);
+    let mut cells = Vec::with_capacity(samples);
+    for row in 0..rows {
+        let columns = (row + 1) * samples / rows - row * samples / rows;
+        for column in 0..columns {
+            cells.push((column as f32 / columns as f32, row as f32 / rows as f32, 1.0 / columns as f32, 1.0 / rows as f32));
+        }
+    }
+    cells
+}
+
+/// The Halton sequence, with a pair of prime bases per dimension. Each
+/// pixel's sequence is shifted by a random offset (a Cranley-Patterson
+/// rotation) so neighbouring pixels don't repeat the same pattern.
+#[derive(Debug, Clone, Copy)]
+pub struct Halton {
+    seed: u64,
+}
+impl Sampler for Halton {
+    fn points(&self, x: u32, y: u32, first: usize, count: usize, dimension: usize) -> Vec<(f32, f32)> {
+        let d = dimension % (PRIMES.len() / 2);
+        let (a, b) = (PRIMES[2 * d], PRIMES[2 * d + 1]);
+        let mut rng = rng(self.seed, x, y, 0, dimension);
+        let (du, dv) = (rng.next_f32(), rng.next_f32());
+        (first..first + count).map(|i| {
+            ((radical_inverse(a, i as u64) + du).fract(), (radical_inverse(b, i as u64) + dv).fract())
+        }).collect()
+    }
+}
+
+// `index` with its digits in `base` mirrored around the radix point.
+fn radical_inverse(base: u32, mut index: u64) -> f32 {
+    let base = base as u64;
+    let (mut digits, mut scale) = (0u64, 1u64);
+    while index > 0 && scale < 1 << 48 {
+        digits = digits * base + index % base;
+        scale *= base;
+        index /= base;
+    }
+    (digits as f64 / scale as f64).min(1.0 - f64::EPSILON) as f32
+}
+
+/// The first two dimensions of the Sobol sequence, a (0, 2)-sequence: every
+/// power-of-two run of samples is stratified at every aspect ratio. Each
+/// pixel and dimension gets its own random digit scramble, which keeps
+/// that property.
+#[derive(Debug, Clone, Copy)]
+pub struct Sobol {
+    seed: u64,
+}
+impl Sampler for Sobol {
+    fn points(&self, x: u32, y: u32, first: usize, count: usize, dimension: usize) -> Vec<(f32, f32)> {
+        let mut rng = rng(self.seed, x, y, 0, dimension);
+        let (su, sv) = (rng.next_u64() as u32, rng.next_u64() as u32);
+        (first..first + count).map(|i| {
+            let i = i as u32;
+            (to_unit(i.reverse_bits() ^ su), to_unit(sobol_second(i) ^ sv))
+        }).collect()
+    }
+}
+
+fn sobol_second(mut index: u32) -> u32 {
+    let (mut v, mut bits) = (1u32 << 31, 0);
+    while index != 0 {
+        if index & 1 != 0 {
+            bits ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    bits
+}
+
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Unscrambled Sobol points, shifted per pixel by a tiled blue-noise mask
+/// rather than by white noise. Neighbouring pixels get very different
+/// shifts, so at low sample counts the error looks like fine, even grain
+/// instead of blotches.
+#[derive(Debug, Clone)]
+pub struct BlueNoise {
+    // MASK_SIZE squared thresholds in `(0, 1)`, row by row
+    mask: Vec<f32>,
+    // where the seed moves the mask to
+    shift: (usize, usize),
+}
+impl BlueNoise {
+    pub fn new(seed: u64) -> BlueNoise {
+        let mut rng = rng(seed, 0, 0, 0, 0);
+        let shift = ((rng.next_u64() % MASK_SIZE as u64) as usize, (rng.next_u64() % MASK_SIZE as u64) as usize);
+        BlueNoise { mask: void_and_cluster(MASK_SIZE), shift: shift }
+    }
+
+    fn at(&self, x: usize, y: usize) -> f32 {
+        let (x, y) = (x + self.shift.0, y + self.shift.1);
+        self.mask[(y % MASK_SIZE) * MASK_SIZE + x % MASK_SIZE]
+    }
+}
+impl Sampler for BlueNoise {
+    fn points(&self, x: u32, y: u32, first: usize, count: usize, dimension: usize) -> Vec<(f32, f32)> {
+        // the two coordinates and every dimension read the mask at their
+        // own offset, so they aren't correlated
+        let (x, y) = (x as usize + 23 * dimension, y as usize + 41 * dimension);
+        let (du, dv) = (self.at(x, y), self.at(x + MASK_SIZE / 2, y + MASK_SIZE / 2 + 7));
+        (first..first + count).map(|i| {
+            let i = i as u32;
+            ((to_unit(i.reverse_bits()) + du).fract(), (to_unit(sobol_second(i)) + dv).fract())
+        }).collect()
+    }
+}
+
+// Builds a blue-noise threshold mask with Ulichney's void-and-cluster
+// method: pixels are ranked by repeatedly filling the emptiest spot,
+// measured by a toroidal Gaussian energy, so every threshold of the mask
+// is an evenly spread pattern.
+fn void_and_cluster(size: usize) -> Vec<f32> {
+    let len = size * size;
+    let sigma: f32 = 1.5;
+    let reach = 6;
+    let mut kernel = vec![];
+    for dy in -reach..reach + 1 {
+        for dx in -reach..reach + 1 {
+            kernel.push((dx, dy, (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp()));
+        }
+    }
+    let splat = |energy: &mut Vec<f32>, i: usize, sign: f32| {
+        let (x, y) = ((i % size) as isize, (i / size) as isize);
+        for &(dx, dy, w) in &kernel {
+            let j = (y + dy).rem_euclid(size as isize) as usize * size + (x + dx).rem_euclid(size as isize) as usize;
+            energy[j] += sign * w;
+        }
+    };
+    // the tightest cluster among set pixels, or the largest void among unset ones
+    let extreme = |ones: &[bool], energy: &[f32], set: bool| -> usize {
+        let mut best = None;
+        for i in 0..len {
+            if ones[i] != set { continue; }
+            let better = best.is_none_or(|b: usize| if set { energy[i] > energy[b] } else { energy[i] < energy[b] });
+            if better { best = Some(i); }
+        }
+        best.unwrap()
+    };
+
+    // a random initial pattern of about a tenth of the pixels, relaxed by
+    // moving its tightest cluster into its largest void until that stops
+    // changing anything
+    let mut rng = Rng::new(0x5eed);
+    let (mut ones, mut energy) = (vec![false; len], vec![0.0; len]);
+    let mut initial = 0;
+    while initial < len / 10 {
+        let i = (rng.next_u64() % len as u64) as usize;
+        if ones[i] { continue; }
+        ones[i] = true;
+        splat(&mut energy, i, 1.0);
+        initial += 1;
+    }
+    for _ in 0..len {
+        let cluster = extreme(&ones, &energy, true);
+        ones[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = extreme(&ones, &energy, false);
+        ones[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster { break; }
+    }
+
+    let mut ranks = vec![0; len];
+    // the initial pattern ranks lowest, emptied tightest cluster first
+    let (mut emptied, mut emptied_energy) = (ones.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = extreme(&emptied, &emptied_energy, true);
+        emptied[cluster] = false;
+        splat(&mut emptied_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    // then every other pixel, filled largest void first
+    for rank in initial..len {
+        let void = extreme(&ones, &energy, false);
+        ones[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks.into_iter().map(|r| (r as f32 + 0.5) / len as f32).collect()
+}
+
+/// The spread of a pixel's samples' brightness, for telling when it has
+/// had enough of them.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    luminance: f32,
+    luminance_squared: f32,
+    count: usize,
+}
+impl Default for Estimate {
+    fn default() -> Estimate {
+        Estimate::new()
+    }
+}
+
+impl Estimate {
+    pub fn new() -> Estimate {
+        Estimate { luminance: 0.0, luminance_squared: 0.0, count: 0 }
+    }
+
+    pub fn add(&mut self, color: Vector3<f32>) {
+        let l = luminance(color);
+        self.luminance += l;
+        self.luminance_squared += l * l;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Whether the standard error of the mean brightness is above
+    /// `threshold`. A single sample says nothing about noise, so it counts
+    /// as noisy.
+    pub fn noisy(&self, threshold: f32) -> bool {
+        if self.count < 2 {
+            return true;
+        }
+        let n = self.count as f32;
+        let mean = self.luminance / n;
+        let variance = (self.luminance_squared / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() > threshold
+    }
+
+    /// Whether one of the samples is more than `factor` times as bright as
+    /// the others are on average. A lone sample has nothing to stand out
+    /// from.
+    pub fn outlier(&self, color: Vector3<f32>, factor: f32) -> bool {
+        if self.count < 2 {
+            return false;
+        }
+        let l = luminance(color);
+        let rest = (self.luminance - l) / (self.count - 1) as f32;
+        l > factor * rest.max(1e-3)
+    }
+}
+
+fn luminance(color: Vector3<f32>) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
@@ -0,0 +1,139 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::Aabb;
+
+// A march stops once it is this close to the surface. It is kept below the
+// secondary ray offset so shadow rays don't immediately re-hit their origin.
+const HIT_DISTANCE: f32 = EPSILON * 0.1;
+const MAX_STEPS: usize = 256;
+
+/// A signed distance field: negative inside the shape, positive outside, and
+/// never overestimating the distance to the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: Point3<f32>) -> f32;
+
+    /// A box the whole surface lies in; marching never leaves it.
+    fn bounds(&self) -> Aabb;
+}
+
+pub struct SdfSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        (p - self.center).length() - self.radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::around_sphere(self.center, self.radius)
+    }
+}
+
+pub struct SdfBox {
+    pub center: Point3<f32>,
+    pub half_extents: Vector3<f32>,
+}
+impl Sdf for SdfBox {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let local = p - self.center;
+        let q = vec3(local.x.abs(), local.y.abs(), local.z.abs()) - self.half_extents;
+        let outside = vec3(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.center + -self.half_extents, self.center + self.half_extents)
+    }
+}
+
+/// Union of two fields, blended over a distance of `k` so the shapes melt
+/// into each other.
+pub struct SmoothUnion<A, B> {
+    pub a: A,
+    pub b: B,
+    pub k: f32,
+}
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        let (d1, d2) = (self.a.distance(p), self.b.distance(p));
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - self.k * h * (1.0 - h)
+    }
+
+    fn bounds(&self) -> Aabb {
+        // the blend pulls the surface out by at most a quarter of `k`
+        let bounds = self.a.bounds().union(&self.b.bounds());
+        let k = vec3(self.k, self.k, self.k) * 0.25;
+        Aabb::new(bounds.min + -k, bounds.max + k)
+    }
+}
+
+/// Renders a distance field by sphere tracing.
+pub struct SdfObject {
+    sdf: Box<dyn Sdf>,
+    color: Vector3<f32>,
+}
+impl SdfObject {
+    pub fn new<S: Sdf + 'static>(sdf: S, color: Vector3<f32>) -> SdfObject {
+        SdfObject {
+            sdf: Box::new(sdf),
+            color: color,
+        }
+    }
+
+    fn normal(&self, p: Point3<f32>) -> Vector3<f32> {
+        // central differences of the field approximate its gradient
+        let h = EPSILON;
+        let d = |x: f32, y: f32, z: f32| self.sdf.distance(p + vec3(x, y, z));
+        vec3(d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+             d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+             d(0.0, 0.0, h) - d(0.0, 0.0, -h)).normalize()
+    }
+}
+
+impl Intersect for SdfObject {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        // march only across the field's bounds, wherever they are in the scene
+        let (enter, exit) = self.sdf.bounds().slabs(ray)?;
+        let mut t = enter.max(0.0);
+        for _ in 0..MAX_STEPS {
+            let p = ray.point_at(t);
+            // rays starting inside march to the far side of the surface
+            let d = self.sdf.distance(p).abs();
+            if d < HIT_DISTANCE {
+                return Some(Hit { t: t, normal: self.normal(p), color: self.color, material: None, front: true });
+            }
+            t += d;
+            if t > exit { break; }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.sdf.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::*;
+    use super::super::{Intersect, Ray};
+
+    #[test]
+    fn fields_far_from_the_origin_are_hit() {
+        let far = SdfObject::new(SmoothUnion {
+            a: SdfSphere { center: Point3::new(5000.0, 0.0, 0.0), radius: 10.0 },
+            b: SdfBox { center: Point3::new(5000.0, 0.0, 12.0), half_extents: vec3(4.0, 4.0, 4.0) },
+            k: 2.0,
+        }, vec3(1.0, 1.0, 1.0));
+        let hit = far.intersect(&Ray::new(Point3::new(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0))).unwrap();
+        assert!((hit.t - 4990.0).abs() < 0.01, "t = {}", hit.t);
+        let miss = Ray::new(Point3::new(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert!(far.intersect(&miss).is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::PathBuf;
+
+use super::accelerator::AcceleratorKind;
+use super::cache::Cache;
+use super::film::Filter;
+use super::integrator::Integrator;
+use super::sampler::SamplerKind;
+
+/// Everything about a render that isn't part of the scene itself.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    // where the image is written
+    pub output: PathBuf,
+    pub accelerator: AcceleratorKind,
+    // where built BVHs are kept between renders, if anywhere
+    pub cache: Option<Cache>,
+    // `None` uses every core
+    pub threads: Option<usize>,
+    pub tile_size: u32,
+    // print how many tiles are done as the render goes
+    pub progress: bool,
+    // write the unfinished image out after every this many tiles
+    pub checkpoint: Option<usize>,
+    // samples per pixel, taken one full-frame pass at a time with the image
+    // saved after each pass
+    pub passes: usize,
+    // rays averaged into each pixel on every pass
+    pub samples: usize,
+    // where in the pixel those rays go
+    pub sampler: SamplerKind,
+    // every random choice in a render follows from this
+    pub seed: u64,
+    // keep sampling pixels whose noise is above the threshold, up to the
+    // max samples per pixel per pass
+    pub adaptive: Option<(f32, usize)>,
+    // how samples are weighted into the pixels around them
+    pub filter: Filter,
+    pub integrator: Integrator,
+    // with path tracing, learn where light comes from on each pass and lean
+    // the next pass's bounces towards it
+    pub guiding: bool,
+    // with path tracing, how many candidate lights each surface resamples
+    // the one it is lit by from
+    pub resampling: Option<usize>,
+    // how many times a ray may bounce off surfaces
+    pub max_depth: usize,
+    // how many of those bounces may be diffuse, glossy and through surfaces
+    pub depths: Option<(usize, usize, usize)>,
+    // the brightest light a ray bounced off a surface may add to it
+    pub clamp: Option<f32>,
+    // drop samples this many times brighter than the rest of their pixel's
+    pub rejection: Option<f32>,
+    // shade everything with plain gray clay instead of its own material
+    pub clay: bool,
+    // trace on the GPU when the scene allows it, otherwise on the CPU
+    pub gpu: bool,
+    // trace each tile as batches of rays, stage by stage, instead of one
+    // pixel at a time
+    pub wavefront: bool,
+}
+impl RenderSettings {
+    pub fn new(width: u32, height: u32) -> RenderSettings {
+        RenderSettings {
+            width: width,
+            height: height,
+            output: PathBuf::from("test.png"),
+            accelerator: AcceleratorKind::Bvh,
+            cache: None,
+            threads: None,
+            tile_size: 32,
+            progress: false,
+            checkpoint: None,
+            passes: 1,
+            samples: 1,
+            sampler: SamplerKind::Stratified,
+            seed: 0,
+            adaptive: None,
+            filter: Filter::Box,
+            integrator: Integrator::Whitted,
+            guiding: false,
+            resampling: None,
+            max_depth: 5,
+            depths: None,
+            clamp: None,
+            rejection: None,
+            clay: false,
+            gpu: false,
+            wavefront: false,
+        }
+    }
+
+    pub fn output<P: Into<PathBuf>>(&mut self, path: P) -> &mut RenderSettings {
+        self.output = path.into();
+        self
+    }
+
+    pub fn accelerator(&mut self, kind: AcceleratorKind) -> &mut RenderSettings {
+        self.accelerator = kind;
+        self
+    }
+
+    pub fn cache(&mut self, cache: Cache) -> &mut RenderSettings {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn threads(&mut self, threads: usize) -> &mut RenderSettings {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn tile_size(&mut self, size: u32) -> &mut RenderSettings {
+        self.tile_size = size;
+        self
+    }
+
+    pub fn progress(&mut self, progress: bool) -> &mut RenderSettings {
+        self.progress = progress;
+        self
+    }
+
+    pub fn checkpoint(&mut self, tiles: usize) -> &mut RenderSettings {
+        self.checkpoint = Some(tiles);
+        self
+    }
+
+    /// Progressive refinement: a first pass through pixel centers is saved
+    /// straight away, then each further pass adds a jittered sample per
+    /// pixel and saves the running average.
+    pub fn progressive(&mut self, passes: usize) -> &mut RenderSettings {
+        self.passes = passes.max(1);
+        self
+    }
+
+    /// Supersampling: each pixel on each pass is the average of this many
+    /// rays spread over it.
+    pub fn samples(&mut self, samples: usize) -> &mut RenderSettings {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn sampler(&mut self, kind: SamplerKind) -> &mut RenderSettings {
+        self.sampler = kind;
+        self
+    }
+
+    /// The same seed, scene and settings always render the same image, bit
+    /// for bit, however many threads the render runs on.
+    pub fn seed(&mut self, seed: u64) -> &mut RenderSettings {
+        self.seed = seed;
+        self
+    }
+
+    /// Adaptive sampling: after its first `samples` rays, a pixel gets
+    /// another `samples` at a time for as long as the standard error of its
+    /// brightness is above `threshold`, to at most `max_samples` a pass.
+    pub fn adaptive(&mut self, threshold: f32, max_samples: usize) -> &mut RenderSettings {
+        self.adaptive = Some((threshold, max_samples.max(1)));
+        self
+    }
+
+    pub fn filter(&mut self, filter: Filter) -> &mut RenderSettings {
+        self.filter = filter;
+        self
+    }
+
+    pub fn integrator(&mut self, integrator: Integrator) -> &mut RenderSettings {
+        self.integrator = integrator;
+        self
+    }
+
+    /// Path guiding: each pass of a path traced render learns where the
+    /// light comes from, and the passes after it bounce towards it more
+    /// often. Needs `progressive` to have anything to learn from.
+    pub fn guiding(&mut self, guiding: bool) -> &mut RenderSettings {
+        self.guiding = guiding;
+        self
+    }
+
+    /// Reservoir resampling of direct light, after ReSTIR: each surface a
+    /// path tracer meets weighs `candidates` points on the glowing spheres
+    /// and objects by how much light they would give it, shadow tests only
+    /// the one picked, and what camera rays picked is reused by nearby
+    /// pixels on the next pass.
+    pub fn resampling(&mut self, candidates: usize) -> &mut RenderSettings {
+        self.resampling = Some(candidates.max(1));
+        self
+    }
+
+    pub fn max_depth(&mut self, depth: usize) -> &mut RenderSettings {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Separate limits on how many of a path's bounces may be diffuse, how
+    /// many glossy, off mirrors and highlights, and how many through glass
+    /// and translucent surfaces, within `max_depth` in all. Cutting the
+    /// bounces an effect doesn't need makes for a faster render that keeps
+    /// the ones it does, like what is seen through a window.
+    pub fn max_depths(&mut self, diffuse: usize, glossy: usize, transmission: usize) -> &mut RenderSettings {
+        self.depths = Some((diffuse, glossy, transmission));
+        self
+    }
+
+    /// Firefly clamping: the light each ray bounced off a surface adds to
+    /// it is scaled down to at most `brightness`, the mean of its channels.
+    /// Rare bright paths stop showing as speckles, at the cost of some of
+    /// the light they carry. Bidirectional paths are left as they are.
+    pub fn clamp(&mut self, brightness: f32) -> &mut RenderSettings {
+        self.clamp = Some(brightness.max(0.0));
+        self
+    }
+
+    /// Outlier rejection: each pass drops a pixel's samples that are more
+    /// than `factor` times as bright as the rest of its samples on average.
+    /// Needs several samples per pixel to have anything to compare with.
+    pub fn reject_outliers(&mut self, factor: f32) -> &mut RenderSettings {
+        self.rejection = Some(factor.max(1.0));
+        self
+    }
+
+    /// A clay render: the scene's lights and shapes as they are, with
+    /// every surface the same matte gray whatever its material.
+    pub fn clay(&mut self, clay: bool) -> &mut RenderSettings {
+        self.clay = clay;
+        self
+    }
+
+    pub fn gpu(&mut self, gpu: bool) -> &mut RenderSettings {
+        self.gpu = gpu;
+        self
+    }
+
+    pub fn wavefront(&mut self, wavefront: bool) -> &mut RenderSettings {
+        self.wavefront = wavefront;
+        self
+    }
+}
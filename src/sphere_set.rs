@@ -0,0 +1,208 @@
+use cgmath::*;
+use wide::{f32x4, CmpGt, CmpLt};
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use super::{inner_radius2, Hit, Intersect, Ray};
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::material::Material;
+
+const LANES: usize = 4;
+
+// Four spheres laid out lane by lane. Unused lanes have a negative squared
+// radius, which no ray can hit.
+#[derive(Debug)]
+struct Packet {
+    x: f32x4,
+    y: f32x4,
+    z: f32x4,
+    r2: f32x4,
+    // `inner_radius2` of each sphere
+    inner2: f32x4,
+}
+
+/// A plain list of spheres, one array per attribute.
+#[derive(Debug, Clone, Default)]
+pub struct Spheres {
+    pub centers: Vec<Point3<f32>>,
+    pub radii: Vec<f32>,
+    pub colors: Vec<Vector3<f32>>,
+    // `None` for the scene's default material
+    pub materials: Vec<Option<Arc<dyn Material>>>,
+}
+impl Spheres {
+    pub fn push(&mut self, center: Point3<f32>, radius: f32, color: Vector3<f32>) {
+        self.centers.push(center);
+        self.radii.push(radius);
+        self.colors.push(color);
+        self.materials.push(None);
+    }
+
+    pub fn len(&self) -> usize {
+        self.centers.len()
+    }
+}
+
+/// Many spheres intersected four at a time with SIMD. Spheres are grouped
+/// into packets of close neighbours, with a BVH over the packets.
+#[derive(Debug)]
+pub struct SphereSet {
+    packets: Vec<Packet>,
+    // per sphere, in packet order
+    centers: Vec<Point3<f32>>,
+    colors: Vec<Vector3<f32>>,
+    materials: Vec<Option<Arc<dyn Material>>>,
+    bounds: Option<Aabb>,
+    bvh: Bvh,
+}
+impl SphereSet {
+    pub fn new(spheres: &Spheres) -> SphereSet {
+        let mut order: Vec<usize> = (0..spheres.len()).collect();
+        group(&mut order, spheres);
+
+        let mut set = SphereSet {
+            packets: vec![],
+            centers: vec![],
+            colors: vec![],
+            materials: vec![],
+            bounds: None,
+            bvh: Bvh::new(&[]),
+        };
+        let mut packet_bounds = vec![];
+        for chunk in order.chunks(LANES) {
+            let mut lanes = [[0.0, 0.0, 0.0, -1.0, -1.0]; LANES];
+            let mut b = None;
+            for (lane, &i) in chunk.iter().enumerate() {
+                let (center, radius, color) = (spheres.centers[i], spheres.radii[i], spheres.colors[i]);
+                lanes[lane] = [center.x, center.y, center.z, radius * radius, inner_radius2(radius)];
+                set.centers.push(center);
+                set.colors.push(color);
+                set.materials.push(spheres.materials[i].clone());
+                let sphere = Aabb::around_sphere(center, radius);
+                b = Some(b.map_or(sphere, |b: Aabb| b.union(&sphere)));
+            }
+            let column = |c: usize| f32x4::new([lanes[0][c], lanes[1][c], lanes[2][c], lanes[3][c]]);
+            set.packets.push(Packet { x: column(0), y: column(1), z: column(2), r2: column(3), inner2: column(4) });
+            packet_bounds.push(b.unwrap());
+        }
+        set.bounds = packet_bounds.iter().fold(None, |a, b| Some(a.map_or(*b, |a: Aabb| a.union(b))));
+        set.bvh = Bvh::new(&packet_bounds);
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        self.centers.len()
+    }
+
+    /// The nearest of the packet's spheres the ray hits, as the ray
+    /// parameter and the sphere's index.
+    fn intersect_packet(&self, ray: &Ray, index: usize) -> Option<(f32, usize)> {
+        let packet = &self.packets[index];
+        let lx = packet.x - f32x4::splat(ray.point.x);
+        let ly = packet.y - f32x4::splat(ray.point.y);
+        let lz = packet.z - f32x4::splat(ray.point.z);
+        let v = lx * f32x4::splat(ray.direction.x) + ly * f32x4::splat(ray.direction.y) + lz * f32x4::splat(ray.direction.z);
+        let l2 = lx * lx + ly * ly + lz * lz;
+        let ax = lx - v * f32x4::splat(ray.direction.x);
+        let ay = ly - v * f32x4::splat(ray.direction.y);
+        let az = lz - v * f32x4::splat(ray.direction.z);
+        let d2 = ax * ax + ay * ay + az * az;
+        let inside = l2.cmp_lt(packet.inner2);
+        let miss = (v.cmp_lt(f32x4::splat(0.0)) & !inside) | d2.cmp_gt(packet.r2);
+        if miss.all() { return None; }
+
+        // the same solution `Sphere::intersect` picks, lane by lane
+        let d = (packet.r2 - d2).max(f32x4::splat(0.0)).sqrt();
+        let t = miss.blend(f32x4::splat(f32::INFINITY), (inside | v.cmp_lt(d)).blend(v + d, v - d));
+        let lanes = t.to_array();
+        let mut closest: Option<(f32, usize)> = None;
+        for (lane, &t) in lanes.iter().enumerate() {
+            if t < f32::INFINITY && closest.is_none_or(|c| t < c.0) {
+                closest = Some((t, index * LANES + lane));
+            }
+        }
+        closest
+    }
+}
+
+impl Intersect for SphereSet {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<(f32, usize)> = None;
+        self.bvh.closest(ray, f32::INFINITY, |p| match self.intersect_packet(ray, p) {
+            Some(hit) if closest.is_none_or(|c| hit.0 < c.0) => {
+                closest = Some(hit);
+                Some(hit.0)
+            }
+            _ => None,
+        });
+        closest.map(|(t, i)| Hit {
+            t: t,
+            normal: (ray.point_at(t) - self.centers[i]).normalize(),
+            color: self.colors[i],
+            material: self.materials[i].clone(),
+            front: true,
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
+
+// Orders spheres so each run of LANES holds close neighbours, by splitting
+// on the longest axis at a multiple of LANES.
+fn group(order: &mut [usize], spheres: &Spheres) {
+    if order.len() <= LANES { return; }
+    let centers = Aabb::from_points(order.iter().map(|&i| spheres.centers[i])).unwrap();
+    let extent = centers.max - centers.min;
+    let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+    let half = (order.len() / 2).div_ceil(LANES) * LANES;
+    order.select_nth_unstable_by(half, |&a, &b| {
+        spheres.centers[a][axis].partial_cmp(&spheres.centers[b][axis]).unwrap_or(Ordering::Equal)
+    });
+    let (left, right) = order.split_at_mut(half);
+    group(left, spheres);
+    group(right, spheres);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Sphere;
+    use super::super::math;
+    use super::super::rng::Rng;
+
+    #[test]
+    fn same_hits_as_one_sphere_at_a_time() {
+        let mut rng = Rng::new(11);
+        // counts that leave lanes of the last packet empty, and one that
+        // doesn't
+        for &count in &[1, 3, 6, 64, 301] {
+            let mut spheres = Spheres::default();
+            for i in 0..count {
+                let center = Point3::new(rng.next_f32() * 20.0 - 10.0, rng.next_f32() * 20.0 - 10.0, rng.next_f32() * 20.0 - 10.0);
+                // the color says which sphere was hit
+                spheres.push(center, 0.3 + rng.next_f32() * 2.0, vec3(i as f32, 0.0, 0.0));
+            }
+            let scalar: Vec<Sphere> = (0..count).map(|i| Sphere::new(spheres.centers[i], spheres.radii[i], spheres.colors[i])).collect();
+            let set = SphereSet::new(&spheres);
+            for _ in 0..500 {
+                let origin = Point3::new(rng.next_f32() * 30.0 - 15.0, rng.next_f32() * 30.0 - 15.0, rng.next_f32() * 30.0 - 15.0);
+                let ray = Ray::new(origin, math::uniform_sphere(rng.next_f32(), rng.next_f32()));
+                let expected = scalar.iter()
+                    .filter_map(|sphere| sphere.intersect(&ray))
+                    .fold(None, |best: Option<Hit>, hit| if best.as_ref().is_none_or(|b| hit.t < b.t) { Some(hit) } else { best });
+                match (set.intersect(&ray), expected) {
+                    (Some(hit), Some(expected)) => {
+                        assert_eq!(hit.color, expected.color, "{} spheres, along {:?}", count, ray);
+                        assert!((hit.t - expected.t).abs() <= 1e-5 * expected.t.max(1.0), "{} spheres, along {:?}", count, ray);
+                    }
+                    (None, None) => {}
+                    (hit, expected) => panic!("{} spheres, along {:?}: {:?} against {:?}", count, ray, hit, expected),
+                }
+            }
+        }
+    }
+}
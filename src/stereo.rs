@@ -0,0 +1,78 @@
+use image::*;
+
+use std::path::{Path, PathBuf};
+
+use super::{save, Image, Scene};
+use super::settings::RenderSettings;
+
+/// How the two eyes of a stereo render are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    // each eye in a file of its own, named after the output with the eye
+    // appended, like `test-left.png`
+    Pair,
+    // left and right next to each other in the output file, each eye
+    // `settings.width` wide, for VR headsets and stereo viewers; the single
+    // eyes are still written as they render
+    SideBySide,
+    // one red/cyan image for coloured glasses: the left eye's red channel
+    // with the right eye's green and blue
+    Anaglyph,
+}
+
+/// Renders the scene from two eyes `interpupillary` apart, level with the
+/// camera and looking the same way, for viewing in 3D. The camera is left
+/// as it was.
+pub fn render(scene: &mut Scene, settings: &RenderSettings, interpupillary: f32, layout: Layout) {
+    let (left, right) = eyes(scene, settings, interpupillary);
+    let (width, height) = left.dimensions();
+    match layout {
+        Layout::Pair => {}
+        Layout::SideBySide => {
+            let mut frame = ImageBuffer::new(2 * width, height);
+            for (x, y, &pixel) in left.enumerate_pixels() {
+                frame.put_pixel(x, y, pixel);
+            }
+            for (x, y, &pixel) in right.enumerate_pixels() {
+                frame.put_pixel(width + x, y, pixel);
+            }
+            save(&frame, &settings.output);
+        }
+        Layout::Anaglyph => {
+            let mut frame = ImageBuffer::new(width, height);
+            for (x, y, pixel) in left.enumerate_pixels() {
+                let other = right.get_pixel(x, y);
+                frame.put_pixel(x, y, Rgb([pixel[0], other[1], other[2]]));
+            }
+            save(&frame, &settings.output);
+        }
+    }
+}
+
+// Renders the left and right eyes to their own files.
+fn eyes(scene: &mut Scene, settings: &RenderSettings, interpupillary: f32) -> (Image, Image) {
+    let original = scene.camera.clone();
+    let (_, right, _) = original.basis();
+    let mut images = vec![];
+    for &(name, side) in &[("left", -0.5), ("right", 0.5)] {
+        let offset = right * (side * interpupillary);
+        let mut camera = original.clone();
+        camera.eye = original.eye + offset;
+        camera.center = original.center + offset;
+        camera.motion = original.motion.map(|(eye, center)| (eye + offset, center + offset));
+        camera.update();
+        scene.camera = camera;
+
+        let mut eye_settings = settings.clone();
+        eye_settings.output = eye_path(&settings.output, name);
+        images.push(scene.render(&eye_settings));
+    }
+    scene.camera = original;
+    let right = images.pop().unwrap();
+    (images.pop().unwrap(), right)
+}
+
+fn eye_path(output: &Path, eye: &str) -> PathBuf {
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("stereo");
+    output.with_file_name(format!("{}-{}.png", stem, eye))
+}
@@ -0,0 +1,106 @@
+use cgmath::*;
+
+use std::collections::HashMap;
+
+use super::mesh::Mesh;
+
+/// Refines a polygonal control mesh with `levels` rounds of Catmull-Clark
+/// subdivision and returns the resulting quads, split into triangles, as a
+/// smooth-shaded mesh. Open boundaries are kept as cubic B-spline curves.
+pub fn catmull_clark(positions: Vec<Point3<f32>>, faces: Vec<Vec<usize>>, levels: usize, color: Vector3<f32>) -> Mesh {
+    let (mut positions, mut faces) = (positions, faces);
+    for _ in 0..levels {
+        let (p, f) = subdivide(&positions, &faces);
+        positions = p;
+        faces = f;
+    }
+
+    let triangles = faces.iter()
+        .flat_map(|face| (1..face.len() - 1).map(move |i| [face[0], face[i], face[i + 1]]))
+        .collect();
+    Mesh::new(positions, vec![], triangles, color)
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn average<I: Iterator<Item = Vector3<f32>>>(points: I) -> Vector3<f32> {
+    let (sum, count) = points.fold((Vector3::zero(), 0), |(sum, count), p| (sum + p, count + 1));
+    sum / count as f32
+}
+
+fn subdivide(positions: &[Point3<f32>], faces: &[Vec<usize>]) -> (Vec<Point3<f32>>, Vec<Vec<usize>>) {
+    let face_points: Vec<Vector3<f32>> = faces.iter()
+        .map(|face| average(face.iter().map(|&i| positions[i].to_vec())))
+        .collect();
+
+    // the faces on either side of every edge
+    let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (f, face) in faces.iter().enumerate() {
+        for i in 0..face.len() {
+            edge_faces.entry(edge_key(face[i], face[(i + 1) % face.len()])).or_default().push(f);
+        }
+    }
+
+    // new vertices are laid out as [vertex points, face points, edge points]
+    let mut new_positions: Vec<Point3<f32>> = Vec::with_capacity(positions.len() + faces.len() + edge_faces.len());
+    let mut vertex_faces: Vec<Vec<usize>> = vec![vec![]; positions.len()];
+    let mut vertex_edges: Vec<Vec<(usize, usize)>> = vec![vec![]; positions.len()];
+    for (f, face) in faces.iter().enumerate() {
+        for &i in face { vertex_faces[i].push(f); }
+    }
+    for &(a, b) in edge_faces.keys() {
+        vertex_edges[a].push((a, b));
+        vertex_edges[b].push((a, b));
+    }
+    let is_boundary = |edge: &(usize, usize)| edge_faces[edge].len() < 2;
+
+    for (v, p) in positions.iter().enumerate() {
+        let boundary: Vec<&(usize, usize)> = vertex_edges[v].iter().filter(|e| is_boundary(e)).collect();
+        let point = if boundary.len() >= 2 {
+            let neighbours = boundary.iter().take(2)
+                .fold(Vector3::zero(), |sum, &&(a, b)| sum + positions[if a == v { b } else { a }].to_vec());
+            p.to_vec() * 0.75 + neighbours * 0.125
+        } else if vertex_faces[v].is_empty() {
+            p.to_vec()
+        } else {
+            let n = vertex_faces[v].len() as f32;
+            let f = average(vertex_faces[v].iter().map(|&f| face_points[f]));
+            let r = average(vertex_edges[v].iter().map(|&(a, b)| (positions[a].to_vec() + positions[b].to_vec()) * 0.5));
+            (f + r * 2.0 + p.to_vec() * (n - 3.0)) / n
+        };
+        new_positions.push(Point3::from_vec(point));
+    }
+
+    let face_base = new_positions.len();
+    new_positions.extend(face_points.iter().map(|&p| Point3::from_vec(p)));
+
+    let mut edge_index = HashMap::new();
+    for (&(a, b), adjacent) in &edge_faces {
+        let midpoint = (positions[a].to_vec() + positions[b].to_vec()) * 0.5;
+        let point = if adjacent.len() == 2 {
+            (midpoint + (face_points[adjacent[0]] + face_points[adjacent[1]]) * 0.5) * 0.5
+        } else {
+            midpoint
+        };
+        edge_index.insert((a, b), new_positions.len());
+        new_positions.push(Point3::from_vec(point));
+    }
+
+    let mut new_faces = Vec::with_capacity(faces.iter().map(|f| f.len()).sum());
+    for (f, face) in faces.iter().enumerate() {
+        let k = face.len();
+        for i in 0..k {
+            let prev = face[(i + k - 1) % k];
+            let next = face[(i + 1) % k];
+            new_faces.push(vec![
+                face[i],
+                edge_index[&edge_key(face[i], next)],
+                face_base + f,
+                edge_index[&edge_key(prev, face[i])],
+            ]);
+        }
+    }
+    (new_positions, new_faces)
+}
@@ -0,0 +1,38 @@
+/// A rectangle of pixels rendered as one unit of work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl Tile {
+    /// The tile's pixel coordinates, row by row.
+    pub fn pixels(&self) -> Vec<(u32, u32)> {
+        let mut pixels = Vec::with_capacity((self.width * self.height) as usize);
+        for y in self.y..self.y + self.height {
+            for x in self.x..self.x + self.width {
+                pixels.push((x, y));
+            }
+        }
+        pixels
+    }
+}
+
+/// Covers a `width` by `height` image with tiles of at most `size` pixels a
+/// side, in scanline order.
+pub fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let size = size.max(1);
+    let mut tiles = vec![];
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(Tile {
+                x: x,
+                y: y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
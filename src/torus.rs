@@ -0,0 +1,73 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray, EPSILON};
+use super::aabb::{Aabb, Bounded};
+use super::math::{orthonormal_basis, solve_quartic};
+
+/// A ring torus lying in the plane perpendicular to `axis`. `major_radius`
+/// is the distance from the center to the middle of the tube and
+/// `minor_radius` the radius of the tube itself.
+#[derive(Debug, PartialEq)]
+pub struct Torus {
+    center: Point3<f32>,
+    axis: Vector3<f32>,
+    major_radius: f32,
+    minor_radius: f32,
+    color: Vector3<f32>,
+}
+impl Torus {
+    pub fn new(center: Point3<f32>, axis: Vector3<f32>, major_radius: f32, minor_radius: f32, color: Vector3<f32>) -> Torus {
+        Torus {
+            center: center,
+            axis: axis.normalize(),
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            color: color,
+        }
+    }
+}
+
+impl Intersect for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        // work in a frame where the torus is centered at the origin around z
+        let (u, v) = orthonormal_basis(self.axis);
+        let w = self.axis;
+        let to_local = |x: Vector3<f32>| vec3(x.dot(u) as f64, x.dot(v) as f64, x.dot(w) as f64);
+        let o = to_local(ray.point - self.center);
+        let d = to_local(ray.direction);
+
+        let r2 = (self.major_radius as f64).powi(2);
+        let m2 = (self.minor_radius as f64).powi(2);
+        let e = o.dot(o) - r2 - m2;
+        let f = o.dot(d);
+        let roots = solve_quartic(
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * r2 * d.z * d.z,
+            4.0 * f * e + 8.0 * r2 * o.z * d.z,
+            e * e - 4.0 * r2 * (m2 - o.z * o.z));
+
+        let t = roots.into_iter()
+            .filter(|&t| t > EPSILON as f64)
+            .fold(None, |min: Option<f64>, t| Some(min.map_or(t, |m| m.min(t))))?;
+
+        let p = o + d * t;
+        let k = p.dot(p) - r2 - m2;
+        let n = p * k + vec3(0.0, 0.0, 2.0 * r2 * p.z);
+        let normal = (u * n.x as f32 + v * n.y as f32 + w * n.z as f32).normalize();
+        Some(Hit { t: t as f32, normal: normal, color: self.color, material: None, front: true })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+}
+
+impl Bounded for Torus {
+    fn aabb(&self) -> Aabb {
+        // the tube's center circle, padded by the tube's radius
+        let ring = Aabb::around_disc(self.center, self.axis, self.major_radius);
+        let r = vec3(self.minor_radius, self.minor_radius, self.minor_radius);
+        Aabb::new(ring.min + -r, ring.max + r)
+    }
+}
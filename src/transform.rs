@@ -0,0 +1,129 @@
+use cgmath::*;
+
+use super::{Hit, Intersect, Ray};
+use super::aabb::Aabb;
+use super::csg::{Solid, Span};
+
+pub fn transform_point(m: &Matrix4<f32>, p: Point3<f32>) -> Point3<f32> {
+    Point3::from_homogeneous(*m * p.to_homogeneous())
+}
+
+pub fn transform_vector(m: &Matrix4<f32>, v: Vector3<f32>) -> Vector3<f32> {
+    (*m * v.extend(0.0)).truncate()
+}
+
+/// Places any object in the world with an object-to-world matrix, so a unit
+/// sphere can become a rotated ellipsoid without a dedicated primitive.
+/// Rays are intersected in object space and hits mapped back. An object
+/// can also move over the shutter interval, towards a second matrix.
+pub struct Transformed<T> {
+    object: T,
+    frame: Frame,
+    // object-to-world when the shutter closes, if it moves
+    motion: Option<Matrix4<f32>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    to_world: Matrix4<f32>,
+    to_object: Matrix4<f32>,
+    // normals transform by the inverse transpose
+    normal_matrix: Matrix4<f32>,
+}
+impl Frame {
+    fn new(to_world: Matrix4<f32>) -> Frame {
+        let to_object = to_world.invert().expect("object transform must be invertible");
+        Frame { to_world: to_world, to_object: to_object, normal_matrix: to_object.transpose() }
+    }
+}
+
+impl<T> Transformed<T> {
+    pub fn new(object: T, to_world: Matrix4<f32>) -> Transformed<T> {
+        Transformed {
+            object: object,
+            frame: Frame::new(to_world),
+            motion: None,
+        }
+    }
+
+    pub fn set_transform(&mut self, to_world: Matrix4<f32>) {
+        self.frame = Frame::new(to_world);
+    }
+
+    /// Moves the object from its transform when the shutter opens to
+    /// `to_world` when it closes, interpolating the matrices in between.
+    pub fn set_motion(&mut self, to_world: Matrix4<f32>) {
+        self.motion = Some(to_world);
+    }
+
+    fn frame_at(&self, time: f32) -> Frame {
+        match self.motion {
+            Some(end) if time > 0.0 => Frame::new(self.frame.to_world * (1.0 - time) + end * time),
+            _ => self.frame,
+        }
+    }
+
+    fn local_ray(&self, frame: &Frame, ray: &Ray) -> Ray {
+        Ray::new(transform_point(&frame.to_object, ray.point), transform_vector(&frame.to_object, ray.direction)).at_time(ray.time)
+    }
+
+    // Object space distances don't survive non-uniform scaling, so hits are
+    // re-measured along the world ray.
+    fn world_hit(&self, frame: &Frame, ray: &Ray, local: &Ray, hit: Hit) -> Hit {
+        let point = transform_point(&frame.to_world, local.point_at(hit.t));
+        Hit {
+            t: (point - ray.point).dot(ray.direction),
+            normal: transform_vector(&frame.normal_matrix, hit.normal).normalize(),
+            ..hit
+        }
+    }
+}
+
+impl<T: Intersect> Intersect for Transformed<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let frame = self.frame_at(ray.time);
+        let local = self.local_ray(&frame, ray);
+        self.object.intersect(&local).map(|hit| self.world_hit(&frame, ray, &local, hit))
+    }
+
+    // Corners move in straight lines as the matrices are interpolated, so
+    // the bounds at both ends of the shutter interval cover the whole move.
+    fn bounds(&self) -> Option<Aabb> {
+        self.object.bounds().and_then(|b| {
+            let mut corners = vec![];
+            for m in Some(self.frame.to_world).iter().chain(self.motion.iter()) {
+                corners.extend(b.corners().iter().map(|&p| transform_point(m, p)));
+            }
+            Aabb::from_points(corners)
+        })
+    }
+
+    fn emits(&self) -> bool {
+        self.object.emits()
+    }
+
+    // The matrix stretches the surface around the point by the length of
+    // the normal under the cofactor matrix, the determinant times the
+    // inverse transpose. That is exact for flat surfaces and uniform scales;
+    // elsewhere it is the area as stretched everywhere as it is there.
+    fn sample_surface(&self, u: f32, v: f32, time: f32) -> Option<(Point3<f32>, Vector3<f32>, f32)> {
+        let frame = self.frame_at(time);
+        let (point, normal, area) = self.object.sample_surface(u, v, time)?;
+        let normal = transform_vector(&frame.normal_matrix, normal);
+        let stretch = frame.to_world.determinant().abs() * normal.length();
+        Some((transform_point(&frame.to_world, point), normal.normalize(), area * stretch))
+    }
+}
+
+impl<T: Solid> Solid for Transformed<T> {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let frame = self.frame_at(ray.time);
+        let local = self.local_ray(&frame, ray);
+        self.object.spans(&local).into_iter()
+            .map(|span| Span {
+                enter: self.world_hit(&frame, ray, &local, span.enter),
+                exit: self.world_hit(&frame, ray, &local, span.exit),
+            })
+            .collect()
+    }
+}
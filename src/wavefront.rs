@@ -0,0 +1,136 @@
+use cgmath::*;
+
+use super::{Ray, Scene};
+use super::bidirectional;
+use super::integrator::{self, Integrator};
+
+/// Traces a batch of camera rays stage by stage: every primary ray is
+/// intersected, then every shadow ray, then every hit is shaded. Each stage
+/// walks its rays sorted by direction so neighbouring queries touch the same
+/// parts of the scene, and the stages only share plain arrays, so any of
+/// them could be moved elsewhere without touching the shading code. Rays
+/// scattered by materials are traced afterwards as a batch of their own,
+/// `depth` times over at most.
+pub fn trace(scene: &Scene, rays: &[Ray], depth: usize) -> Vec<Vector3<f32>> {
+    // bidirectional paths are traced one at a time
+    if scene.integrator == Integrator::Bidirectional {
+        return rays.iter().map(|ray| bidirectional::trace(scene, ray, depth)).collect();
+    }
+    let mut colors = vec![scene.ambient; rays.len()];
+
+    // rays inside media that scatter light wander through them first, to
+    // the surface they meet, with what is left of them
+    let mut rays = rays.to_vec();
+    let (mut hits, mut walked) = (vec![], vec![]);
+    for i in coherent_order(rays.iter().map(|ray| ray.direction)) {
+        let hit = if rays[i].media.scattering().is_some() {
+            match scene.walk(&rays[i]) {
+                Some((ray, hit, weight)) => {
+                    rays[i] = ray;
+                    walked.push((i, weight));
+                    hit
+                }
+                None => {
+                    colors[i] = Vector3::zero();
+                    continue;
+                }
+            }
+        } else {
+            scene.closest_hit(&rays[i])
+        };
+        if let Some(hit) = hit { hits.push((i, hit)); }
+    }
+    if let Integrator::AmbientOcclusion { distance, rays: count } = scene.integrator {
+        let mut colors = vec![vec3(1.0, 1.0, 1.0); rays.len()];
+        for (i, hit) in hits {
+            colors[i] = integrator::occlusion(scene, &rays[i], &hit, distance, count);
+        }
+        return colors;
+    }
+
+    // which hits bounce rays, the rays they bounce, and how much of each is
+    // seen; the light hits give off themselves, and what reaches them
+    // straight from glowing spheres
+    let (mut scattered, mut secondary, mut weights) = (vec![], vec![], vec![]);
+    let (mut emitted, mut lit) = (vec![], vec![]);
+    for &(i, ref hit) in &hits {
+        let mut rng = rays[i].rng();
+        if depth > 0 {
+            for (ray, weight) in scene.bounces(&rays[i], hit, &mut rng) {
+                scattered.push(i);
+                secondary.push(ray);
+                weights.push(weight);
+            }
+        }
+        let e = integrator::emission(scene, &rays[i], hit);
+        if e != Vector3::zero() { emitted.push((i, e)); }
+        let l = integrator::emitter_light(scene, &rays[i], hit, &mut rng);
+        if l != Vector3::zero() { lit.push((i, l)); }
+    }
+
+    // how far rays inside transparent media went through them
+    let absorbed: Vec<(usize, f32)> = hits.iter()
+        .filter(|&&(i, _)| !rays[i].media.is_empty())
+        .map(|&(i, ref hit)| (i, hit.t))
+        .collect();
+
+    // the rays from each hit towards the lights, shadow tested together
+    let mut shadow: Vec<(usize, Ray, f32, Vector3<f32>)> = vec![];
+    for (h, &(i, ref hit)) in hits.iter().enumerate() {
+        for (ray, distance, radiance) in scene.light_rays(&rays[i], hit) {
+            shadow.push((h, ray, distance, radiance));
+        }
+    }
+    let order = coherent_order(shadow.iter().map(|s| s.1.direction));
+    let mut shadowed = vec![false; shadow.len()];
+    for &j in &order {
+        shadowed[j] = scene.in_shadow(&shadow[j].1, shadow[j].2);
+    }
+
+    let mut lights = vec![vec![]; hits.len()];
+    for (&(h, ref light_ray, _, radiance), &blocked) in shadow.iter().zip(&shadowed) {
+        if !blocked { lights[h].push((light_ray.direction, radiance)); }
+    }
+    for ((i, hit), lights) in hits.into_iter().zip(lights) {
+        colors[i] = scene.shade(&rays[i], &hit, &lights);
+    }
+
+    for (i, e) in emitted {
+        colors[i] = colors[i] + e;
+    }
+    for (i, l) in lit {
+        colors[i] = colors[i] + l;
+    }
+    if !secondary.is_empty() {
+        let bounced = trace(scene, &secondary, depth - 1);
+        for (((&i, &weight), ray), color) in scattered.iter().zip(&weights).zip(&secondary).zip(bounced) {
+            if let Some(ref guide) = scene.guide { guide.learn(ray, color); }
+            colors[i] = colors[i] + scene.clamped(weight * color);
+        }
+    }
+    for (i, distance) in absorbed {
+        colors[i] = colors[i] * rays[i].media.transmittance(distance);
+    }
+    for (i, weight) in walked {
+        colors[i] = weight * colors[i];
+    }
+    colors
+}
+
+// Indices of the directions ordered by octant, then roughly by direction
+// within it.
+fn coherent_order<I: Iterator<Item = Vector3<f32>>>(directions: I) -> Vec<usize> {
+    let mut keyed: Vec<(u32, usize)> = directions.enumerate().map(|(i, d)| (direction_key(d), i)).collect();
+    keyed.sort_unstable();
+    keyed.into_iter().map(|(_, i)| i).collect()
+}
+
+fn direction_key(d: Vector3<f32>) -> u32 {
+    let mut key = 0;
+    for axis in 0..3 {
+        let octant = if d[axis] < 0.0 { 1 } else { 0 };
+        let q = (d[axis].abs().min(1.0) * 511.0) as u32;
+        key |= octant << (29 + axis) | q << (9 * (2 - axis));
+    }
+    key
+}